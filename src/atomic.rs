@@ -0,0 +1,182 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use tempfile::NamedTempFile;
+
+/// A file that is written to a temporary file in the same directory and
+/// renamed to the destination on [`AtomicFile::commit`].
+///
+/// The destination is never left half-written: if the process is
+/// interrupted or the file is dropped without committing, the temporary
+/// file is removed and the destination is untouched.
+pub struct AtomicFile {
+    temp: NamedTempFile,
+    path: PathBuf,
+}
+
+impl AtomicFile {
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let mut builder = tempfile::Builder::new();
+        builder.prefix(".safemetadata-").suffix(".tmp");
+        // temporary files are private by default, but the result should be
+        // created like any other file (subject to the umask)
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            builder.permissions(fs::Permissions::from_mode(0o666));
+        }
+        let temp = builder.tempfile_in(dir)?;
+
+        // keep the permissions of the file that is replaced
+        if let Ok(metadata) = fs::metadata(&path) {
+            temp.as_file().set_permissions(metadata.permissions())?;
+        }
+
+        Ok(Self { temp, path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn file(&mut self) -> &mut File {
+        self.temp.as_file_mut()
+    }
+
+    /// Flush the data to disk and replace the destination with the file.
+    pub fn commit(mut self) -> Result<()> {
+        self.temp.flush()?;
+        self.temp.as_file().sync_all()?;
+        self.temp.persist(&self.path).map_err(|err| err.error)?;
+        sync_parent_dir(&self.path)?;
+
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.temp.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.temp.flush()
+    }
+}
+
+/// Make the rename durable by syncing the directory entry.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> Result<()> {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()?;
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Whether both paths refer to the same file, including hard links.
+/// Returns `false` if either file does not exist.
+pub fn is_same_file<P: AsRef<Path>, Q: AsRef<Path>>(first: P, second: Q) -> Result<bool> {
+    let (a, b) = match (fs::metadata(first.as_ref()), fs::metadata(second.as_ref())) {
+        (Ok(a), Ok(b)) => (a, b),
+        _ => return Ok(false),
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Ok(a.dev() == b.dev() && a.ino() == b.ino())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (a, b);
+        Ok(fs::canonicalize(first)? == fs::canonicalize(second)?)
+    }
+}
+
+/// The backup path of the file, e.g. `model.safetensors.bak`.
+pub fn backup_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut backup = path.as_ref().as_os_str().to_owned();
+    backup.push(".bak");
+    PathBuf::from(backup)
+}
+
+/// The backup path of the header bytes of the file, e.g. `model.safetensors.header.bak`.
+pub fn header_backup_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut backup = path.as_ref().as_os_str().to_owned();
+    backup.push(".header.bak");
+    PathBuf::from(backup)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_atomic_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.safetensors");
+        fs::write(&path, b"original").unwrap();
+
+        // dropped without committing
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"partial").unwrap();
+        drop(file);
+        assert_eq!(fs::read(&path).unwrap(), b"original");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"updated").unwrap();
+        file.commit().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"updated");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_atomic_file_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.safetensors");
+        fs::write(&path, b"original").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+
+        AtomicFile::create(&path).unwrap().commit().unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+    }
+
+    #[test]
+    fn test_is_same_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.safetensors");
+        let link = dir.path().join("link.safetensors");
+        fs::write(&path, b"data").unwrap();
+        fs::hard_link(&path, &link).unwrap();
+
+        assert!(is_same_file(&path, &link).unwrap());
+        assert!(is_same_file(&path, dir.path().join(".").join("model.safetensors")).unwrap());
+        assert!(!is_same_file(&path, dir.path().join("missing.safetensors")).unwrap());
+
+        assert_eq!(backup_path(&path), dir.path().join("model.safetensors.bak"));
+        assert_eq!(
+            header_backup_path(&path),
+            dir.path().join("model.safetensors.header.bak")
+        );
+    }
+}
@@ -0,0 +1,726 @@
+use anyhow::Result;
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind},
+    layout::{Constraint, Layout, Rect},
+    style::{Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, List, ListItem, ListState, Paragraph, Wrap},
+    DefaultTerminal, Frame,
+};
+use safemetadata::metadata::Metadata;
+use safemetadata::parser::HeaderInfo;
+use serde_json::Value;
+
+use crate::utils::{format_with_separators, natural_cmp, pretty_byte_size, pretty_param_size};
+
+/// A module or a tensor in the tree of tensor names.
+#[derive(Debug, Default)]
+struct Node {
+    /// The last component of the name
+    label: String,
+    /// The full dotted name
+    path: String,
+    /// Set if a tensor has exactly this name
+    tensor: Option<String>,
+    children: Vec<Node>,
+    expanded: bool,
+    tensors: usize,
+    params: i64,
+    bytes: i64,
+}
+
+impl Node {
+    fn build(info: &HeaderInfo) -> Self {
+        let mut root = Node {
+            expanded: true,
+            ..Default::default()
+        };
+
+        for name in info.header.weights.keys() {
+            let mut node = &mut root;
+            for component in name.split('.') {
+                let index = match node.children.iter().position(|c| c.label == component) {
+                    Some(index) => index,
+                    None => {
+                        let path = match node.path.as_str() {
+                            "" => component.to_string(),
+                            parent => format!("{}.{}", parent, component),
+                        };
+                        node.children.push(Node {
+                            label: component.to_string(),
+                            path,
+                            ..Default::default()
+                        });
+                        node.children.len() - 1
+                    }
+                };
+                node = &mut node.children[index];
+            }
+            node.tensor = Some(name.clone());
+        }
+
+        root.finalize(info);
+        root
+    }
+
+    /// Sort the children and aggregate the sizes.
+    fn finalize(&mut self, info: &HeaderInfo) {
+        self.children
+            .sort_by(|a, b| natural_cmp(&a.label, &b.label));
+
+        if let Some(weight) = self
+            .tensor
+            .as_ref()
+            .and_then(|name| info.header.weights.get(name))
+        {
+            self.tensors += 1;
+            self.params += weight.num_elements();
+            self.bytes += weight.byte_size();
+        }
+        for child in self.children.iter_mut() {
+            child.finalize(info);
+            self.tensors += child.tensors;
+            self.params += child.params;
+            self.bytes += child.bytes;
+        }
+    }
+
+    fn get(&self, index_path: &[usize]) -> &Node {
+        index_path
+            .iter()
+            .fold(self, |node, index| &node.children[*index])
+    }
+
+    fn get_mut(&mut self, index_path: &[usize]) -> &mut Node {
+        index_path
+            .iter()
+            .fold(self, |node, index| &mut node.children[*index])
+    }
+
+    fn set_expanded_all(&mut self, expanded: bool) {
+        self.expanded = expanded;
+        for child in self.children.iter_mut() {
+            child.set_expanded_all(expanded);
+        }
+    }
+
+    /// Expand the ancestors of the tensor and return its index path.
+    fn reveal(&mut self, name: &str) -> Option<Vec<usize>> {
+        let mut index_path = Vec::new();
+        let mut node = self;
+        for component in name.split('.') {
+            node.expanded = true;
+            let index = node.children.iter().position(|c| c.label == component)?;
+            index_path.push(index);
+            node = &mut node.children[index];
+        }
+
+        Some(index_path)
+    }
+
+    /// The visible nodes as (depth, index path), depth-first.
+    fn visible_rows(&self, depth: usize, index_path: &mut Vec<usize>, rows: &mut Vec<Row>) {
+        if !self.expanded {
+            return;
+        }
+        for (index, child) in self.children.iter().enumerate() {
+            index_path.push(index);
+            rows.push(Row {
+                depth,
+                index_path: index_path.clone(),
+            });
+            child.visible_rows(depth + 1, index_path, rows);
+            index_path.pop();
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Row {
+    depth: usize,
+    index_path: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Focus {
+    Tree,
+    Metadata,
+}
+
+struct App<'a> {
+    name: String,
+    info: &'a HeaderInfo,
+    root: Node,
+    rows: Vec<Row>,
+    tree_state: ListState,
+    focus: Focus,
+    metadata_lines: Vec<(String, String)>,
+    metadata_scroll: u16,
+    metadata_max_scroll: u16,
+    /// The search query while typing it
+    searching: Option<String>,
+    query: String,
+    matches: Vec<String>,
+    match_index: usize,
+    quit: bool,
+}
+
+/// The metadata as key-value pairs, as shown by the `metadata` command.
+fn metadata_lines(metadata: &Option<Metadata>) -> Vec<(String, String)> {
+    let Some(metadata) = metadata else {
+        return vec![];
+    };
+
+    match serde_json::to_value(metadata) {
+        Ok(Value::Object(map)) => {
+            let mut lines = map
+                .into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| match value {
+                    Value::String(value) => (key, value),
+                    value => (key, value.to_string()),
+                })
+                .collect::<Vec<_>>();
+            lines.sort_by(|(a, _), (b, _)| a.cmp(b));
+            lines
+        }
+        _ => vec![],
+    }
+}
+
+impl<'a> App<'a> {
+    fn new(name: String, info: &'a HeaderInfo) -> Self {
+        let root = Node::build(info);
+        let mut app = Self {
+            name,
+            info,
+            root,
+            rows: vec![],
+            tree_state: ListState::default(),
+            focus: Focus::Tree,
+            metadata_lines: metadata_lines(&info.header.metadata),
+            metadata_scroll: 0,
+            metadata_max_scroll: 0,
+            searching: None,
+            query: String::new(),
+            matches: vec![],
+            match_index: 0,
+            quit: false,
+        };
+        app.refresh_rows();
+        app.tree_state.select_first();
+
+        app
+    }
+
+    fn refresh_rows(&mut self) {
+        let mut rows = Vec::new();
+        self.root.visible_rows(0, &mut vec![], &mut rows);
+        self.rows = rows;
+    }
+
+    fn selected_path(&self) -> Option<Vec<usize>> {
+        self.tree_state
+            .selected()
+            .and_then(|index| self.rows.get(index))
+            .map(|row| row.index_path.clone())
+    }
+
+    fn select_path(&mut self, index_path: &[usize]) {
+        if let Some(index) = self
+            .rows
+            .iter()
+            .position(|row| row.index_path == index_path)
+        {
+            self.tree_state.select(Some(index));
+        }
+    }
+
+    fn move_selection(&mut self, delta: i32) {
+        let last = self.rows.len().saturating_sub(1) as i32;
+        let index = self.tree_state.selected().unwrap_or(0) as i32 + delta;
+        self.tree_state.select(Some(index.clamp(0, last) as usize));
+    }
+
+    fn toggle(&mut self, expanded: Option<bool>) {
+        let Some(index_path) = self.selected_path() else {
+            return;
+        };
+        let node = self.root.get_mut(&index_path);
+        if node.children.is_empty() {
+            // collapsing a tensor moves to its module
+            if expanded == Some(false) && index_path.len() > 1 {
+                self.select_path(&index_path[..index_path.len() - 1]);
+            }
+            return;
+        }
+
+        let expanded = expanded.unwrap_or(!node.expanded);
+        if !expanded && !node.expanded && index_path.len() > 1 {
+            self.select_path(&index_path[..index_path.len() - 1]);
+            return;
+        }
+        node.expanded = expanded;
+        self.refresh_rows();
+        self.select_path(&index_path);
+    }
+
+    fn set_expanded_all(&mut self, expanded: bool) {
+        let selected = self.selected_path();
+        self.root.set_expanded_all(expanded);
+        self.root.expanded = true;
+        self.refresh_rows();
+        match selected {
+            Some(index_path) if expanded => self.select_path(&index_path),
+            Some(index_path) => self.select_path(&index_path[..1]),
+            None => self.tree_state.select_first(),
+        }
+    }
+
+    fn update_matches(&mut self) {
+        let query = self.query.to_lowercase();
+        self.matches = match query.is_empty() {
+            true => vec![],
+            false => {
+                let mut matches = self
+                    .info
+                    .header
+                    .weights
+                    .keys()
+                    .filter(|name| name.to_lowercase().contains(&query))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                matches.sort_by(|a, b| natural_cmp(a, b));
+                matches
+            }
+        };
+        self.match_index = 0;
+        self.jump_to_match();
+    }
+
+    fn jump_to_match(&mut self) {
+        let Some(name) = self.matches.get(self.match_index).cloned() else {
+            return;
+        };
+        if let Some(index_path) = self.root.reveal(&name) {
+            self.refresh_rows();
+            self.select_path(&index_path);
+        }
+    }
+
+    fn next_match(&mut self, forward: bool) {
+        if self.matches.is_empty() {
+            return;
+        }
+        let len = self.matches.len();
+        self.match_index = match forward {
+            true => (self.match_index + 1) % len,
+            false => (self.match_index + len - 1) % len,
+        };
+        self.jump_to_match();
+    }
+
+    fn scroll_metadata(&mut self, lines: i32) {
+        let scroll = (self.metadata_scroll as i32 + lines).max(0) as u16;
+        self.metadata_scroll = scroll.min(self.metadata_max_scroll);
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        if let Some(query) = self.searching.as_mut() {
+            match key.code {
+                KeyCode::Esc => {
+                    self.searching = None;
+                    self.query.clear();
+                    self.update_matches();
+                }
+                KeyCode::Enter => self.searching = None,
+                KeyCode::Backspace => {
+                    query.pop();
+                    self.query = query.clone();
+                    self.update_matches();
+                }
+                KeyCode::Char(c) => {
+                    query.push(c);
+                    self.query = query.clone();
+                    self.update_matches();
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+            KeyCode::Tab => {
+                self.focus = match self.focus {
+                    Focus::Tree => Focus::Metadata,
+                    Focus::Metadata => Focus::Tree,
+                }
+            }
+            KeyCode::Char('/') => {
+                self.searching = Some(String::new());
+                self.focus = Focus::Tree;
+            }
+            KeyCode::Char('n') => self.next_match(true),
+            KeyCode::Char('N') => self.next_match(false),
+            KeyCode::PageDown => self.scroll_metadata(10),
+            KeyCode::PageUp => self.scroll_metadata(-10),
+            _ => match self.focus {
+                Focus::Tree => self.handle_tree_key(key),
+                Focus::Metadata => self.handle_metadata_key(key),
+            },
+        }
+    }
+
+    fn handle_tree_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::Home | KeyCode::Char('g') => self.tree_state.select_first(),
+            KeyCode::End | KeyCode::Char('G') => self.move_selection(self.rows.len() as i32),
+            KeyCode::Right | KeyCode::Char('l') => self.toggle(Some(true)),
+            KeyCode::Left | KeyCode::Char('h') => self.toggle(Some(false)),
+            KeyCode::Enter | KeyCode::Char(' ') => self.toggle(None),
+            KeyCode::Char('e') => self.set_expanded_all(true),
+            KeyCode::Char('c') => self.set_expanded_all(false),
+            _ => {}
+        }
+    }
+
+    fn handle_metadata_key(&mut self, key: KeyEvent) {
+        match key.code {
+            KeyCode::Down | KeyCode::Char('j') => self.scroll_metadata(1),
+            KeyCode::Up | KeyCode::Char('k') => self.scroll_metadata(-1),
+            KeyCode::Home | KeyCode::Char('g') => self.metadata_scroll = 0,
+            KeyCode::End | KeyCode::Char('G') => self.metadata_scroll = self.metadata_max_scroll,
+            _ => {}
+        }
+    }
+
+    fn block(&self, title: &str, focus: Option<Focus>) -> Block<'static> {
+        let block = Block::bordered().title(title.to_string());
+        match focus {
+            Some(focus) if focus == self.focus => block.border_style(Style::new().cyan()),
+            _ => block,
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Fill(1), Constraint::Length(1)]).areas(frame.area());
+        let [tree, side] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(main);
+        let [detail, metadata] =
+            Layout::vertical([Constraint::Length(10), Constraint::Fill(1)]).areas(side);
+
+        self.draw_tree(frame, tree);
+        self.draw_detail(frame, detail);
+        self.draw_metadata(frame, metadata);
+        self.draw_status(frame, status);
+    }
+
+    fn draw_tree(&mut self, frame: &mut Frame, area: Rect) {
+        let items = self
+            .rows
+            .iter()
+            .map(|row| {
+                let node = self.root.get(&row.index_path);
+                let marker = match (node.children.is_empty(), node.expanded) {
+                    (true, _) => "  ",
+                    (false, true) => "▾ ",
+                    (false, false) => "▸ ",
+                };
+                let indent = "  ".repeat(row.depth);
+                let is_match = node
+                    .tensor
+                    .as_ref()
+                    .is_some_and(|name| self.matches.contains(name));
+                let label = match is_match {
+                    true => Span::from(node.label.clone()).yellow(),
+                    false => Span::from(node.label.clone()),
+                };
+
+                ListItem::new(Line::from(vec![Span::from(indent + marker), label]))
+            })
+            .collect::<Vec<_>>();
+
+        let title = format!(
+            " {} ({} tensors) ",
+            self.name,
+            self.info.header.weights.len()
+        );
+        let list = List::new(items)
+            .block(self.block(&title, Some(Focus::Tree)))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+
+        frame.render_stateful_widget(list, area, &mut self.tree_state);
+    }
+
+    fn draw_detail(&self, frame: &mut Frame, area: Rect) {
+        let field =
+            |key: &str, value: String| Line::from(vec![format!("{}: ", key).bold(), value.into()]);
+
+        let lines = match self.selected_path() {
+            Some(index_path) => {
+                let node = self.root.get(&index_path);
+                let weight = node
+                    .tensor
+                    .as_ref()
+                    .and_then(|name| self.info.header.weights.get(name));
+                match weight {
+                    Some(weight) if node.children.is_empty() => {
+                        let [begin, end] = weight.data_offsets;
+                        let start = self.info.data_start() as i64;
+                        vec![
+                            field("Name", node.path.clone()),
+                            field("DType", weight.dtype.to_string()),
+                            field("Shape", format!("{:?}", weight.shape)),
+                            field("Elements", format_with_separators(weight.num_elements())),
+                            field(
+                                "Bytes",
+                                format!(
+                                    "{} ({})",
+                                    pretty_byte_size(weight.byte_size() as u64),
+                                    format_with_separators(weight.byte_size())
+                                ),
+                            ),
+                            field("Data offsets", format!("{}..{}", begin, end)),
+                            field(
+                                "File offsets",
+                                format!("{}..{}", start + begin, start + end),
+                            ),
+                        ]
+                    }
+                    _ => {
+                        let (params, unit) = pretty_param_size(node.params, false);
+                        vec![
+                            field("Module", node.path.clone()),
+                            field("Tensors", node.tensors.to_string()),
+                            field(
+                                "Parameters",
+                                format!(
+                                    "{}{} ({})",
+                                    params,
+                                    unit,
+                                    format_with_separators(node.params)
+                                ),
+                            ),
+                            field("Bytes", pretty_byte_size(node.bytes as u64)),
+                        ]
+                    }
+                }
+            }
+            None => vec![],
+        };
+
+        let paragraph = Paragraph::new(lines)
+            .block(self.block(" Details ", None))
+            .wrap(Wrap { trim: false });
+        frame.render_widget(paragraph, area);
+    }
+
+    fn draw_metadata(&mut self, frame: &mut Frame, area: Rect) {
+        let lines = match self.metadata_lines.is_empty() {
+            true => vec![Line::from("No metadata found in the file.")],
+            false => self
+                .metadata_lines
+                .iter()
+                .map(|(key, value)| {
+                    Line::from(vec![format!("{}: ", key).bold(), value.clone().into()])
+                })
+                .collect(),
+        };
+
+        // estimate the number of wrapped lines to limit the scroll
+        let width = area.width.saturating_sub(2).max(1) as usize;
+        let height = area.height.saturating_sub(2) as usize;
+        let wrapped = lines
+            .iter()
+            .map(|line| line.width().div_ceil(width).max(1))
+            .sum::<usize>();
+        self.metadata_max_scroll = wrapped.saturating_sub(height) as u16;
+        self.metadata_scroll = self.metadata_scroll.min(self.metadata_max_scroll);
+
+        let paragraph = Paragraph::new(lines)
+            .block(self.block(" Metadata ", Some(Focus::Metadata)))
+            .wrap(Wrap { trim: false })
+            .scroll((self.metadata_scroll, 0));
+        frame.render_widget(paragraph, area);
+    }
+
+    fn draw_status(&self, frame: &mut Frame, area: Rect) {
+        let line = match &self.searching {
+            Some(query) => Line::from(vec![
+                "/".bold(),
+                query.clone().into(),
+                format!("  ({} matches)", self.matches.len()).dim(),
+            ]),
+            None if !self.query.is_empty() => Line::from(vec![
+                format!("\"{}\" ", self.query).yellow(),
+                format!(
+                    "{}/{} matches  n/N: next/previous  /: search  q: quit",
+                    (self.match_index + 1).min(self.matches.len()),
+                    self.matches.len()
+                )
+                .dim(),
+            ]),
+            None => Line::from(
+                "↑↓: move  ←→: collapse/expand  e/c: expand/collapse all  /: search  Tab: focus  PgUp/PgDn: scroll metadata  q: quit"
+                    .dim(),
+            ),
+        };
+
+        frame.render_widget(Paragraph::new(line), area);
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        while !self.quit {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press {
+                    self.handle_key(key);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Open the interactive browser for the header.
+pub fn browse(name: String, info: &HeaderInfo) -> Result<()> {
+    let mut app = App::new(name, info);
+
+    let mut terminal = ratatui::init();
+    let result = app.run(&mut terminal);
+    ratatui::restore();
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ratatui::{backend::TestBackend, crossterm::event::KeyModifiers, Terminal};
+    use safemetadata::metadata::{Dtype, Header, Weight, Weights};
+
+    fn header_info() -> HeaderInfo {
+        let names = [
+            "lm_head.weight",
+            "model.layers.10.mlp.weight",
+            "model.layers.2.mlp.weight",
+            "model.norm.weight",
+        ];
+        let weights = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let begin = i as i64 * 16;
+                (
+                    name.to_string(),
+                    Weight {
+                        dtype: Dtype::Fp32,
+                        shape: vec![2, 2],
+                        data_offsets: [begin, begin + 16],
+                    },
+                )
+            })
+            .collect::<Weights>();
+
+        HeaderInfo {
+            header: Header {
+                metadata: None,
+                weights,
+            },
+            header_size: 256,
+            file_size: 8 + 256 + 64,
+        }
+    }
+
+    fn press(app: &mut App, code: KeyCode) {
+        app.handle_key(KeyEvent::new(code, KeyModifiers::NONE));
+    }
+
+    fn selected_name(app: &App) -> String {
+        app.root.get(&app.selected_path().unwrap()).path.clone()
+    }
+
+    #[test]
+    fn test_tree() {
+        let info = header_info();
+        let root = Node::build(&info);
+
+        let labels = root
+            .children
+            .iter()
+            .map(|c| c.label.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(labels, vec!["lm_head", "model"]);
+
+        let model = &root.children[1];
+        assert_eq!(model.tensors, 3);
+        assert_eq!(model.bytes, 48);
+
+        // natural order
+        let layers = model.children[0]
+            .children
+            .iter()
+            .map(|c| c.label.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(layers, vec!["2", "10"]);
+    }
+
+    #[test]
+    fn test_navigation_and_search() {
+        let info = header_info();
+        let mut app = App::new("test".to_string(), &info);
+        assert_eq!(app.rows.len(), 2); // only the top-level modules
+
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Right);
+        assert_eq!(app.rows.len(), 4);
+        assert_eq!(selected_name(&app), "model");
+
+        press(&mut app, KeyCode::Char('/'));
+        for c in "mlp".chars() {
+            press(&mut app, KeyCode::Char(c));
+        }
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(app.matches.len(), 2);
+        assert_eq!(selected_name(&app), "model.layers.2.mlp.weight");
+
+        press(&mut app, KeyCode::Char('n'));
+        assert_eq!(selected_name(&app), "model.layers.10.mlp.weight");
+
+        press(&mut app, KeyCode::Left);
+        assert_eq!(selected_name(&app), "model.layers.10.mlp");
+
+        press(&mut app, KeyCode::Char('q'));
+        assert!(app.quit);
+    }
+
+    #[test]
+    fn test_draw() {
+        let info = header_info();
+        let mut app = App::new("test".to_string(), &info);
+        press(&mut app, KeyCode::Char('e'));
+        press(&mut app, KeyCode::End);
+
+        let mut terminal = Terminal::new(TestBackend::new(100, 20)).unwrap();
+        terminal.draw(|frame| app.draw(frame)).unwrap();
+
+        let buffer = terminal.backend().buffer();
+        let content = buffer
+            .content()
+            .chunks(buffer.area.width as usize)
+            .map(|line| line.iter().map(|cell| cell.symbol()).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        assert!(content.contains("Name: model.norm.weight"));
+        assert!(content.contains("File offsets: 312..328"));
+        assert!(content.contains("No metadata found in the file."));
+    }
+}
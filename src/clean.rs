@@ -0,0 +1,140 @@
+use anyhow::Result;
+
+use crate::named::named_enum;
+use crate::pattern::NamePattern;
+
+/// Named sets of metadata keys to keep and remove.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CleanPreset {
+    /// Remove every key, including `format`.
+    All,
+    /// Remove every key except `format`, which transformers needs to load the file.
+    KeepFormat,
+    /// Keep `format` and the modelspec, remove everything else.
+    KeepModelSpec,
+    /// Keep `format` and the modelspec, remove the kohya-ss training info (`ss_*`, `sshs_*`).
+    Training,
+}
+
+named_enum!(CleanPreset, "preset", {
+    All => "all",
+    KeepFormat => "keep-format",
+    KeepModelSpec => "keep-modelspec",
+    Training => "training",
+});
+
+impl CleanPreset {
+    /// The `(keep, remove)` patterns of the preset.
+    fn patterns(&self) -> (&'static [&'static str], &'static [&'static str]) {
+        match self {
+            CleanPreset::All => (&[], &[]),
+            CleanPreset::KeepFormat => (&["format"], &[]),
+            CleanPreset::KeepModelSpec => (&["format", "modelspec.*"], &[]),
+            CleanPreset::Training => (&["format", "modelspec.*"], &["ss_*", "sshs_*"]),
+        }
+    }
+}
+
+/// Decides which metadata keys are removed.
+///
+/// A key matching any `keep` pattern is always kept. Otherwise, it is removed
+/// if it matches any `remove` pattern, or if there are no `remove` patterns
+/// (so an empty filter removes everything).
+#[derive(Debug, Clone, Default)]
+pub struct KeyFilter {
+    pub keep: Vec<NamePattern>,
+    pub remove: Vec<NamePattern>,
+}
+
+impl KeyFilter {
+    pub fn new(keep: Vec<NamePattern>, remove: Vec<NamePattern>) -> Self {
+        Self { keep, remove }
+    }
+
+    pub fn from_preset(preset: CleanPreset) -> Self {
+        let (keep, remove) = preset.patterns();
+        let compile = |patterns: &[&str]| {
+            patterns
+                .iter()
+                .map(|pattern| NamePattern::new(pattern).expect("valid preset pattern"))
+                .collect()
+        };
+
+        Self::new(compile(keep), compile(remove))
+    }
+
+    /// Also keep the keys matching the patterns.
+    pub fn with_keep(mut self, keep: Vec<NamePattern>) -> Self {
+        self.keep.extend(keep);
+        self
+    }
+
+    pub fn is_removed(&self, key: &str) -> bool {
+        if self.keep.iter().any(|pattern| pattern.is_match(key)) {
+            return false;
+        }
+
+        self.remove.is_empty() || self.remove.iter().any(|pattern| pattern.is_match(key))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn patterns(patterns: &[&str]) -> Vec<NamePattern> {
+        patterns
+            .iter()
+            .map(|p| NamePattern::new(p).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_key_filter() {
+        let all = KeyFilter::default();
+        assert!(all.is_removed("format"));
+        assert!(all.is_removed("ss_network_dim"));
+
+        let keep = KeyFilter::new(patterns(&["format"]), vec![]);
+        assert!(!keep.is_removed("format"));
+        assert!(keep.is_removed("modelspec.title"));
+
+        let remove = KeyFilter::new(vec![], patterns(&["re:^ss_"]));
+        assert!(remove.is_removed("ss_network_dim"));
+        assert!(!remove.is_removed("sshs_model_hash"));
+        assert!(!remove.is_removed("format"));
+
+        let both = KeyFilter::new(patterns(&["ss_network_*"]), patterns(&["ss_*"]));
+        assert!(!both.is_removed("ss_network_dim"));
+        assert!(both.is_removed("ss_dataset_dirs"));
+    }
+
+    #[test]
+    fn test_presets() {
+        for preset in CleanPreset::ALL {
+            assert_eq!(preset.to_string().parse::<CleanPreset>().unwrap(), preset);
+        }
+        assert!("unknown".parse::<CleanPreset>().is_err());
+
+        let training = KeyFilter::from_preset(CleanPreset::Training);
+        assert!(!training.is_removed("format"));
+        assert!(!training.is_removed("modelspec.title"));
+        assert!(!training.is_removed("custom_key"));
+        assert!(training.is_removed("ss_network_dim"));
+        assert!(training.is_removed("sshs_model_hash"));
+
+        let modelspec = KeyFilter::from_preset(CleanPreset::KeepModelSpec);
+        assert!(!modelspec.is_removed("modelspec.title"));
+        assert!(modelspec.is_removed("custom_key"));
+
+        let training =
+            KeyFilter::from_preset(CleanPreset::Training).with_keep(patterns(&["ss_network_*"]));
+        assert!(!training.is_removed("ss_network_dim"));
+        assert!(training.is_removed("ss_dataset_dirs"));
+        assert!(!training.is_removed("custom_key"));
+
+        let all = KeyFilter::from_preset(CleanPreset::All).with_keep(patterns(&["format"]));
+        assert!(!all.is_removed("format"));
+        assert!(all.is_removed("modelspec.title"));
+    }
+}
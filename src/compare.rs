@@ -0,0 +1,422 @@
+use std::io::Read;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::Result;
+use rayon::prelude::*;
+
+use crate::convert::{decode, is_float};
+use crate::metadata::Dtype;
+use crate::parser::{HeaderInfo, MetadataParser};
+use crate::progress::Progress;
+
+/// The number of elements compared at a time.
+const CHUNK_ELEMENTS: usize = 1 << 20;
+
+/// Whether the values of the dtype can be decoded for comparison.
+/// The 4-bit integer types cannot.
+fn is_numeric(dtype: Dtype) -> bool {
+    is_float(dtype)
+        || matches!(
+            dtype,
+            Dtype::Int64 | Dtype::Int32 | Dtype::Int16 | Dtype::Int8 | Dtype::Uint8 | Dtype::Bool
+        )
+}
+
+/// Decode one little-endian element as `f64`.
+fn decode_value(dtype: Dtype, bytes: &[u8]) -> f64 {
+    match dtype {
+        Dtype::Int64 => i64::from_le_bytes(bytes.try_into().expect("8 bytes")) as f64,
+        Dtype::Int32 => i32::from_le_bytes(bytes.try_into().expect("4 bytes")) as f64,
+        Dtype::Int16 => i16::from_le_bytes(bytes.try_into().expect("2 bytes")) as f64,
+        Dtype::Int8 => bytes[0] as i8 as f64,
+        Dtype::Uint8 | Dtype::Bool => bytes[0] as f64,
+        _ => decode(dtype, bytes),
+    }
+}
+
+/// The limits a tensor must stay within to pass. Unset limits are not checked.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Thresholds {
+    pub max_abs_diff: Option<f64>,
+    pub max_relative_error: Option<f64>,
+    pub min_cosine_similarity: Option<f64>,
+}
+
+/// How much the values of a tensor differ between the two files.
+///
+/// Values that are NaN in both files are equal, and so are infinities of the
+/// same sign. Only finite values count towards the cosine similarity and the
+/// relative error, so NaN and infinity mismatches are counted separately.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TensorComparison {
+    pub name: String,
+    pub first_dtype: Dtype,
+    pub second_dtype: Dtype,
+    pub elements: u64,
+    pub max_abs_diff: f64,
+    pub mean_abs_diff: f64,
+    pub cosine_similarity: f64,
+    /// `‖second - first‖ / ‖first‖`, the L2 norm of the difference relative
+    /// to the first tensor.
+    pub relative_error: f64,
+    /// Values that are NaN in only one of the files.
+    pub nan_mismatches: u64,
+    /// Values that are infinite in only one of the files, e.g. after an
+    /// overflow in a narrowing cast, or infinities of opposite signs.
+    pub inf_mismatches: u64,
+}
+
+impl TensorComparison {
+    /// Whether the tensor is within the thresholds. A NaN or infinity
+    /// mismatch always fails.
+    pub fn passes(&self, thresholds: &Thresholds) -> bool {
+        self.nan_mismatches == 0
+            && self.inf_mismatches == 0
+            && thresholds
+                .max_abs_diff
+                .is_none_or(|max| self.max_abs_diff <= max)
+            && thresholds
+                .max_relative_error
+                .is_none_or(|max| self.relative_error <= max)
+            && thresholds
+                .min_cosine_similarity
+                .is_none_or(|min| self.cosine_similarity >= min)
+    }
+}
+
+/// Why a tensor of both files was not compared.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkipReason {
+    /// The shapes differ.
+    Shape { first: Vec<i64>, second: Vec<i64> },
+    /// The values of the dtype cannot be decoded.
+    Dtype(Dtype),
+}
+
+/// The result of [`compare`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    /// The tensors of both files, by name.
+    pub tensors: Vec<TensorComparison>,
+    pub skipped: Vec<(String, SkipReason)>,
+    pub only_in_first: Vec<String>,
+    pub only_in_second: Vec<String>,
+}
+
+/// The running sums of the differences of a tensor.
+#[derive(Debug, Default)]
+struct Accumulator {
+    elements: u64,
+    sum_abs_diff: f64,
+    max_abs_diff: f64,
+    dot: f64,
+    first_norm: f64,
+    second_norm: f64,
+    diff_norm: f64,
+    nan_mismatches: u64,
+    inf_mismatches: u64,
+}
+
+impl Accumulator {
+    fn add(&mut self, first: f64, second: f64) {
+        self.elements += 1;
+        if first.is_nan() || second.is_nan() {
+            if first.is_nan() != second.is_nan() {
+                self.nan_mismatches += 1;
+            }
+            return;
+        }
+
+        // equal infinities do not differ
+        let diff = match first == second {
+            true => 0.0,
+            false => (second - first).abs(),
+        };
+        self.sum_abs_diff += diff;
+        self.max_abs_diff = self.max_abs_diff.max(diff);
+        if first.is_finite() && second.is_finite() {
+            self.dot += first * second;
+            self.first_norm += first * first;
+            self.second_norm += second * second;
+            self.diff_norm += diff * diff;
+        } else if diff != 0.0 {
+            self.inf_mismatches += 1;
+        }
+    }
+
+    fn finish(self, name: &str, first_dtype: Dtype, second_dtype: Dtype) -> TensorComparison {
+        let cosine_similarity = match (self.first_norm == 0.0, self.second_norm == 0.0) {
+            (true, true) => 1.0,
+            (true, false) | (false, true) => 0.0,
+            (false, false) => {
+                (self.dot / (self.first_norm * self.second_norm).sqrt()).clamp(-1.0, 1.0)
+            }
+        };
+        let relative_error = match (self.first_norm == 0.0, self.diff_norm == 0.0) {
+            (_, true) => 0.0,
+            (true, false) => f64::INFINITY,
+            (false, false) => self.diff_norm.sqrt() / self.first_norm.sqrt(),
+        };
+        let mean_abs_diff = match self.elements {
+            0 => 0.0,
+            elements => self.sum_abs_diff / elements as f64,
+        };
+
+        TensorComparison {
+            name: name.to_string(),
+            first_dtype,
+            second_dtype,
+            elements: self.elements,
+            max_abs_diff: self.max_abs_diff,
+            mean_abs_diff,
+            cosine_similarity,
+            relative_error,
+            nan_mismatches: self.nan_mismatches,
+            inf_mismatches: self.inf_mismatches,
+        }
+    }
+}
+
+/// Read the tensor from both files chunk by chunk and compare the values.
+fn compare_tensor(
+    name: &str,
+    (first, first_info): (&(dyn MetadataParser + Sync), &HeaderInfo),
+    (second, second_info): (&(dyn MetadataParser + Sync), &HeaderInfo),
+    done: &dyn Fn(u64),
+) -> Result<TensorComparison> {
+    let first_dtype = first_info.header.weights[name].dtype;
+    let second_dtype = second_info.header.weights[name].dtype;
+    let elements = first_info.header.weights[name].num_elements() as u64;
+    let (first_size, second_size) = (first_dtype.bits() / 8, second_dtype.bits() / 8);
+
+    let mut first_reader = first.open_tensor(first_info, name)?;
+    let mut second_reader = second.open_tensor(second_info, name)?;
+    let chunk_elements = CHUNK_ELEMENTS.min(elements as usize);
+    let mut first_buffer = vec![0; chunk_elements * first_size];
+    let mut second_buffer = vec![0; chunk_elements * second_size];
+
+    let mut accumulator = Accumulator::default();
+    let mut remaining = elements;
+    while remaining > 0 {
+        let count = CHUNK_ELEMENTS.min(remaining as usize);
+        let first_chunk = &mut first_buffer[..count * first_size];
+        let second_chunk = &mut second_buffer[..count * second_size];
+        first_reader.read_exact(first_chunk)?;
+        second_reader.read_exact(second_chunk)?;
+
+        for (first_bytes, second_bytes) in first_chunk
+            .chunks_exact(first_size)
+            .zip(second_chunk.chunks_exact(second_size))
+        {
+            accumulator.add(
+                decode_value(first_dtype, first_bytes),
+                decode_value(second_dtype, second_bytes),
+            );
+        }
+        remaining -= count as u64;
+        done((first_chunk.len() + second_chunk.len()) as u64);
+    }
+
+    Ok(accumulator.finish(name, first_dtype, second_dtype))
+}
+
+/// Decode the tensors that both files have and compare their values.
+///
+/// The dtypes may differ, e.g. to check a conversion from fp32 to bf16, but
+/// the shapes must match. Tensors are compared in parallel, reading the data
+/// of local files or of remote files with range requests.
+pub fn compare(
+    first: &(dyn MetadataParser + Sync),
+    second: &(dyn MetadataParser + Sync),
+    progress: Option<&Progress>,
+) -> Result<Comparison> {
+    let first_info = first.parse_header_info()?;
+    let second_info = second.parse_header_info()?;
+    let first_weights = &first_info.header.weights;
+    let second_weights = &second_info.header.weights;
+
+    let mut names = Vec::new();
+    let mut skipped = Vec::new();
+    for (name, weight) in first_weights {
+        let Some(other) = second_weights.get(name) else {
+            continue;
+        };
+        if weight.shape != other.shape {
+            let reason = SkipReason::Shape {
+                first: weight.shape.clone(),
+                second: other.shape.clone(),
+            };
+            skipped.push((name.clone(), reason));
+        } else if let Some(dtype) = [weight.dtype, other.dtype]
+            .into_iter()
+            .find(|dtype| !is_numeric(*dtype))
+        {
+            skipped.push((name.clone(), SkipReason::Dtype(dtype)));
+        } else {
+            names.push(name);
+        }
+    }
+    let only_in_first = first_weights
+        .keys()
+        .filter(|name| !second_weights.contains_key(*name))
+        .cloned()
+        .collect();
+    let only_in_second = second_weights
+        .keys()
+        .filter(|name| !first_weights.contains_key(*name))
+        .cloned()
+        .collect();
+
+    let total = names
+        .iter()
+        .map(|name| (first_weights[*name].byte_size() + second_weights[*name].byte_size()) as u64)
+        .sum();
+    let done = AtomicU64::new(0);
+    if let Some(progress) = progress {
+        progress.update(0, total);
+    }
+    let report = |length: u64| {
+        let done = done.fetch_add(length, Ordering::Relaxed) + length;
+        if let Some(progress) = progress {
+            progress.update(done, total);
+        }
+    };
+
+    let tensors = names
+        .into_par_iter()
+        .map(|name| {
+            compare_tensor(name, (first, &first_info), (second, &second_info), &report)
+                .map_err(|err| anyhow::anyhow!("{}: {}", name, err))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Comparison {
+        tensors,
+        skipped,
+        only_in_first,
+        only_in_second,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::LocalParser;
+    use crate::writer::fixtures::{f32_bytes, write_model};
+
+    #[test]
+    fn test_compare() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.safetensors");
+        let second = dir.path().join("second.safetensors");
+        write_model(
+            &first,
+            &[
+                ("same", Dtype::Fp32, f32_bytes(&[1.0, -2.0, f32::NAN])),
+                ("changed", Dtype::Fp32, f32_bytes(&[3.0, 4.0])),
+                ("opposite", Dtype::Fp32, f32_bytes(&[1.0, 1.0])),
+                ("reshaped", Dtype::Fp32, f32_bytes(&[1.0])),
+                ("first", Dtype::Uint8, vec![1]),
+            ],
+        );
+        write_model(
+            &second,
+            &[
+                (
+                    "same",
+                    Dtype::Fp16,
+                    vec![0x00, 0x3c, 0x00, 0xc0, 0x00, 0x7e],
+                ),
+                ("changed", Dtype::Fp32, f32_bytes(&[3.0, 4.5])),
+                ("opposite", Dtype::Fp32, f32_bytes(&[-1.0, -1.0])),
+                ("reshaped", Dtype::Fp32, f32_bytes(&[1.0, 2.0])),
+                ("second", Dtype::Uint8, vec![1]),
+            ],
+        );
+
+        let comparison =
+            compare(&LocalParser::new(&first), &LocalParser::new(&second), None).unwrap();
+        let tensor = |name| comparison.tensors.iter().find(|t| t.name == name).unwrap();
+
+        let same = tensor("same");
+        assert_eq!(same.second_dtype, Dtype::Fp16);
+        assert_eq!(same.max_abs_diff, 0.0);
+        assert_eq!(same.cosine_similarity, 1.0);
+        assert_eq!(same.nan_mismatches, 0);
+
+        let changed = tensor("changed");
+        assert_eq!(changed.max_abs_diff, 0.5);
+        assert_eq!(changed.mean_abs_diff, 0.25);
+        assert_eq!(changed.relative_error, 0.1);
+        assert!(changed.cosine_similarity > 0.99 && changed.cosine_similarity < 1.0);
+        assert_eq!(tensor("opposite").cosine_similarity, -1.0);
+
+        let thresholds = Thresholds {
+            max_abs_diff: Some(0.1),
+            ..Default::default()
+        };
+        assert!(same.passes(&thresholds));
+        assert!(!changed.passes(&thresholds));
+        assert!(changed.passes(&Thresholds {
+            max_relative_error: Some(0.1),
+            ..Default::default()
+        }));
+
+        assert_eq!(
+            comparison.skipped,
+            vec![(
+                "reshaped".to_string(),
+                SkipReason::Shape {
+                    first: vec![1],
+                    second: vec![2]
+                }
+            )]
+        );
+        assert_eq!(comparison.only_in_first, vec!["first"]);
+        assert_eq!(comparison.only_in_second, vec!["second"]);
+    }
+
+    #[test]
+    fn test_compare_overflow() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.safetensors");
+        let second = dir.path().join("second.safetensors");
+        let values = (1..=1000).map(|i| i as f32).collect::<Vec<_>>();
+        let mut large = values.clone();
+        large[0] = 1e5;
+        write_model(
+            &first,
+            &[
+                ("large", Dtype::Fp32, f32_bytes(&large)),
+                ("inf", Dtype::Fp32, f32_bytes(&[f32::INFINITY, 1.0])),
+            ],
+        );
+        // 1e5 overflows to infinity in fp16, like a narrowing cast does
+        let mut fp16 = values
+            .iter()
+            .flat_map(|value| half::f16::from_f32(*value).to_le_bytes())
+            .collect::<Vec<_>>();
+        fp16[..2].copy_from_slice(&half::f16::INFINITY.to_le_bytes());
+        write_model(
+            &second,
+            &[
+                ("large", Dtype::Fp16, fp16),
+                ("inf", Dtype::Fp32, f32_bytes(&[f32::INFINITY, 1.0])),
+            ],
+        );
+
+        let comparison =
+            compare(&LocalParser::new(&first), &LocalParser::new(&second), None).unwrap();
+        let tensor = |name| comparison.tensors.iter().find(|t| t.name == name).unwrap();
+
+        // the other values are exact, so only the overflow tells them apart
+        let large = tensor("large");
+        assert_eq!(large.inf_mismatches, 1);
+        assert!(large.cosine_similarity > 0.999);
+        assert!(!large.passes(&Thresholds::default()));
+
+        let inf = tensor("inf");
+        assert_eq!(inf.inf_mismatches, 0);
+        assert!(inf.passes(&Thresholds::default()));
+    }
+}
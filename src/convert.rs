@@ -0,0 +1,455 @@
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use half::{bf16, f16};
+
+use crate::metadata::Dtype;
+use crate::pattern::NamePattern;
+
+/// The number of elements converted at a time.
+const CHUNK_ELEMENTS: usize = 1 << 20;
+
+/// Whether the dtype is a floating-point type that can be converted.
+pub fn is_float(dtype: Dtype) -> bool {
+    matches!(
+        dtype,
+        Dtype::Fp64 | Dtype::Fp32 | Dtype::Fp16 | Dtype::Bf16 | Dtype::Fp8E4M3 | Dtype::Fp8E5M2
+    )
+}
+
+/// The bit layout of an 8-bit float.
+#[derive(Debug, Clone, Copy)]
+struct Fp8Format {
+    exponent_bits: i32,
+    mantissa_bits: i32,
+    bias: i32,
+    max: f64,
+    /// Whether the largest exponent is reserved for infinities and NaNs, like
+    /// IEEE 754. Otherwise there are no infinities and only the all-ones
+    /// pattern is NaN, e.g. `float8_e4m3fn`.
+    ieee: bool,
+}
+
+const FP8_E4M3: Fp8Format = Fp8Format {
+    exponent_bits: 4,
+    mantissa_bits: 3,
+    bias: 7,
+    max: 448.0,
+    ieee: false,
+};
+
+const FP8_E5M2: Fp8Format = Fp8Format {
+    exponent_bits: 5,
+    mantissa_bits: 2,
+    bias: 15,
+    max: 57344.0,
+    ieee: true,
+};
+
+impl Fp8Format {
+    fn exponent_mask(&self) -> u8 {
+        ((1 << self.exponent_bits) - 1) as u8
+    }
+
+    fn mantissa_mask(&self) -> u8 {
+        ((1 << self.mantissa_bits) - 1) as u8
+    }
+
+    fn infinity(&self) -> u8 {
+        self.exponent_mask() << self.mantissa_bits
+    }
+
+    fn decode(&self, bits: u8) -> f64 {
+        let sign = if bits & 0x80 != 0 { -1.0 } else { 1.0 };
+        let exponent = ((bits >> self.mantissa_bits) & self.exponent_mask()) as i32;
+        let mantissa = (bits & self.mantissa_mask()) as f64;
+
+        if self.ieee && exponent == self.exponent_mask() as i32 {
+            return match mantissa == 0.0 {
+                true => sign * f64::INFINITY,
+                false => f64::NAN,
+            };
+        }
+        if !self.ieee && bits & 0x7f == 0x7f {
+            return f64::NAN;
+        }
+
+        let scale = (1 << self.mantissa_bits) as f64;
+        match exponent {
+            0 => sign * mantissa / scale * 2f64.powi(1 - self.bias),
+            _ => sign * (1.0 + mantissa / scale) * 2f64.powi(exponent - self.bias),
+        }
+    }
+
+    /// Round to the nearest representable value, ties to even. Values that
+    /// are too large become infinity, or the largest finite value if there
+    /// are no infinities, which infinities saturate to as well. Returns the
+    /// bits and whether the value saturated.
+    fn encode(&self, value: f64) -> (u8, bool) {
+        let sign = if value.is_sign_negative() { 0x80 } else { 0 };
+        if value.is_nan() {
+            return (0x7f, false);
+        }
+        if value.is_infinite() {
+            return match self.ieee {
+                true => (sign | self.infinity(), false),
+                false => (sign | 0x7e, true),
+            };
+        }
+
+        let magnitude = value.abs();
+        let min_exponent = 1 - self.bias;
+        let exponent = binary_exponent(magnitude).max(min_exponent);
+        let quantum = 2f64.powi(exponent - self.mantissa_bits);
+        let rounded = (magnitude / quantum).round_ties_even() * quantum;
+
+        if rounded > self.max {
+            return match self.ieee {
+                true => (sign | self.infinity(), false),
+                false => (sign | 0x7e, true),
+            };
+        }
+        if rounded == 0.0 {
+            return (sign, false);
+        }
+
+        // rounding may have carried into the next binade
+        let exponent = binary_exponent(rounded);
+        let bits = match exponent < min_exponent {
+            true => (rounded / 2f64.powi(min_exponent - self.mantissa_bits)) as u8,
+            false => {
+                let mantissa =
+                    (rounded / 2f64.powi(exponent) - 1.0) * (1 << self.mantissa_bits) as f64;
+                (((exponent + self.bias) as u8) << self.mantissa_bits) | mantissa as u8
+            }
+        };
+
+        (sign | bits, false)
+    }
+}
+
+/// The exponent of a positive finite number, i.e. `floor(log2(value))` for normal numbers.
+fn binary_exponent(value: f64) -> i32 {
+    ((value.to_bits() >> 52) & 0x7ff) as i32 - 1023
+}
+
+/// Decode one little-endian element.
+pub(crate) fn decode(dtype: Dtype, bytes: &[u8]) -> f64 {
+    match dtype {
+        Dtype::Fp64 => f64::from_le_bytes(bytes.try_into().expect("8 bytes")),
+        Dtype::Fp32 => f32::from_le_bytes(bytes.try_into().expect("4 bytes")) as f64,
+        Dtype::Fp16 => f16::from_le_bytes(bytes.try_into().expect("2 bytes")).to_f64(),
+        Dtype::Bf16 => bf16::from_le_bytes(bytes.try_into().expect("2 bytes")).to_f64(),
+        Dtype::Fp8E4M3 => FP8_E4M3.decode(bytes[0]),
+        Dtype::Fp8E5M2 => FP8_E5M2.decode(bytes[0]),
+        _ => unreachable!("{} is not a floating-point dtype", dtype),
+    }
+}
+
+/// Encode one element with round-to-nearest-even, returning whether it saturated.
+fn encode(dtype: Dtype, value: f64, output: &mut Vec<u8>) -> bool {
+    match dtype {
+        Dtype::Fp64 => output.extend(value.to_le_bytes()),
+        Dtype::Fp32 => output.extend((value as f32).to_le_bytes()),
+        Dtype::Fp16 => output.extend(f16::from_f64(value).to_le_bytes()),
+        Dtype::Bf16 => output.extend(bf16::from_f64(value).to_le_bytes()),
+        Dtype::Fp8E4M3 | Dtype::Fp8E5M2 => {
+            let format = match dtype {
+                Dtype::Fp8E4M3 => FP8_E4M3,
+                _ => FP8_E5M2,
+            };
+            let (bits, saturated) = format.encode(value);
+            output.push(bits);
+            return saturated;
+        }
+        _ => unreachable!("{} is not a floating-point dtype", dtype),
+    }
+    false
+}
+
+/// What happened to the values of a converted tensor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TensorConversion {
+    pub name: String,
+    pub from: Dtype,
+    pub to: Dtype,
+    pub elements: u64,
+    /// Finite values that became infinite, or values, infinities included,
+    /// that were clamped to the largest finite value of a dtype without
+    /// infinities.
+    pub overflow: u64,
+    /// Non-zero values that became zero.
+    pub underflow: u64,
+    /// NaN values, which were already NaN in the input.
+    pub nan: u64,
+}
+
+impl TensorConversion {
+    pub fn new(name: &str, from: Dtype, to: Dtype) -> Self {
+        Self {
+            name: name.to_string(),
+            from,
+            to,
+            elements: 0,
+            overflow: 0,
+            underflow: 0,
+            nan: 0,
+        }
+    }
+
+    /// Whether any value overflowed, underflowed or was NaN.
+    pub fn has_issues(&self) -> bool {
+        self.overflow > 0 || self.underflow > 0 || self.nan > 0
+    }
+}
+
+/// Convert a buffer of elements, counting the lossy values.
+pub fn convert_elements(
+    from: Dtype,
+    to: Dtype,
+    input: &[u8],
+    output: &mut Vec<u8>,
+    stats: &mut TensorConversion,
+) {
+    let size = from.bits() / 8;
+    for bytes in input.chunks_exact(size) {
+        let value = decode(from, bytes);
+        let start = output.len();
+        let saturated = encode(to, value, output);
+        let converted = decode(to, &output[start..]);
+
+        stats.elements += 1;
+        if value.is_nan() {
+            stats.nan += 1;
+        } else if saturated || (value.is_finite() && !converted.is_finite()) {
+            stats.overflow += 1;
+        } else if value != 0.0 && converted == 0.0 {
+            stats.underflow += 1;
+        }
+    }
+}
+
+/// Which tensors to convert, and to which dtype.
+///
+/// Only floating-point tensors are converted. A tensor is converted if it
+/// matches any `include` pattern (or there are none) and no `exclude` pattern,
+/// e.g. to keep the norms in fp32 with `--exclude '*norm*'`.
+#[derive(Debug, Clone)]
+pub struct DtypeConversion {
+    pub target: Dtype,
+    pub include: Vec<NamePattern>,
+    pub exclude: Vec<NamePattern>,
+}
+
+impl DtypeConversion {
+    pub fn new(target: Dtype) -> Result<Self> {
+        if !is_float(target) {
+            return Err(anyhow::anyhow!(
+                "Cannot convert to {}, only to floating-point dtypes",
+                target
+            ));
+        }
+
+        Ok(Self {
+            target,
+            include: Vec::new(),
+            exclude: Vec::new(),
+        })
+    }
+
+    pub fn with_include(mut self, include: Vec<NamePattern>) -> Self {
+        self.include = include;
+        self
+    }
+
+    pub fn with_exclude(mut self, exclude: Vec<NamePattern>) -> Self {
+        self.exclude = exclude;
+        self
+    }
+
+    /// Whether the tensor is converted.
+    pub fn applies(&self, name: &str, dtype: Dtype) -> bool {
+        is_float(dtype)
+            && dtype != self.target
+            && (self.include.is_empty() || self.include.iter().any(|p| p.is_match(name)))
+            && !self.exclude.iter().any(|pattern| pattern.is_match(name))
+    }
+}
+
+/// Converts the elements of a reader while they are read.
+pub struct ConvertReader<R> {
+    inner: R,
+    from: Dtype,
+    to: Dtype,
+    remaining: u64,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    position: usize,
+    stats: Arc<Mutex<TensorConversion>>,
+}
+
+impl<R: Read> ConvertReader<R> {
+    /// Read `elements` elements of `from` from the reader and yield them as `to`.
+    /// The counts are added to `stats`.
+    pub fn new(
+        inner: R,
+        from: Dtype,
+        to: Dtype,
+        elements: u64,
+        stats: Arc<Mutex<TensorConversion>>,
+    ) -> Self {
+        Self {
+            inner,
+            from,
+            to,
+            remaining: elements,
+            input: Vec::new(),
+            output: Vec::new(),
+            position: 0,
+            stats,
+        }
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        let elements = (self.remaining as usize).min(CHUNK_ELEMENTS);
+        self.input.resize(elements * self.from.bits() / 8, 0);
+        self.inner.read_exact(&mut self.input)?;
+        self.remaining -= elements as u64;
+
+        self.output.clear();
+        self.position = 0;
+        let mut stats = self.stats.lock().expect("conversion stats lock");
+        convert_elements(
+            self.from,
+            self.to,
+            &self.input,
+            &mut self.output,
+            &mut stats,
+        );
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for ConvertReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.output.len() {
+            if self.remaining == 0 {
+                return Ok(0);
+            }
+            self.fill()?;
+        }
+
+        let length = buf.len().min(self.output.len() - self.position);
+        buf[..length].copy_from_slice(&self.output[self.position..self.position + length]);
+        self.position += length;
+
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::writer::fixtures::f32_bytes;
+
+    fn convert(from: Dtype, to: Dtype, input: &[u8]) -> (Vec<u8>, TensorConversion) {
+        let mut output = Vec::new();
+        let mut stats = TensorConversion::new("test", from, to);
+        convert_elements(from, to, input, &mut output, &mut stats);
+        (output, stats)
+    }
+
+    #[test]
+    fn test_round_to_nearest_even() {
+        // 1 + 2^-8 is halfway between two bf16 values and rounds to the even 1.0,
+        // 1 + 3 * 2^-8 rounds up to the even 1 + 2^-6
+        let input = f32_bytes(&[1.0 + 2f32.powi(-8), 1.0 + 3.0 * 2f32.powi(-8)]);
+        let (output, _) = convert(Dtype::Fp32, Dtype::Bf16, &input);
+        assert_eq!(output, [0x80, 0x3f, 0x82, 0x3f]);
+
+        // fp8 e4m3: 1.0625 is halfway between 1.0 and 1.125
+        assert_eq!(FP8_E4M3.encode(1.0625), (0x38, false));
+        assert_eq!(FP8_E4M3.encode(1.1875), (0x3a, false));
+        assert_eq!(FP8_E4M3.decode(0x3a), 1.25);
+    }
+
+    #[test]
+    fn test_fp8() {
+        for bits in 0..=255u8 {
+            for format in [FP8_E4M3, FP8_E5M2] {
+                let value = format.decode(bits);
+                if !value.is_nan() {
+                    assert_eq!(format.encode(value).0, bits, "{:#x}", bits);
+                }
+            }
+        }
+
+        assert_eq!(FP8_E4M3.decode(0x7e), 448.0);
+        assert_eq!(FP8_E4M3.decode(0x01), 2f64.powi(-9));
+        assert_eq!(FP8_E4M3.encode(1000.0), (0x7e, true));
+        assert_eq!(FP8_E4M3.encode(-1000.0), (0xfe, true));
+        // e4m3 has no infinities, they saturate instead of becoming NaN
+        assert_eq!(FP8_E4M3.encode(f64::INFINITY), (0x7e, true));
+        assert_eq!(FP8_E4M3.encode(f64::NEG_INFINITY), (0xfe, true));
+        assert!(FP8_E4M3.encode(f64::NAN).0 & 0x7f == 0x7f);
+        assert_eq!(FP8_E5M2.decode(0x7b), 57344.0);
+        assert_eq!(FP8_E5M2.encode(1e6), (0x7c, false));
+        assert_eq!(FP8_E5M2.encode(f64::INFINITY), (0x7c, false));
+        assert_eq!(FP8_E5M2.encode(f64::NEG_INFINITY), (0xfc, false));
+    }
+
+    #[test]
+    fn test_conversion_stats() {
+        let input = f32_bytes(&[1.0, 1e6, f32::NAN, 1e-10, 0.0, f32::INFINITY]);
+        let (output, stats) = convert(Dtype::Fp32, Dtype::Fp16, &input);
+        assert_eq!(output.len(), 12);
+        assert_eq!(
+            (stats.elements, stats.overflow, stats.nan, stats.underflow),
+            (6, 1, 1, 1)
+        );
+
+        let (_, stats) = convert(Dtype::Fp32, Dtype::Bf16, &input);
+        assert_eq!((stats.overflow, stats.underflow), (0, 0));
+
+        let (output, stats) = convert(Dtype::Fp32, Dtype::Fp8E4M3, &f32_bytes(&[500.0]));
+        assert_eq!((output, stats.overflow), (vec![0x7e], 1));
+
+        let infinities = f32_bytes(&[f32::INFINITY, f32::NEG_INFINITY]);
+        let (output, stats) = convert(Dtype::Fp32, Dtype::Fp8E4M3, &infinities);
+        assert_eq!(output, [0x7e, 0xfe]);
+        assert_eq!((stats.overflow, stats.nan), (2, 0));
+        let (output, stats) = convert(Dtype::Fp32, Dtype::Fp8E5M2, &infinities);
+        assert_eq!(output, [0x7c, 0xfc]);
+        assert_eq!((stats.overflow, stats.nan), (0, 0));
+
+        let (output, _) = convert(Dtype::Bf16, Dtype::Fp32, &[0x80, 0x3f]);
+        assert_eq!(output, f32_bytes(&[1.0]));
+    }
+
+    #[test]
+    fn test_convert_reader() {
+        let input = f32_bytes(&[1.0, 2.0, 3.0]);
+        let stats = Arc::new(Mutex::new(TensorConversion::new(
+            "test",
+            Dtype::Fp32,
+            Dtype::Fp16,
+        )));
+        let mut reader = ConvertReader::new(&input[..], Dtype::Fp32, Dtype::Fp16, 3, stats.clone());
+
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+        assert_eq!(output, [0x00, 0x3c, 0x00, 0x40, 0x00, 0x42]);
+        assert_eq!(stats.lock().unwrap().elements, 3);
+
+        let conversion = DtypeConversion::new(Dtype::Bf16)
+            .unwrap()
+            .with_exclude(vec![NamePattern::new("*norm*").unwrap()]);
+        assert!(conversion.applies("layers.0.mlp.weight", Dtype::Fp32));
+        assert!(!conversion.applies("layers.0.norm.weight", Dtype::Fp32));
+        assert!(!conversion.applies("layers.0.mlp.weight", Dtype::Int64));
+        assert!(!conversion.applies("layers.0.mlp.weight", Dtype::Bf16));
+        assert!(DtypeConversion::new(Dtype::Int8).is_err());
+    }
+}
@@ -0,0 +1,492 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::Result;
+use rayon::prelude::*;
+
+use crate::hash::{compute_hashes, compute_hashes_cached, HashKind};
+use crate::metadata::Dtype;
+use crate::parser::{HeaderInfo, LocalParser, MetadataParser};
+use crate::progress::Progress;
+
+/// The bytes hashed from the start of each sampled tensor.
+const SAMPLE_BYTES: u64 = 1 << 20;
+
+/// Files whose tensor data is byte-identical.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdenticalFiles {
+    /// The BLAKE3 of the data section, as [`HashKind::Blake3`].
+    pub digest: String,
+    pub data_size: u64,
+    pub paths: Vec<PathBuf>,
+}
+
+impl IdenticalFiles {
+    /// The bytes saved by keeping one of the files.
+    pub fn savings(&self) -> u64 {
+        (self.paths.len() as u64 - 1) * self.data_size
+    }
+}
+
+/// A sampled tensor of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SampledTensor {
+    dtype: Dtype,
+    shape: Vec<i64>,
+    digest: blake3::Hash,
+}
+
+/// The tensors of a file sampled by name, so that files with the same tensor
+/// names sample the same tensors.
+#[derive(Debug, Clone)]
+struct FileSample {
+    tensors: BTreeMap<String, SampledTensor>,
+}
+
+/// A stable hash of the tensor name, which decides the sampled tensors.
+fn name_rank(name: &str) -> u64 {
+    let hash = blake3::hash(name.as_bytes());
+    u64::from_le_bytes(hash.as_bytes()[..8].try_into().expect("8 bytes"))
+}
+
+/// The names of the tensors to sample: the ones with the lowest ranks.
+fn sampled_names(info: &HeaderInfo, sample_size: usize) -> Vec<&String> {
+    let mut names = info.header.weights.keys().collect::<Vec<_>>();
+    names.sort_by_key(|name| name_rank(name));
+    names.truncate(sample_size);
+    names
+}
+
+/// The bytes read to sample the file.
+fn sample_bytes(info: &HeaderInfo, sample_size: usize) -> u64 {
+    sampled_names(info, sample_size)
+        .into_iter()
+        .map(|name| (info.header.weights[name].byte_size() as u64).min(SAMPLE_BYTES))
+        .sum()
+}
+
+/// Hash the start of the sampled tensors of the file.
+fn sample_file(
+    path: &Path,
+    info: &HeaderInfo,
+    sample_size: usize,
+    done: &dyn Fn(u64),
+) -> Result<FileSample> {
+    let mut file = File::open(path)?;
+    let mut tensors = BTreeMap::new();
+    for name in sampled_names(info, sample_size) {
+        let weight = &info.header.weights[name];
+        let length = (weight.byte_size() as u64).min(SAMPLE_BYTES);
+        let mut buffer = vec![0; length as usize];
+        file.seek(SeekFrom::Start(
+            info.data_start() + weight.data_offsets[0] as u64,
+        ))?;
+        file.read_exact(&mut buffer)?;
+        done(length);
+
+        let sample = SampledTensor {
+            dtype: weight.dtype,
+            shape: weight.shape.clone(),
+            digest: blake3::hash(&buffer),
+        };
+        tensors.insert(name.clone(), sample);
+    }
+
+    Ok(FileSample { tensors })
+}
+
+/// Two files that share tensors, e.g. a finetune and its base model.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelatedFiles {
+    pub first: PathBuf,
+    pub second: PathBuf,
+    /// The sampled tensors with the same name, dtype and shape in both files.
+    pub common: usize,
+    /// The common tensors whose sampled data is identical.
+    pub identical: usize,
+}
+
+impl RelatedFiles {
+    /// The share of the common tensors that are identical, from 0 to 1.
+    pub fn relatedness(&self) -> f64 {
+        match self.common {
+            0 => 0.0,
+            common => self.identical as f64 / common as f64,
+        }
+    }
+}
+
+/// The result of [`Dedupe::run`].
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DedupeReport {
+    /// The groups of identical files, the largest savings first.
+    pub identical: Vec<IdenticalFiles>,
+    /// The pairs of related files that are not identical, the most related
+    /// first. Identical files are represented by the first one.
+    pub related: Vec<RelatedFiles>,
+    /// The files that could not be read.
+    pub errors: Vec<(PathBuf, String)>,
+}
+
+/// Finds the files with identical tensor data and the related files.
+///
+/// Files are identical if their data sections are byte-identical; their
+/// `__metadata__` may differ. Only the files with the same data size are
+/// hashed, and the hashes are cached like [`compute_hashes_cached`] does,
+/// unless the cache is disabled.
+///
+/// Relatedness is estimated by sampling: the same tensor names are sampled
+/// in every file, and the first MiB of their data is hashed. A finetune that
+/// keeps some tensors of its base, e.g. the VAE or the text encoder, shares
+/// the hashes of those tensors. The bytes must be identical, so a full
+/// finetune that changed every tensor even slightly is not found related.
+#[derive(Debug, Clone)]
+pub struct Dedupe {
+    paths: Vec<PathBuf>,
+    sample_size: usize,
+    min_relatedness: f64,
+    cache: bool,
+    progress: Option<Progress>,
+}
+
+impl Dedupe {
+    pub fn new<P: AsRef<Path>>(paths: &[P]) -> Self {
+        Self {
+            paths: paths
+                .iter()
+                .map(|path| path.as_ref().to_path_buf())
+                .collect(),
+            sample_size: 64,
+            min_relatedness: 0.1,
+            cache: true,
+            progress: None,
+        }
+    }
+
+    /// The number of tensors sampled in each file. Zero disables the
+    /// relatedness estimate.
+    pub fn with_sample_size(mut self, sample_size: usize) -> Self {
+        self.sample_size = sample_size;
+        self
+    }
+
+    /// The share of identical sampled tensors above which two files are
+    /// reported as related.
+    pub fn with_min_relatedness(mut self, min_relatedness: f64) -> Self {
+        self.min_relatedness = min_relatedness;
+        self
+    }
+
+    /// Whether to reuse and store the hashes in the cache of each directory.
+    pub fn with_cache(mut self, cache: bool) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    pub fn with_progress(mut self, progress: Progress) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    pub fn run(&self) -> Result<DedupeReport> {
+        let mut report = DedupeReport::default();
+        let mut files = Vec::new();
+        for path in &self.paths {
+            match LocalParser::new(path).parse_header_info() {
+                Ok(info) => files.push((path, info)),
+                Err(err) => report.errors.push((path.clone(), format!("{:#}", err))),
+            }
+        }
+
+        // only the files with the same data size can be identical
+        let mut by_size: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (index, (_, info)) in files.iter().enumerate() {
+            by_size.entry(info.data_size()).or_default().push(index);
+        }
+        let mut candidates = by_size
+            .into_values()
+            .filter(|indices| indices.len() > 1)
+            .flatten()
+            .collect::<Vec<_>>();
+        candidates.sort();
+
+        let hash_total = candidates
+            .iter()
+            .map(|index| files[*index].1.data_size())
+            .sum::<u64>();
+        let sample_total = files
+            .iter()
+            .map(|(_, info)| sample_bytes(info, self.sample_size))
+            .sum::<u64>();
+        let total = hash_total + sample_total;
+        let done = AtomicU64::new(0);
+        let report_done = |length: u64| {
+            let done = done.fetch_add(length, Ordering::Relaxed) + length;
+            if let Some(progress) = &self.progress {
+                progress.update(done, total);
+            }
+        };
+
+        let mut digests: HashMap<usize, String> = HashMap::new();
+        let mut failed = vec![false; files.len()];
+        for index in candidates {
+            let (path, info) = &files[index];
+            let base = done.load(Ordering::Relaxed);
+            let progress = self.progress.clone().map(|progress| {
+                Progress::new(move |hashed, _| progress.update(base + hashed, total))
+            });
+            let hashes = match self.cache {
+                true => compute_hashes_cached(path, &[HashKind::Blake3], progress.as_ref()),
+                false => compute_hashes(path, &[HashKind::Blake3], progress.as_ref()),
+            };
+            match hashes {
+                Ok(hashes) => {
+                    digests.insert(index, hashes[0].computed.clone());
+                }
+                Err(err) => {
+                    report
+                        .errors
+                        .push((path.to_path_buf(), format!("{:#}", err)));
+                    failed[index] = true;
+                }
+            }
+            done.fetch_add(info.data_size(), Ordering::Relaxed);
+        }
+
+        let mut groups: BTreeMap<&String, Vec<usize>> = BTreeMap::new();
+        for (index, digest) in &digests {
+            groups.entry(digest).or_default().push(*index);
+        }
+        // the identical files are represented by the first one
+        let mut duplicate = vec![false; files.len()];
+        for (digest, mut indices) in groups {
+            if indices.len() < 2 {
+                continue;
+            }
+            indices.sort();
+            for index in &indices[1..] {
+                duplicate[*index] = true;
+            }
+            report.identical.push(IdenticalFiles {
+                digest: digest.clone(),
+                data_size: files[indices[0]].1.data_size(),
+                paths: indices
+                    .into_iter()
+                    .map(|index| files[index].0.clone())
+                    .collect(),
+            });
+        }
+        report
+            .identical
+            .sort_by(|a, b| (b.savings(), &a.paths[0]).cmp(&(a.savings(), &b.paths[0])));
+
+        if self.sample_size == 0 {
+            return Ok(report);
+        }
+        let samples = files
+            .par_iter()
+            .enumerate()
+            .map(
+                |(index, (path, info))| match duplicate[index] || failed[index] {
+                    true => {
+                        report_done(sample_bytes(info, self.sample_size));
+                        None
+                    }
+                    false => Some(sample_file(path, info, self.sample_size, &report_done)),
+                },
+            )
+            .collect::<Vec<_>>();
+
+        for (first, first_sample) in samples.iter().enumerate() {
+            let Some(Ok(first_sample)) = first_sample else {
+                continue;
+            };
+            for (second, second_sample) in samples.iter().enumerate().skip(first + 1) {
+                let Some(Ok(second_sample)) = second_sample else {
+                    continue;
+                };
+
+                let (mut common, mut identical) = (0, 0);
+                for (name, tensor) in &first_sample.tensors {
+                    match second_sample.tensors.get(name) {
+                        Some(other)
+                            if other.dtype == tensor.dtype && other.shape == tensor.shape =>
+                        {
+                            common += 1;
+                            if other.digest == tensor.digest {
+                                identical += 1;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                let related = RelatedFiles {
+                    first: files[first].0.clone(),
+                    second: files[second].0.clone(),
+                    common,
+                    identical,
+                };
+                if identical > 0 && related.relatedness() >= self.min_relatedness {
+                    report.related.push(related);
+                }
+            }
+        }
+        for (index, sample) in samples.into_iter().enumerate() {
+            if let Some(Err(err)) = sample {
+                report
+                    .errors
+                    .push((files[index].0.clone(), format!("{:#}", err)));
+            }
+        }
+        report.related.sort_by(|a, b| {
+            b.relatedness()
+                .total_cmp(&a.relatedness())
+                .then_with(|| (&a.first, &a.second).cmp(&(&b.first, &b.second)))
+        });
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::file::{Output, SafetensorsFile};
+    use crate::writer::fixtures::write_model;
+
+    #[test]
+    fn test_dedupe() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = (0..10)
+            .map(|i| {
+                (
+                    format!("layers.{}.weight", i),
+                    Dtype::Uint8,
+                    vec![i as u8; 16],
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut finetune = base.clone();
+        for (_, _, bytes) in finetune.iter_mut().take(4) {
+            bytes[0] = 255;
+        }
+        let unrelated = (0..10)
+            .map(|i| {
+                (
+                    format!("layers.{}.weight", i),
+                    Dtype::Uint8,
+                    vec![100 + i as u8; 16],
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let path = |name: &str| dir.path().join(name);
+        write_model(&path("base.safetensors"), &base);
+        write_model(&path("finetune.safetensors"), &finetune);
+        write_model(&path("unrelated.safetensors"), &unrelated);
+        // the same weights with other metadata
+        SafetensorsFile::new(path("base.safetensors"))
+            .set_metadata(
+                Output::Path(path("copy.safetensors")),
+                &[("modelspec.title".to_string(), "Copy".to_string())],
+            )
+            .unwrap();
+        std::fs::write(path("broken.safetensors"), b"broken").unwrap();
+
+        let paths = [
+            "base.safetensors",
+            "broken.safetensors",
+            "copy.safetensors",
+            "finetune.safetensors",
+            "unrelated.safetensors",
+        ]
+        .map(path);
+        let report = Dedupe::new(&paths).run().unwrap();
+
+        assert_eq!(report.identical.len(), 1);
+        assert_eq!(
+            report.identical[0].paths,
+            vec![path("base.safetensors"), path("copy.safetensors")]
+        );
+        assert_eq!(report.identical[0].savings(), 160);
+
+        let related = report
+            .related
+            .iter()
+            .map(|related| {
+                let name = |path: &PathBuf| path.file_name().unwrap().to_string_lossy().to_string();
+                (
+                    name(&related.first),
+                    name(&related.second),
+                    related.identical,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            related,
+            vec![(
+                "base.safetensors".to_string(),
+                "finetune.safetensors".to_string(),
+                6
+            ),]
+        );
+        assert_eq!(report.related[0].common, 10);
+        assert_eq!(report.related[0].relatedness(), 0.6);
+
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(report.errors[0].0, path("broken.safetensors"));
+
+        // without sampling, only the identical files are found
+        let report = Dedupe::new(&paths).with_sample_size(0).run().unwrap();
+        assert_eq!(report.identical.len(), 1);
+        assert!(report.related.is_empty());
+        assert_eq!(report.errors.len(), 1);
+    }
+
+    #[test]
+    fn test_dedupe_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+        let tensors = [("weight", Dtype::Uint8, vec![1; 16])];
+        write_model(&path("first.safetensors"), &tensors);
+        write_model(&path("second.safetensors"), &tensors);
+        write_model(
+            &path("other.safetensors"),
+            &[("weight", Dtype::Uint8, vec![1; 8])],
+        );
+        let paths = [
+            "first.safetensors",
+            "second.safetensors",
+            "other.safetensors",
+        ]
+        .map(path);
+        let cache_path = path(crate::hash::HASH_CACHE_FILE);
+
+        let report = Dedupe::new(&paths).with_cache(false).run().unwrap();
+        assert_eq!(report.identical.len(), 1);
+        assert!(!cache_path.exists());
+
+        // only the files with the same data size are hashed and cached
+        let report = Dedupe::new(&paths).run().unwrap();
+        assert_eq!(report.identical.len(), 1);
+        let cache = std::fs::read_to_string(&cache_path).unwrap();
+        assert!(cache.contains("first.safetensors") && cache.contains("second.safetensors"));
+        assert!(!cache.contains("other.safetensors"));
+
+        // the cached hashes are reused, so a stale entry hides the duplicate
+        let cache = cache.replacen(&report.identical[0].digest, "0xstale", 1);
+        std::fs::write(&cache_path, cache).unwrap();
+        assert!(Dedupe::new(&paths).run().unwrap().identical.is_empty());
+        assert_eq!(
+            Dedupe::new(&paths)
+                .with_cache(false)
+                .run()
+                .unwrap()
+                .identical,
+            report.identical
+        );
+    }
+}
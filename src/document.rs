@@ -0,0 +1,149 @@
+use anyhow::Result;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The file format of exported metadata.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl DocumentFormat {
+    /// Guess the format from the file extension.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let extension = path
+            .as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default();
+
+        extension.parse().map_err(|_| {
+            anyhow::anyhow!(
+                "Cannot guess the format of {}, expected a .json, .toml or .yaml file",
+                path.as_ref().display()
+            )
+        })
+    }
+
+    /// Serialize the metadata as a flat map of keys to strings.
+    pub fn serialize(&self, map: &BTreeMap<String, String>) -> Result<String> {
+        let text = match self {
+            DocumentFormat::Json => serde_json::to_string_pretty(map)? + "\n",
+            DocumentFormat::Toml => toml::to_string(map)?,
+            DocumentFormat::Yaml => serde_yaml::to_string(map)?,
+        };
+
+        Ok(text)
+    }
+
+    /// Deserialize a flat map of metadata keys. See [`from_value`].
+    pub fn deserialize(&self, text: &str) -> Result<BTreeMap<String, String>> {
+        let value: Value = match self {
+            DocumentFormat::Json => serde_json::from_str(text)?,
+            DocumentFormat::Toml => toml::from_str(text)?,
+            DocumentFormat::Yaml => serde_yaml::from_str(text)?,
+        };
+
+        from_value(value)
+    }
+}
+
+/// Convert a JSON object into metadata entries.
+///
+/// Numbers and booleans are converted to strings, and nested tables or
+/// arrays are stored as JSON strings, as the metadata only holds strings.
+pub fn from_value(value: Value) -> Result<BTreeMap<String, String>> {
+    let Value::Object(object) = value else {
+        return Err(anyhow::anyhow!("Expected a table of metadata keys"));
+    };
+
+    object
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Value::String(value) => value,
+                Value::Null => return Err(anyhow::anyhow!("{}: null is not allowed", key)),
+                Value::Bool(_) | Value::Number(_) => value.to_string(),
+                Value::Array(_) | Value::Object(_) => serde_json::to_string(&value)?,
+            };
+            Ok((key, value))
+        })
+        .collect()
+}
+
+impl FromStr for DocumentFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(DocumentFormat::Json),
+            "toml" => Ok(DocumentFormat::Toml),
+            "yaml" | "yml" => Ok(DocumentFormat::Yaml),
+            _ => Err(anyhow::anyhow!(
+                "Unknown format: {} (expected json, toml or yaml)",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for DocumentFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            DocumentFormat::Json => "json",
+            DocumentFormat::Toml => "toml",
+            DocumentFormat::Yaml => "yaml",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let map = BTreeMap::from([
+            ("format".to_string(), "pt".to_string()),
+            ("modelspec.title".to_string(), "My \"LoRA\"".to_string()),
+            ("ss_tag_frequency".to_string(), r#"{"a": 1}"#.to_string()),
+        ]);
+
+        for format in [
+            DocumentFormat::Json,
+            DocumentFormat::Toml,
+            DocumentFormat::Yaml,
+        ] {
+            let text = format.serialize(&map).unwrap();
+            assert_eq!(format.deserialize(&text).unwrap(), map, "{}", format);
+        }
+    }
+
+    #[test]
+    fn test_deserialize_values() {
+        let text = "format = \"pt\"\nss_network_dim = 4\nss_enabled = true\n[ss_tags]\na = 1\n";
+        let map = DocumentFormat::Toml.deserialize(text).unwrap();
+
+        assert_eq!(map["ss_network_dim"], "4");
+        assert_eq!(map["ss_enabled"], "true");
+        assert_eq!(map["ss_tags"], r#"{"a":1}"#);
+
+        assert!(DocumentFormat::Json.deserialize("[1, 2]").is_err());
+        assert!(DocumentFormat::Yaml.deserialize("title: null").is_err());
+    }
+
+    #[test]
+    fn test_from_path() {
+        assert_eq!(
+            DocumentFormat::from_path("meta.yml").unwrap(),
+            DocumentFormat::Yaml
+        );
+        assert!(DocumentFormat::from_path("meta.txt").is_err());
+    }
+}
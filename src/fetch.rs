@@ -1,6 +1,7 @@
 use anyhow::Result;
-use reqwest::blocking::Client;
-use reqwest::header::{HeaderMap, HeaderValue, RANGE};
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_RANGE, RANGE};
+use reqwest::StatusCode;
 
 /// Insert the Hugging Face token into the headers
 fn insert_hf_token_header(token: &Option<String>, headers: &mut HeaderMap) -> Result<HeaderMap> {
@@ -14,41 +15,248 @@ fn insert_hf_token_header(token: &Option<String>, headers: &mut HeaderMap) -> Re
     Ok(headers.clone())
 }
 
-/// Insert the Range header into the headers
-fn insert_range_bytes_header(headers: &mut HeaderMap, start: u64, end: u64) -> Result<HeaderMap> {
+/// Insert the Range header for `length` bytes from `start` into the headers.
+/// Fails for an empty range, which cannot be expressed.
+fn insert_range_bytes_header(
+    headers: &mut HeaderMap,
+    start: u64,
+    length: u64,
+) -> Result<HeaderMap> {
+    let end = length
+        .checked_sub(1)
+        .and_then(|last| start.checked_add(last))
+        .ok_or_else(|| anyhow::anyhow!("Invalid byte range: {} bytes from {}", length, start))?;
     let range_header_value = format!("bytes={}-{}", start, end);
     headers.insert(RANGE, HeaderValue::from_str(&range_header_value)?);
 
     Ok(headers.clone())
 }
 
-pub fn fetch_remote_bytes(
-    url: &String,
+/// Parse the total size from the Content-Range header, e.g. `bytes 0-7/12345`
+fn parse_content_range_total(value: &str) -> Option<u64> {
+    value.rsplit_once('/')?.1.trim().parse().ok()
+}
+
+/// The fetched bytes and the total size of the remote file if the server reported it.
+#[derive(Debug, Clone)]
+pub struct RemoteBytes {
+    pub bytes: Vec<u8>,
+    pub total_size: Option<u64>,
+}
+
+/// Fetch the byte range with a range request.
+///
+/// Fails if the server ignores the range, instead of downloading the whole file.
+pub fn fetch_remote_range(
+    url: &str,
     token: &Option<String>,
     start: u64,
     length: u64,
-) -> Result<Vec<u8>> {
+) -> Result<RemoteBytes> {
     let client = Client::new();
 
     let mut headers = HeaderMap::new();
 
     // insert headers
-    insert_hf_token_header(&token, &mut headers)?;
-    insert_range_bytes_header(&mut headers, start, start + length - 1)?;
+    insert_hf_token_header(token, &mut headers)?;
+    insert_range_bytes_header(&mut headers, start, length)?;
+
+    let res = client.get(url).headers(headers).send()?;
+
+    match res.status() {
+        StatusCode::PARTIAL_CONTENT => {
+            let total_size = match res.headers().get(CONTENT_RANGE) {
+                Some(value) => parse_content_range_total(value.to_str()?),
+                None => None,
+            };
+            let bytes = res.bytes()?;
+
+            Ok(RemoteBytes {
+                bytes: bytes.to_vec(),
+                total_size,
+            })
+        }
+        status if status.is_success() => Err(anyhow::anyhow!(
+            "The server does not support range requests: {}",
+            url
+        )),
+        status => Err(anyhow::anyhow!(
+            "Failed to fetch the data: {} ({})",
+            url,
+            status
+        )),
+    }
+}
+
+pub fn fetch_remote_bytes(
+    url: &str,
+    token: &Option<String>,
+    start: u64,
+    length: u64,
+) -> Result<Vec<u8>> {
+    Ok(fetch_remote_range(url, token, start, length)?.bytes)
+}
+
+/// A remote file read with range requests, reusing the connection between requests.
+#[derive(Debug, Clone)]
+pub struct RemoteFile {
+    client: Client,
+    url: String,
+    token: Option<String>,
+}
+
+impl RemoteFile {
+    pub fn new(url: &str, token: &Option<String>) -> Self {
+        Self {
+            client: Client::new(),
+            url: url.to_string(),
+            token: token.clone(),
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
 
-    let res = client.get(url.as_str()).headers(headers).send()?;
+    /// Stream the byte range. The response implements `Read`.
+    ///
+    /// Fails if the server ignores the range, so that the wrong bytes are never read.
+    pub fn open_range(&self, start: u64, length: u64) -> Result<Response> {
+        let mut headers = HeaderMap::new();
+        insert_hf_token_header(&self.token, &mut headers)?;
+        insert_range_bytes_header(&mut headers, start, length)?;
 
-    if res.status().is_success() {
-        let bytes = res.bytes()?;
-        Ok(bytes.to_vec())
-    } else {
-        Err(anyhow::anyhow!("Failed to fetch the data"))
+        let res = self.client.get(&self.url).headers(headers).send()?;
+
+        match res.status() {
+            StatusCode::PARTIAL_CONTENT => Ok(res),
+            status if status.is_success() => Err(anyhow::anyhow!(
+                "The server does not support range requests: {}",
+                self.url
+            )),
+            status => Err(anyhow::anyhow!(
+                "Failed to fetch the data: {} ({})",
+                self.url,
+                status
+            )),
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+
+    /// Answer one request on a local port with the status and headers, and
+    /// return the URL and a receiver for the Range header of the request.
+    fn serve_once(status: &str, headers: &[&str], body: &[u8]) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/model.safetensors",
+            listener.local_addr().unwrap()
+        );
+        let mut response = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\n", status, body.len());
+        for header in headers {
+            response.push_str(&format!("{}\r\n", header));
+        }
+        response.push_str("Connection: close\r\n\r\n");
+        let mut response = response.into_bytes();
+        response.extend_from_slice(body);
+
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut range = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("range") {
+                        range = value.trim().to_string();
+                    }
+                }
+            }
+            // the receiver is dropped by the tests that do not check the range
+            let _ = sender.send(range);
+            stream.write_all(&response).unwrap();
+        });
+
+        (url, receiver)
+    }
+
+    #[test]
+    fn test_fetch_remote_range() {
+        let (url, range) = serve_once(
+            "206 Partial Content",
+            &["Content-Range: bytes 8-11/100"],
+            b"abcd",
+        );
+        let remote = fetch_remote_range(&url, &None, 8, 4).unwrap();
+        assert_eq!(range.recv().unwrap(), "bytes=8-11");
+        assert_eq!(remote.bytes, b"abcd");
+        assert_eq!(remote.total_size, Some(100));
+
+        // without Content-Range, the total size is unknown
+        let (url, _) = serve_once("206 Partial Content", &[], b"abcd");
+        let remote = fetch_remote_range(&url, &None, 8, 4).unwrap();
+        assert_eq!(remote.total_size, None);
+
+        // a server ignoring the range sends the whole file
+        let (url, _) = serve_once("200 OK", &[], &[0; 100]);
+        let err = fetch_remote_range(&url, &None, 8, 4).unwrap_err();
+        assert!(err.to_string().contains("does not support range requests"));
+
+        let (url, _) = serve_once("404 Not Found", &[], b"");
+        let err = fetch_remote_range(&url, &None, 8, 4).unwrap_err();
+        assert!(err.to_string().contains("404"));
+
+        assert!(fetch_remote_range("http://127.0.0.1:1/", &None, 8, 0).is_err());
+    }
+
+    #[test]
+    fn test_open_range() {
+        let (url, range) = serve_once(
+            "206 Partial Content",
+            &["Content-Range: bytes 0-3/100"],
+            b"abcd",
+        );
+        let mut bytes = Vec::new();
+        RemoteFile::new(&url, &None)
+            .open_range(0, 4)
+            .unwrap()
+            .read_to_end(&mut bytes)
+            .unwrap();
+        assert_eq!(range.recv().unwrap(), "bytes=0-3");
+        assert_eq!(bytes, b"abcd");
+
+        let (url, _) = serve_once("200 OK", &[], &[0; 100]);
+        let err = RemoteFile::new(&url, &None).open_range(0, 4).unwrap_err();
+        assert!(err.to_string().contains("does not support range requests"));
+    }
+
+    #[test]
+    fn test_parse_content_range_total() {
+        assert_eq!(parse_content_range_total("bytes 0-7/12345"), Some(12345));
+        assert_eq!(parse_content_range_total("bytes 0-7/*"), None);
+        assert_eq!(parse_content_range_total("bytes 0-7"), None);
+    }
+
+    #[test]
+    fn test_insert_range_bytes_header() {
+        let mut headers = HeaderMap::new();
+        insert_range_bytes_header(&mut headers, 8, 8).unwrap();
+        assert_eq!(headers[RANGE], "bytes=8-15");
+        assert!(insert_range_bytes_header(&mut headers, 8, 0).is_err());
+        assert!(insert_range_bytes_header(&mut headers, u64::MAX, 2).is_err());
+    }
 
     #[test]
     fn test_read_example_8bytes() {
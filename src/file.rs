@@ -1,17 +1,145 @@
 use std::{
-    fs::File,
-    io::{self, BufWriter, Seek, SeekFrom, Write},
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{BufWriter, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
+    sync::{Arc, Mutex},
 };
 
 use anyhow::Result;
+use regex::Regex;
 
-use crate::parser::{LocalParser, MetadataParser};
+use crate::atomic::{backup_path, header_backup_path, is_same_file, AtomicFile};
+use crate::clean::KeyFilter;
+use crate::convert::{ConvertReader, DtypeConversion, TensorConversion};
+use crate::metadata::{Header, Metadata};
+use crate::parser::{HeaderInfo, LocalParser, MetadataParser, RemoteParser};
+use crate::patch::{diff, MetadataChange, MetadataPatch};
+use crate::pattern::NamePattern;
+use crate::progress::{copy_with_progress, Progress};
+use crate::writer::{FileRange, SafetensorsWriter, TensorSource};
+
+/// The data section starts at a multiple of this, as the reference implementation does.
+pub(crate) const HEADER_ALIGNMENT: usize = 8;
+
+/// Serialize the header, padded with trailing spaces so that the data section is aligned.
+///
+/// `headroom` extra bytes are reserved so that later edits can be done in place.
+pub fn serialize_header(header: &Header, headroom: usize) -> Result<Vec<u8>> {
+    let mut buffer = serde_json::to_vec(header)?;
+    let padded_size = (buffer.len() + headroom).div_ceil(HEADER_ALIGNMENT) * HEADER_ALIGNMENT;
+    buffer.resize(padded_size, b' ');
+
+    Ok(buffer)
+}
+
+/// Where to write the modified file.
+///
+/// New files are written to a temporary file in the same directory, synced,
+/// and renamed to the destination, so they are never left half-written. The
+/// one exception is an in-place edit whose header fits, see [`Output::InPlace`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+    /// Write a new file, copying the data section. Must not be the input file.
+    Path(PathBuf),
+    /// Modify the input file.
+    ///
+    /// If the new header fits in the existing header, including its padding,
+    /// only the header bytes are overwritten with a single synced write. This
+    /// avoids copying the tensor data, but is not atomic: a crash during the
+    /// write can leave a corrupt header. The backup of such an edit only holds
+    /// the original header bytes, as `<file>.header.bak`, which can be written
+    /// back at the start of the file.
+    ///
+    /// Otherwise the whole file is rewritten and renamed over the input, and
+    /// the backup is the original file, as `<file>.bak`.
+    InPlace,
+}
+
+impl From<PathBuf> for Output {
+    fn from(path: PathBuf) -> Self {
+        Output::Path(path)
+    }
+}
+
+impl From<&PathBuf> for Output {
+    fn from(path: &PathBuf) -> Self {
+        Output::Path(path.clone())
+    }
+}
+
+impl From<&Path> for Output {
+    fn from(path: &Path) -> Self {
+        Output::Path(path.to_path_buf())
+    }
+}
+
+impl From<&str> for Output {
+    fn from(path: &str) -> Self {
+        Output::Path(PathBuf::from(path))
+    }
+}
+
+/// How imported metadata is combined with the existing metadata.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImportMode {
+    /// Discard the existing metadata.
+    #[default]
+    Replace,
+    /// Keep the existing keys that are not in the imported metadata.
+    Merge,
+}
+
+/// What a write operation did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriteReport {
+    /// The path of the written file.
+    pub output: PathBuf,
+    /// The changes to the metadata.
+    pub changes: Vec<MetadataChange>,
+    pub old_header_size: u64,
+    pub new_header_size: u64,
+    /// The bytes of tensor data copied, 0 if only the header was overwritten.
+    pub bytes_copied: u64,
+    /// Whether the file was rewritten, rather than only its header bytes.
+    pub rewritten: bool,
+    /// The backup of the original file, if any.
+    pub backup: Option<PathBuf>,
+    /// The number of tensors written, 0 if the tensors were not rewritten.
+    pub tensors_written: usize,
+    /// The tensors that were dropped.
+    pub removed_tensors: Vec<String>,
+    /// The tensors that were renamed, as `(old, new)`.
+    pub renamed_tensors: Vec<(String, String)>,
+    /// The tensors that were converted to another dtype.
+    pub converted_tensors: Vec<TensorConversion>,
+}
+
+impl WriteReport {
+    /// The metadata keys that were removed.
+    pub fn removed_keys(&self) -> Vec<&String> {
+        self.changes
+            .iter()
+            .filter_map(|change| match change {
+                MetadataChange::Removed { key, .. } => Some(key),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// How much smaller the file became, negative if it grew.
+    pub fn bytes_removed(&self) -> i64 {
+        self.old_header_size as i64 - self.new_header_size as i64
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct SafetensorsFile {
     file_path: PathBuf,
     parser: LocalParser,
+    headroom: usize,
+    backup: bool,
+    progress: Option<Progress>,
 }
 
 impl SafetensorsFile {
@@ -20,55 +148,987 @@ impl SafetensorsFile {
 
         Self {
             file_path: file_path.as_ref().to_path_buf(),
-            parser: parser,
+            parser,
+            headroom: 0,
+            backup: false,
+            progress: None,
         }
     }
 
-    pub fn clear_metadata<P: AsRef<Path>>(&self, output_path: P) -> Result<()> {
-        let output_file = File::options()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(&output_path)?;
+    /// Reserve extra bytes in the header of new files, so that later edits can be done in place.
+    pub fn with_headroom(mut self, headroom: usize) -> Self {
+        self.headroom = headroom;
+        self
+    }
 
-        let mut writer = BufWriter::new(output_file);
+    /// Keep the original file as `<file>.bak` when modifying it in place.
+    pub fn with_backup(mut self, backup: bool) -> Self {
+        self.backup = backup;
+        self
+    }
 
-        let header_buffer_size = self.parser.get_header_size()?;
+    /// Report the bytes of tensor data copied while writing a new file.
+    pub fn with_progress(mut self, progress: Progress) -> Self {
+        self.progress = Some(progress);
+        self
+    }
 
-        // remove metadata
-        let mut header = self.parser.parse_header()?;
-        if let Some(metadata) = header.metadata {
-            let bytes = serde_json::to_vec(&metadata)?.len() as u64;
-            println!("Found metadata: {} bytes", bytes);
-        } else {
-            println!("No metadata found");
+    fn write_with_header(&self, output: &Output, header: &Header) -> Result<WriteReport> {
+        match output {
+            Output::Path(output_path) => {
+                self.check_not_input(output_path)?;
+                let (file, report) = self.copy_with_header(output_path, header)?;
+                file.commit()?;
+
+                Ok(report)
+            }
+            Output::InPlace => {
+                let new_header_size = serde_json::to_vec(header)?.len() as u64;
+                if new_header_size <= self.parser.get_header_size()? {
+                    let backup = match self.backup {
+                        true => Some(self.backup_header()?),
+                        false => None,
+                    };
+                    let report = self.overwrite_header(header)?;
+
+                    return Ok(WriteReport { backup, ..report });
+                }
+
+                let (file, mut report) = self.copy_with_header(&self.file_path, header)?;
+                if self.backup {
+                    report.backup = Some(self.link_backup()?);
+                }
+                file.commit()?;
+
+                Ok(report)
+            }
         }
+    }
+
+    /// Writing a new file over the input would destroy the data being copied.
+    fn check_not_input(&self, output_path: &Path) -> Result<()> {
+        match is_same_file(&self.file_path, output_path)? {
+            true => Err(anyhow::anyhow!(
+                "The output {} is the input file. Modify it in place instead.",
+                output_path.display()
+            )),
+            false => Ok(()),
+        }
+    }
+
+    /// Keep the original file under the backup name before it is replaced by a
+    /// rename. A hard link is used to avoid copying, if possible.
+    fn link_backup(&self) -> Result<PathBuf> {
+        let backup = backup_path(&self.file_path);
+        if backup.exists() {
+            fs::remove_file(&backup)?;
+        }
+        fs::hard_link(&self.file_path, &backup)
+            .or_else(|_| fs::copy(&self.file_path, &backup).map(|_| ()))?;
 
-        header.metadata = None;
+        Ok(backup)
+    }
+
+    /// Keep the header size and the header bytes of the file under the header
+    /// backup name before the header is overwritten.
+    fn backup_header(&self) -> Result<PathBuf> {
+        let backup = header_backup_path(&self.file_path);
+        let header_size = self.parser.get_header_size()?;
+        let mut source_file = File::open(&self.file_path)?;
+        let mut backup_file = AtomicFile::create(&backup)?;
+        copy_with_progress(&mut source_file, backup_file.file(), 8 + header_size, None)?;
+        backup_file.commit()?;
 
-        let new_header_buffer = serde_json::to_vec(&header)?;
-        let new_header_size = new_header_buffer.len() as u64; // smaller
+        Ok(backup)
+    }
+
+    /// Write the new header and the original data section to a temporary file,
+    /// which replaces the output path when committed.
+    fn copy_with_header(
+        &self,
+        output_path: &Path,
+        header: &Header,
+    ) -> Result<(AtomicFile, WriteReport)> {
+        let mut output_file = AtomicFile::create(output_path)?;
+
+        let header_buffer_size = self.parser.get_header_size()?;
+
+        let new_header_buffer = serialize_header(header, self.headroom)?;
+        let new_header_size = new_header_buffer.len() as u64;
+
+        let mut writer = BufWriter::new(output_file.file());
 
         // first part: header size [u8; 8]
         writer.write_all(&new_header_size.to_le_bytes())?;
 
         // second part: header buffer
         writer.write_all(&new_header_buffer)?;
-        writer.flush()?;
 
         // third part: weights
         // move the original third part to the new position
         let mut source_file = File::open(&self.file_path)?;
+        let data_size = source_file
+            .metadata()?
+            .len()
+            .checked_sub(8 + header_buffer_size)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "{} is shorter than its header size of {} bytes",
+                    self.file_path.display(),
+                    header_buffer_size
+                )
+            })?;
         // skip the header size and the header buffer
         source_file.seek(SeekFrom::Start(8 + header_buffer_size))?;
-        let mut dst_file = File::options()
-            .write(true)
-            .create(false)
-            .append(true)
-            .truncate(false)
-            .open(&output_path)?;
-        io::copy(&mut source_file, &mut dst_file)?;
-
-        Ok(())
+        let bytes_copied = copy_with_progress(
+            &mut source_file,
+            &mut writer,
+            data_size,
+            self.progress.as_ref(),
+        )?;
+        writer.flush()?;
+        drop(writer);
+
+        let report = WriteReport {
+            output: output_path.to_path_buf(),
+            old_header_size: header_buffer_size,
+            new_header_size,
+            bytes_copied,
+            rewritten: true,
+            ..Default::default()
+        };
+
+        Ok((output_file, report))
+    }
+
+    /// Overwrite the header bytes of the original file, padding the new header
+    /// with trailing spaces to the existing header size.
+    fn overwrite_header(&self, header: &Header) -> Result<WriteReport> {
+        let header_buffer_size = self.parser.get_header_size()?;
+
+        let mut new_header_buffer = serde_json::to_vec(header)?;
+        new_header_buffer.resize(header_buffer_size as usize, b' ');
+
+        // the header size (first part) stays the same
+        let mut file = File::options().write(true).open(&self.file_path)?;
+        file.seek(SeekFrom::Start(8))?;
+        file.write_all(&new_header_buffer)?;
+        file.sync_all()?;
+
+        Ok(WriteReport {
+            output: self.file_path.clone(),
+            old_header_size: header_buffer_size,
+            new_header_size: header_buffer_size,
+            ..Default::default()
+        })
+    }
+
+    /// Remove the metadata field.
+    pub fn clear_metadata<O: Into<Output>>(&self, output: O) -> Result<WriteReport> {
+        self.update_metadata(output, |map| {
+            map.clear();
+            Ok(())
+        })
+    }
+
+    /// Rewrite the metadata as plain key-value pairs.
+    ///
+    /// The metadata is removed from the header if no keys are left.
+    pub fn update_metadata<O, F>(&self, output: O, update: F) -> Result<WriteReport>
+    where
+        O: Into<Output>,
+        F: FnOnce(&mut BTreeMap<String, String>) -> Result<()>,
+    {
+        let mut header = self.parser.parse_header()?;
+
+        let old_map = match &header.metadata {
+            Some(metadata) => metadata.to_map()?,
+            None => BTreeMap::new(),
+        };
+        let mut map = old_map.clone();
+        update(&mut map)?;
+        let changes = diff(&old_map, &map);
+
+        header.metadata = match map.is_empty() {
+            true => None,
+            false => Some(Metadata::from_map(map)?),
+        };
+
+        let report = self.write_with_header(&output.into(), &header)?;
+
+        Ok(WriteReport { changes, ..report })
+    }
+
+    /// Set the metadata keys, overwriting the existing values.
+    pub fn set_metadata<O: Into<Output>>(
+        &self,
+        output: O,
+        entries: &[(String, String)],
+    ) -> Result<WriteReport> {
+        self.update_metadata(output, |map| {
+            for (key, value) in entries {
+                map.insert(key.clone(), value.clone());
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Remove the metadata keys. Fails if any of the keys does not exist.
+    pub fn unset_metadata<O: Into<Output>>(
+        &self,
+        output: O,
+        keys: &[String],
+    ) -> Result<WriteReport> {
+        self.update_metadata(output, |map| {
+            for key in keys {
+                if map.remove(key).is_none() {
+                    return Err(anyhow::anyhow!("Metadata key not found: {}", key));
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Replace or merge the metadata with the imported entries.
+    pub fn import_metadata<O: Into<Output>>(
+        &self,
+        output: O,
+        entries: BTreeMap<String, String>,
+        mode: ImportMode,
+    ) -> Result<WriteReport> {
+        self.update_metadata(output, |map| {
+            if mode == ImportMode::Replace {
+                map.clear();
+            }
+            map.extend(entries);
+
+            Ok(())
+        })
+    }
+
+    /// Apply the patch to the metadata.
+    pub fn patch_metadata<O: Into<Output>>(
+        &self,
+        output: O,
+        patch: &MetadataPatch,
+    ) -> Result<WriteReport> {
+        self.update_metadata(output, |map| {
+            *map = patch.apply(map)?;
+            Ok(())
+        })
+    }
+
+    /// Remove the metadata keys selected by the filter.
+    pub fn clean_metadata<O: Into<Output>>(
+        &self,
+        output: O,
+        filter: &KeyFilter,
+    ) -> Result<WriteReport> {
+        self.update_metadata(output, |map| {
+            map.retain(|key, _| !filter.is_removed(key));
+            Ok(())
+        })
+    }
+
+    /// Rename the metadata key, keeping its value.
+    pub fn rename_metadata_key<O: Into<Output>>(
+        &self,
+        output: O,
+        from: &str,
+        to: &str,
+    ) -> Result<WriteReport> {
+        self.update_metadata(output, |map| {
+            if map.contains_key(to) {
+                return Err(anyhow::anyhow!("Metadata key already exists: {}", to));
+            }
+            let value = map
+                .remove(from)
+                .ok_or_else(|| anyhow::anyhow!("Metadata key not found: {}", from))?;
+            map.insert(to.to_string(), value);
+
+            Ok(())
+        })
+    }
+
+    /// Write the tensors selected by `select` to a new file, keeping the
+    /// metadata except the stored hashes.
+    ///
+    /// `select` returns the new name of the tensor, or `None` to drop it.
+    /// The offsets are recomputed and only the byte ranges of the remaining
+    /// tensors are copied.
+    pub fn rewrite_tensors<O, F>(&self, output: O, select: F) -> Result<WriteReport>
+    where
+        O: Into<Output>,
+        F: Fn(&str) -> Option<String>,
+    {
+        self.rewrite_with(output, |info, writer| {
+            let selection =
+                writer.add_tensors_from(info, select, |offset, length| TensorSource::File {
+                    path: self.file_path.clone(),
+                    offset,
+                    length,
+                })?;
+
+            Ok(WriteReport {
+                removed_tensors: selection.skipped,
+                renamed_tensors: selection.renamed,
+                ..Default::default()
+            })
+        })
+    }
+
+    /// Rewrite the file with the tensors added by `add`, keeping the metadata
+    /// except the stored hashes, which the writer drops.
+    ///
+    /// The tensor lists of the report returned by `add` are kept in the final report.
+    fn rewrite_with<O, F>(&self, output: O, add: F) -> Result<WriteReport>
+    where
+        O: Into<Output>,
+        F: FnOnce(&HeaderInfo, &mut SafetensorsWriter) -> Result<WriteReport>,
+    {
+        let info = self.parser.parse_header_info()?;
+
+        let mut writer = SafetensorsWriter::new().with_headroom(self.headroom);
+        if let Some(metadata) = &info.header.metadata {
+            writer = writer.with_metadata(metadata.to_map()?);
+        }
+        if let Some(progress) = &self.progress {
+            writer = writer.with_progress(progress.clone());
+        }
+        let added = add(&info, &mut writer)?;
+
+        let output_path = match output.into() {
+            Output::Path(output_path) => {
+                self.check_not_input(&output_path)?;
+                output_path
+            }
+            Output::InPlace => self.file_path.clone(),
+        };
+        let backup = match self.backup && output_path == self.file_path {
+            true => Some(self.link_backup()?),
+            false => None,
+        };
+
+        let report = writer.write(&output_path)?;
+
+        Ok(WriteReport {
+            old_header_size: info.header_size,
+            backup,
+            removed_tensors: added.removed_tensors,
+            renamed_tensors: added.renamed_tensors,
+            converted_tensors: added.converted_tensors,
+            ..report
+        })
+    }
+
+    /// Cast the floating-point tensors selected by the conversion to its
+    /// target dtype, with round-to-nearest-even. The other tensors are copied
+    /// unchanged. The report lists the overflows, underflows and NaNs of each
+    /// converted tensor. The stored hashes are dropped, as for every rewrite.
+    pub fn convert_tensors<O: Into<Output>>(
+        &self,
+        output: O,
+        conversion: &DtypeConversion,
+    ) -> Result<WriteReport> {
+        let mut stats = Vec::new();
+
+        let report = self.rewrite_with(output, |info, writer| {
+            let data_start = info.data_start();
+            for (name, weight) in &info.header.weights {
+                let offset = data_start + weight.data_offsets[0] as u64;
+                let length = weight.byte_size() as u64;
+                if !conversion.applies(name, weight.dtype) {
+                    let source = TensorSource::File {
+                        path: self.file_path.clone(),
+                        offset,
+                        length,
+                    };
+                    writer.add_tensor(name, weight.dtype, weight.shape.clone(), source)?;
+                    continue;
+                }
+
+                let tensor_stats = Arc::new(Mutex::new(TensorConversion::new(
+                    name,
+                    weight.dtype,
+                    conversion.target,
+                )));
+                let elements = length * 8 / weight.dtype.bits() as u64;
+                let reader = ConvertReader::new(
+                    FileRange::new(&self.file_path, offset, length),
+                    weight.dtype,
+                    conversion.target,
+                    elements,
+                    tensor_stats.clone(),
+                );
+                let source = TensorSource::Reader {
+                    reader: Box::new(reader),
+                    length: elements * conversion.target.bits() as u64 / 8,
+                };
+                writer.add_tensor(name, conversion.target, weight.shape.clone(), source)?;
+                stats.push(tensor_stats);
+            }
+
+            Ok(WriteReport::default())
+        })?;
+
+        let converted_tensors = stats
+            .iter()
+            .map(|stats| stats.lock().expect("conversion stats lock").clone())
+            .collect();
+
+        Ok(WriteReport {
+            converted_tensors,
+            ..report
+        })
+    }
+
+    /// Drop the tensors matching any of the patterns. Fails if nothing matches.
+    pub fn remove_tensors<O: Into<Output>>(
+        &self,
+        output: O,
+        patterns: &[NamePattern],
+    ) -> Result<WriteReport> {
+        let is_removed = |name: &str| patterns.iter().any(|pattern| pattern.is_match(name));
+        self.check_any_tensor(|name| is_removed(name), "No tensors matched")?;
+
+        self.rewrite_tensors(output, |name| match is_removed(name) {
+            true => None,
+            false => Some(name.to_string()),
+        })
+    }
+
+    /// Rename the tensors by replacing all matches of the regex.
+    /// The replacement can refer to capture groups, e.g. `$1`. Fails if nothing matches.
+    pub fn rename_tensors<O: Into<Output>>(
+        &self,
+        output: O,
+        regex: &Regex,
+        replacement: &str,
+    ) -> Result<WriteReport> {
+        self.check_any_tensor(|name| regex.is_match(name), "No tensors matched")?;
+
+        self.rewrite_tensors(output, |name| {
+            Some(regex.replace_all(name, replacement).into_owned())
+        })
+    }
+
+    /// Keep only the tensors whose names start with the prefix, optionally removing the prefix.
+    /// Fails if nothing matches.
+    pub fn extract_tensors<O: Into<Output>>(
+        &self,
+        output: O,
+        prefix: &str,
+        strip_prefix: bool,
+    ) -> Result<WriteReport> {
+        self.check_any_tensor(
+            |name| name.starts_with(prefix),
+            &format!("No tensors start with {}", prefix),
+        )?;
+
+        self.rewrite_tensors(output, select_prefix(prefix, strip_prefix))
+    }
+
+    fn check_any_tensor<F: Fn(&str) -> bool>(&self, predicate: F, message: &str) -> Result<()> {
+        let header = self.parser.parse_header()?;
+        match header.weights.keys().any(|name| predicate(name)) {
+            true => Ok(()),
+            false => Err(anyhow::anyhow!("{}", message)),
+        }
+    }
+}
+
+/// Select the tensors whose names start with the prefix, optionally removing the prefix.
+fn select_prefix(prefix: &str, strip_prefix: bool) -> impl Fn(&str) -> Option<String> + '_ {
+    move |name| {
+        let rest = name.strip_prefix(prefix)?;
+        match strip_prefix {
+            true => Some(rest.to_string()),
+            false => Some(name.to_string()),
+        }
+    }
+}
+
+/// Extract the tensors whose names start with the prefix from a remote file
+/// into a local file, keeping the metadata except the stored hashes.
+///
+/// Only the byte ranges of the selected tensors are fetched, with one range
+/// request per tensor.
+pub fn extract_remote_tensors<P: AsRef<Path>>(
+    parser: &RemoteParser,
+    output_path: P,
+    prefix: &str,
+    strip_prefix: bool,
+    headroom: usize,
+    progress: Option<Progress>,
+) -> Result<WriteReport> {
+    let info = parser.parse_header_info()?;
+    if !info
+        .header
+        .weights
+        .keys()
+        .any(|name| name.starts_with(prefix))
+    {
+        return Err(anyhow::anyhow!("No tensors start with {}", prefix));
+    }
+
+    let mut writer = SafetensorsWriter::new().with_headroom(headroom);
+    if let Some(metadata) = &info.header.metadata {
+        writer = writer.with_metadata(metadata.to_map()?);
+    }
+    if let Some(progress) = progress {
+        writer = writer.with_progress(progress);
+    }
+
+    let remote_file = parser.remote_file();
+    let selection = writer.add_tensors_from(
+        &info,
+        select_prefix(prefix, strip_prefix),
+        |offset, length| TensorSource::Remote {
+            file: remote_file.clone(),
+            offset,
+            length,
+        },
+    )?;
+
+    let report = writer.write(output_path)?;
+
+    Ok(WriteReport {
+        old_header_size: info.header_size,
+        removed_tensors: selection.skipped,
+        renamed_tensors: selection.renamed,
+        ..report
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clean::CleanPreset;
+    use crate::metadata::Dtype;
+    use std::sync::{Arc, Mutex};
+
+    /// Write a safetensors file with two float32 tensors and the metadata.
+    fn write_test_file(path: &Path, metadata: &[(&str, &str)]) -> Vec<u8> {
+        let data = (0..24u8).collect::<Vec<_>>();
+        let metadata = metadata
+            .iter()
+            .map(|(key, value)| format!("\"{}\":\"{}\"", key, value))
+            .collect::<Vec<_>>()
+            .join(",");
+        let header = format!(
+            "{{\"__metadata__\":{{{}}},\"a\":{{\"dtype\":\"F32\",\"shape\":[2],\"data_offsets\":[0,8]}},\"b\":{{\"dtype\":\"F32\",\"shape\":[4],\"data_offsets\":[8,24]}}}}",
+            metadata
+        );
+
+        let mut file = File::create(path).unwrap();
+        file.write_all(&(header.len() as u64).to_le_bytes())
+            .unwrap();
+        file.write_all(header.as_bytes()).unwrap();
+        file.write_all(&data).unwrap();
+
+        data
+    }
+
+    fn read_data(path: &Path) -> Vec<u8> {
+        let header_size = LocalParser::new(path).get_header_size().unwrap();
+        std::fs::read(path).unwrap()[8 + header_size as usize..].to_vec()
+    }
+
+    fn read_metadata(path: &Path) -> BTreeMap<String, String> {
+        match LocalParser::new(path).parse_header().unwrap().metadata {
+            Some(metadata) => metadata.to_map().unwrap(),
+            None => BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_set_unset_rename_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("edit-input.safetensors");
+        let output = dir.path().join("edit-output.safetensors");
+        let data = write_test_file(
+            &input,
+            &[
+                ("format", "pt"),
+                ("modelspec.title", "old"),
+                ("license", "mit"),
+            ],
+        );
+        let safetensors = SafetensorsFile::new(&input);
+
+        safetensors
+            .set_metadata(
+                &output,
+                &[
+                    ("modelspec.title".to_string(), "new".to_string()),
+                    (
+                        "modelspec.is_negative_embedding".to_string(),
+                        "True".to_string(),
+                    ),
+                ],
+            )
+            .unwrap();
+        let metadata = read_metadata(&output);
+        assert_eq!(metadata["modelspec.title"], "new");
+        // kept as written, not normalized to a JSON boolean's spelling
+        assert_eq!(metadata["modelspec.is_negative_embedding"], "True");
+        assert_eq!(metadata["format"], "pt");
+        assert_eq!(read_data(&output), data);
+
+        safetensors
+            .unset_metadata(&output, &["license".to_string()])
+            .unwrap();
+        assert!(!read_metadata(&output).contains_key("license"));
+        assert!(safetensors
+            .unset_metadata(&output, &["missing".to_string()])
+            .is_err());
+
+        safetensors
+            .rename_metadata_key(&output, "license", "modelspec.license")
+            .unwrap();
+        let metadata = read_metadata(&output);
+        assert_eq!(metadata["modelspec.license"], "mit");
+        assert!(!metadata.contains_key("license"));
+
+        let header = LocalParser::new(&output).parse_header().unwrap();
+        assert_eq!(header.weights["b"].dtype, Dtype::Fp32);
+        assert_eq!(read_data(&output), data);
+    }
+
+    #[test]
+    fn test_clear_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("clear-input.safetensors");
+        let output = dir.path().join("clear-output.safetensors");
+        let data = write_test_file(&input, &[("format", "pt")]);
+
+        let old_header_size = LocalParser::new(&input).get_header_size().unwrap();
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let progress = {
+            let updates = updates.clone();
+            Progress::new(move |done, total| updates.lock().unwrap().push((done, total)))
+        };
+        let report = SafetensorsFile::new(&input)
+            .with_progress(progress)
+            .clear_metadata(&output)
+            .unwrap();
+
+        let header_size = LocalParser::new(&output).get_header_size().unwrap();
+        assert_eq!(header_size % 8, 0);
+        assert_eq!(report.output, output);
+        assert_eq!(report.removed_keys(), vec!["format"]);
+        assert_eq!(report.old_header_size, old_header_size);
+        assert_eq!(report.new_header_size, header_size);
+        assert_eq!(
+            report.bytes_removed(),
+            (old_header_size - header_size) as i64
+        );
+        assert_eq!(report.bytes_copied, 24);
+        assert!(report.rewritten);
+        assert_eq!(updates.lock().unwrap().last(), Some(&(24, 24)));
+        let header = std::fs::read(&output).unwrap()[8..8 + header_size as usize].to_vec();
+        assert!(!String::from_utf8(header).unwrap().contains("__metadata__"));
+        assert_eq!(read_data(&output), data);
+    }
+
+    #[test]
+    fn test_in_place_and_headroom() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in-place-input.safetensors");
+        let output = dir.path().join("in-place-output.safetensors");
+        let data = write_test_file(&input, &[("format", "pt")]);
+
+        // shorter headers fit, only the header is overwritten
+        let original = std::fs::read(&input).unwrap();
+        let safetensors = SafetensorsFile::new(&input).with_backup(true);
+        let header_size = LocalParser::new(&input).get_header_size().unwrap();
+        let report = safetensors
+            .unset_metadata(Output::InPlace, &["format".to_string()])
+            .unwrap();
+        assert!(!report.rewritten);
+        assert_eq!(report.bytes_copied, 0);
+        assert!(read_metadata(&input).is_empty());
+        assert_eq!(
+            LocalParser::new(&input).get_header_size().unwrap(),
+            header_size
+        );
+        assert_eq!(read_data(&input), data);
+
+        // only the header bytes are backed up, and can be written back
+        let header_backup = header_backup_path(&input);
+        assert_eq!(report.backup.as_ref(), Some(&header_backup));
+        let header_bytes = std::fs::read(&header_backup).unwrap();
+        assert_eq!(header_bytes, original[..8 + header_size as usize]);
+        let mut file = File::options().write(true).open(&input).unwrap();
+        file.write_all(&header_bytes).unwrap();
+        drop(file);
+        assert_eq!(std::fs::read(&input).unwrap(), original);
+        let safetensors = SafetensorsFile::new(&input);
+        safetensors
+            .unset_metadata(Output::InPlace, &["format".to_string()])
+            .unwrap();
+
+        // no room for a longer header, the file is rewritten
+        let long_title = ("modelspec.title".to_string(), "x".repeat(100));
+        SafetensorsFile::new(&input)
+            .with_backup(true)
+            .set_metadata(Output::InPlace, std::slice::from_ref(&long_title))
+            .unwrap();
+        assert!(LocalParser::new(&input).get_header_size().unwrap() > header_size);
+        assert_eq!(read_metadata(&input)["modelspec.title"], "x".repeat(100));
+        assert_eq!(read_data(&input), data);
+
+        let backup = backup_path(&input);
+        assert!(read_metadata(&backup).is_empty());
+        assert_eq!(read_data(&backup), data);
+
+        // reserve room, then edit in place
+        SafetensorsFile::new(&input)
+            .with_headroom(1024)
+            .set_metadata(&output, &[("format".to_string(), "pt".to_string())])
+            .unwrap();
+        let header_size = LocalParser::new(&output).get_header_size().unwrap();
+        assert!(header_size >= 1024);
+        assert_eq!(header_size % 8, 0);
+
+        SafetensorsFile::new(&output)
+            .set_metadata(Output::InPlace, &[long_title])
+            .unwrap();
+        assert_eq!(
+            LocalParser::new(&output).get_header_size().unwrap(),
+            header_size
+        );
+        assert_eq!(read_metadata(&output)["modelspec.title"], "x".repeat(100));
+        assert_eq!(read_data(&output), data);
+    }
+
+    #[test]
+    fn test_truncated_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("truncated-input.safetensors");
+        let output = dir.path().join("truncated-output.safetensors");
+        write_test_file(&input, &[("format", "pt")]);
+        let header_size = LocalParser::new(&input).get_header_size().unwrap();
+        // the header size claims more bytes than the file has
+        let mut file = File::options().write(true).open(&input).unwrap();
+        file.write_all(&(header_size + 100).to_le_bytes()).unwrap();
+        drop(file);
+
+        let safetensors = SafetensorsFile::new(&input);
+        let header = Header {
+            metadata: None,
+            weights: Default::default(),
+        };
+        assert!(safetensors.copy_with_header(&output, &header).is_err());
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn test_output_is_input() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("output-is-input.safetensors");
+        let data = write_test_file(&input, &[("format", "pt")]);
+
+        let safetensors = SafetensorsFile::new(&input);
+        assert!(safetensors.clear_metadata(&input).is_err());
+        assert_eq!(read_metadata(&input)["format"], "pt");
+        assert_eq!(read_data(&input), data);
+    }
+
+    #[test]
+    fn test_clean_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("clean-input.safetensors");
+        let output = dir.path().join("clean-output.safetensors");
+        let data = write_test_file(
+            &input,
+            &[
+                ("format", "pt"),
+                ("modelspec.title", "test"),
+                ("ss_network_dim", "4"),
+                ("sshs_model_hash", "abc"),
+            ],
+        );
+
+        let safetensors = SafetensorsFile::new(&input);
+        let report = safetensors
+            .clean_metadata(&output, &KeyFilter::from_preset(CleanPreset::Training))
+            .unwrap();
+
+        assert_eq!(
+            report.removed_keys(),
+            vec!["ss_network_dim", "sshs_model_hash"]
+        );
+        let metadata = read_metadata(&output);
+        assert_eq!(
+            metadata.keys().collect::<Vec<_>>(),
+            vec!["format", "modelspec.title"]
+        );
+        assert_eq!(read_data(&output), data);
+    }
+
+    #[test]
+    fn test_import_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("import-input.safetensors");
+        let output = dir.path().join("import-output.safetensors");
+        let data = write_test_file(&input, &[("format", "pt"), ("license", "mit")]);
+
+        let entries = BTreeMap::from([("license".to_string(), "apache-2.0".to_string())]);
+        let safetensors = SafetensorsFile::new(&input);
+
+        safetensors
+            .import_metadata(&output, entries.clone(), ImportMode::Merge)
+            .unwrap();
+        let metadata = read_metadata(&output);
+        assert_eq!(metadata["format"], "pt");
+        assert_eq!(metadata["license"], "apache-2.0");
+
+        safetensors
+            .import_metadata(&output, entries.clone(), ImportMode::Replace)
+            .unwrap();
+        assert_eq!(read_metadata(&output), entries);
+        assert_eq!(read_data(&output), data);
+    }
+
+    #[test]
+    fn test_rewrite_tensors() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("tensors-input.safetensors");
+        let output = dir.path().join("tensors-output.safetensors");
+        let data = write_test_file(
+            &input,
+            &[("format", "pt"), ("modelspec.hash_sha256", "0x1234")],
+        );
+        let safetensors = SafetensorsFile::new(&input);
+
+        let report = safetensors
+            .remove_tensors(&output, &[NamePattern::new("a").unwrap()])
+            .unwrap();
+        assert_eq!(report.removed_tensors, vec!["a"]);
+        // the hash of the data is no longer valid
+        assert_eq!(report.removed_keys(), vec!["modelspec.hash_sha256"]);
+        assert!(!read_metadata(&output).contains_key("modelspec.hash_sha256"));
+        assert_eq!(report.bytes_copied, 16);
+        let header = LocalParser::new(&output).parse_header().unwrap();
+        assert_eq!(header.weights.keys().collect::<Vec<_>>(), vec!["b"]);
+        assert_eq!(header.weights["b"].data_offsets, [0, 16]);
+        assert_eq!(read_data(&output), data[8..]);
+        assert_eq!(read_metadata(&output)["format"], "pt");
+
+        let report = safetensors
+            .rename_tensors(&output, &Regex::new("^(a|b)$").unwrap(), "model.$1")
+            .unwrap();
+        assert_eq!(report.renamed_tensors.len(), 2);
+        let header = LocalParser::new(&output).parse_header().unwrap();
+        assert_eq!(
+            header.weights.keys().collect::<Vec<_>>(),
+            vec!["model.a", "model.b"]
+        );
+        assert_eq!(read_data(&output), data);
+
+        // collisions are rejected
+        assert!(safetensors
+            .rename_tensors(&output, &Regex::new(".*").unwrap(), "x")
+            .is_err());
+        assert!(safetensors
+            .remove_tensors(&output, &[NamePattern::new("missing").unwrap()])
+            .is_err());
+
+        SafetensorsFile::new(&output)
+            .extract_tensors(Output::InPlace, "model.", true)
+            .unwrap();
+        let header = LocalParser::new(&output).parse_header().unwrap();
+        assert_eq!(header.weights.keys().collect::<Vec<_>>(), vec!["a", "b"]);
+        assert_eq!(read_data(&output), data);
+    }
+
+    #[test]
+    fn test_convert_tensors() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("convert-input.safetensors");
+        let output = dir.path().join("convert-output.safetensors");
+        let data = write_test_file(
+            &input,
+            &[
+                ("format", "pt"),
+                ("sshs_model_hash", "1234"),
+                ("sshs_legacy_hash", "5678"),
+            ],
+        );
+
+        let conversion = DtypeConversion::new(Dtype::Bf16)
+            .unwrap()
+            .with_exclude(vec![NamePattern::new("a").unwrap()]);
+        let report = SafetensorsFile::new(&input)
+            .convert_tensors(&output, &conversion)
+            .unwrap();
+        assert_eq!(report.converted_tensors.len(), 1);
+        assert_eq!(report.converted_tensors[0].name, "b");
+        assert_eq!(report.converted_tensors[0].elements, 4);
+        assert!(!report.converted_tensors[0].has_issues());
+
+        let header = LocalParser::new(&output).parse_header().unwrap();
+        assert_eq!(header.weights["a"].dtype, Dtype::Fp32);
+        assert_eq!(header.weights["b"].dtype, Dtype::Bf16);
+        assert_eq!(header.weights["b"].shape, vec![4]);
+        // the upper halves of the float32 values, which are rounded down
+        let mut expected = data[..8].to_vec();
+        expected.extend([10, 11, 14, 15, 18, 19, 22, 23]);
+        assert_eq!(read_data(&output), expected);
+        assert_eq!(
+            read_metadata(&output).keys().collect::<Vec<_>>(),
+            vec!["format"]
+        );
+        assert_eq!(
+            report.removed_keys(),
+            vec!["sshs_legacy_hash", "sshs_model_hash"]
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_remote {
+    use super::*;
+    use crate::hash::HashKind;
+    use crate::validate::validate;
+    use hf_hub::RepoType;
+
+    #[test]
+    fn test_extract_remote_tensors() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("final_layer_norm.safetensors");
+        let parser = RemoteParser::from_hub(
+            "runwayml/stable-diffusion-v1-5",
+            RepoType::Model,
+            "text_encoder/model.fp16.safetensors",
+            &None,
+        );
+
+        let report = extract_remote_tensors(
+            &parser,
+            &path,
+            "text_model.final_layer_norm.",
+            true,
+            0,
+            None,
+        )
+        .unwrap();
+        assert_eq!(report.tensors_written, 2);
+
+        let info = LocalParser::new(&path).parse_header_info().unwrap();
+        assert_eq!(validate(&info), vec![]);
+        let metadata = info
+            .header
+            .metadata
+            .as_ref()
+            .map(|metadata| metadata.to_map().unwrap());
+        assert!(HashKind::metadata_keys()
+            .all(|key| metadata.as_ref().is_none_or(|map| !map.contains_key(key))));
+        let mut names = info.header.weights.keys().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["bias", "weight"]);
     }
 }
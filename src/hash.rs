@@ -0,0 +1,854 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use anyhow::Result;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::atomic::AtomicFile;
+use crate::file::HEADER_ALIGNMENT;
+use crate::named::named_enum;
+use crate::parser::{HeaderInfo, LocalParser, MetadataParser};
+use crate::progress::Progress;
+
+/// The metadata key of the ModelSpec hash.
+pub const MODELSPEC_HASH_KEY: &str = "modelspec.hash_sha256";
+
+/// The name of the hash cache written in the directory of the hashed files.
+pub const HASH_CACHE_FILE: &str = ".safemetadata-hashes.json";
+
+/// The size of the chunks read and hashed at once.
+const HASH_CHUNK_SIZE: usize = 16 << 20;
+
+/// The window of the legacy hashes: 64 KiB at an offset of 1 MiB.
+const LEGACY_OFFSET: u64 = 0x100000;
+const LEGACY_LENGTH: u64 = 0x10000;
+
+/// The prefix of the metadata keys that kohya-ss keeps when computing its hashes.
+const TRAINING_KEY_PREFIX: &str = "ss_";
+
+/// The hash schemes used to identify checkpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HashKind {
+    /// `modelspec.hash_sha256`: sha256 of the data section, `0x` + lowercase hex.
+    ModelSpec,
+    /// The AutoV2 hash of A1111 and Civitai: the first 10 hex digits of the
+    /// sha256 of the whole file.
+    AutoV2,
+    /// The legacy `model_hash` of A1111: the first 8 hex digits of the sha256
+    /// of 64 KiB at an offset of 1 MiB of the file.
+    A1111Legacy,
+    /// kohya-ss `sshs_model_hash`: sha256 of the data section.
+    SshsModel,
+    /// kohya-ss `sshs_legacy_hash`: like the A1111 legacy hash, but of the
+    /// file as kohya-ss serializes it, with only the `ss_*` metadata.
+    SshsLegacy,
+    /// BLAKE3 of the data section, `0x` + lowercase hex. Much faster than
+    /// sha256 on large files, as it is computed on all cores.
+    Blake3,
+}
+
+named_enum!(HashKind, "hash", {
+    ModelSpec => "modelspec",
+    AutoV2 => "autov2",
+    A1111Legacy => "a1111-legacy",
+    SshsModel => "sshs-model",
+    SshsLegacy => "sshs-legacy",
+    Blake3 => "blake3",
+});
+
+impl HashKind {
+    /// The metadata key where the hash is stored, if any.
+    pub fn metadata_key(&self) -> Option<&'static str> {
+        match self {
+            HashKind::ModelSpec => Some(MODELSPEC_HASH_KEY),
+            HashKind::SshsModel => Some("sshs_model_hash"),
+            HashKind::SshsLegacy => Some("sshs_legacy_hash"),
+            HashKind::AutoV2 | HashKind::A1111Legacy | HashKind::Blake3 => None,
+        }
+    }
+
+    /// The metadata keys where hashes of the tensor data are stored.
+    ///
+    /// They are only valid for the data they were computed on, so they are
+    /// dropped whenever the tensors are rewritten.
+    pub fn metadata_keys() -> impl Iterator<Item = &'static str> {
+        HashKind::ALL.iter().filter_map(|kind| kind.metadata_key())
+    }
+
+    /// Format the digest as the scheme does.
+    fn format(&self, digest: &[u8]) -> String {
+        let hex = hex::encode(digest);
+        match self {
+            HashKind::ModelSpec | HashKind::Blake3 => format!("0x{}", hex),
+            HashKind::AutoV2 => hex[..10].to_string(),
+            HashKind::A1111Legacy | HashKind::SshsLegacy => hex[..8].to_string(),
+            HashKind::SshsModel => hex,
+        }
+    }
+
+    /// What the hash is computed over.
+    fn target(&self) -> Target {
+        match self {
+            HashKind::ModelSpec | HashKind::SshsModel => Target::Data,
+            HashKind::AutoV2 => Target::File,
+            HashKind::A1111Legacy => Target::Legacy,
+            HashKind::SshsLegacy => Target::TrainingLegacy,
+            HashKind::Blake3 => Target::DataBlake3,
+        }
+    }
+}
+
+/// Normalize a stored hash for comparison: lowercase, without the `0x` prefix.
+pub fn normalize_hash(hash: &str) -> String {
+    let hash = hash.trim().to_lowercase();
+    match hash.strip_prefix("0x") {
+        Some(hash) => hash.to_string(),
+        None => hash,
+    }
+}
+
+/// A computed hash and the value stored in the metadata, if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileHash {
+    pub kind: HashKind,
+    pub computed: String,
+    pub stored: Option<String>,
+}
+
+impl FileHash {
+    /// Whether the stored hash matches, or `None` if there is none.
+    pub fn matches(&self) -> Option<bool> {
+        self.stored
+            .as_ref()
+            .map(|stored| normalize_hash(stored) == normalize_hash(&self.computed))
+    }
+}
+
+/// The hash functions.
+enum Algorithm {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Algorithm {
+    fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Algorithm::Sha256(hasher) => hasher.update(bytes),
+            // BLAKE3 hashes the chunks of the buffer on all cores
+            Algorithm::Blake3(hasher) => {
+                hasher.update_rayon(bytes);
+            }
+        }
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        match self {
+            Algorithm::Sha256(hasher) => hasher.finalize().to_vec(),
+            Algorithm::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+        }
+    }
+}
+
+/// What a hasher hashes. Several hash kinds can share a hasher.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    File,
+    Data,
+    DataBlake3,
+    Legacy,
+    TrainingLegacy,
+}
+
+/// Hashes the bytes of a byte range of the file.
+struct RangeHasher {
+    target: Target,
+    start: u64,
+    end: u64,
+    algorithm: Algorithm,
+}
+
+impl RangeHasher {
+    fn new(target: Target, start: u64, end: u64) -> Self {
+        let algorithm = match target {
+            Target::DataBlake3 => Algorithm::Blake3(Box::default()),
+            _ => Algorithm::Sha256(Sha256::new()),
+        };
+
+        Self {
+            target,
+            start,
+            end,
+            algorithm,
+        }
+    }
+
+    /// Hash the part of the chunk at `position` that is in the range.
+    fn update(&mut self, position: u64, chunk: &[u8]) {
+        let chunk_end = position + chunk.len() as u64;
+        let start = self.start.max(position);
+        let end = self.end.min(chunk_end);
+        if start < end {
+            self.algorithm
+                .update(&chunk[(start - position) as usize..(end - position) as usize]);
+        }
+    }
+}
+
+/// The beginning of the file as kohya-ss serializes it to compute its hashes,
+/// i.e. with only the `ss_*` metadata: the header size and the header.
+///
+/// The reference serializer writes `__metadata__` first and the tensors in
+/// the order of their data (by dtype, then by name), not sorted by name.
+/// Its metadata keys are in hash map order, which cannot be reproduced, so
+/// with several `ss_*` keys the hash only matches when the header ends
+/// before the hashed window, as it does for all but the largest headers.
+fn training_prefix(info: &HeaderInfo) -> Result<Vec<u8>> {
+    let map = match &info.header.metadata {
+        Some(metadata) => metadata.to_map()?,
+        None => BTreeMap::new(),
+    };
+    let training = map
+        .into_iter()
+        .filter(|(key, _)| key.starts_with(TRAINING_KEY_PREFIX))
+        .collect::<BTreeMap<_, _>>();
+    let mut weights = info.header.weights.iter().collect::<Vec<_>>();
+    weights.sort_by_key(|(_, weight)| weight.data_offsets);
+
+    let mut header_buffer = b"{\"__metadata__\":".to_vec();
+    serde_json::to_writer(&mut header_buffer, &training)?;
+    for (name, weight) in weights {
+        header_buffer.push(b',');
+        serde_json::to_writer(&mut header_buffer, name)?;
+        header_buffer.push(b':');
+        serde_json::to_writer(&mut header_buffer, weight)?;
+    }
+    header_buffer.push(b'}');
+    let padded_size = header_buffer.len().div_ceil(HEADER_ALIGNMENT) * HEADER_ALIGNMENT;
+    header_buffer.resize(padded_size, b' ');
+
+    let mut prefix = (header_buffer.len() as u64).to_le_bytes().to_vec();
+    prefix.extend(header_buffer);
+
+    Ok(prefix)
+}
+
+/// The kohya-ss legacy hasher. The part of the window in the kohya-ss header
+/// is hashed right away; the rest is mapped to the data section of the file.
+fn training_legacy_hasher(info: &HeaderInfo) -> Result<RangeHasher> {
+    let prefix = training_prefix(info)?;
+    let mut hasher = RangeHasher::new(
+        Target::TrainingLegacy,
+        LEGACY_OFFSET,
+        LEGACY_OFFSET + LEGACY_LENGTH,
+    );
+    hasher.update(0, &prefix);
+
+    let to_file = |position: u64| position.max(prefix.len() as u64) - prefix.len() as u64;
+    hasher.start = info.data_start() + to_file(hasher.start);
+    hasher.end = info.data_start() + to_file(hasher.end);
+
+    Ok(hasher)
+}
+
+/// Read `total` bytes of the file from the current position, which is
+/// `start`, and feed them to all the hashers. The hashers run in parallel,
+/// while the next chunk is read.
+fn hash_file(
+    file: &mut File,
+    start: u64,
+    total: u64,
+    hashers: &mut [RangeHasher],
+    progress: Option<&Progress>,
+) -> Result<()> {
+    let read_chunk = |file: &mut File, remaining: u64| -> io::Result<Vec<u8>> {
+        let mut buffer = vec![0; HASH_CHUNK_SIZE.min(remaining as usize)];
+        file.read_exact(&mut buffer)?;
+        Ok(buffer)
+    };
+
+    if let Some(progress) = progress {
+        progress.update(0, total);
+    }
+    let mut done = 0;
+    let mut chunk = read_chunk(file, total)?;
+    while !chunk.is_empty() {
+        let position = start + done;
+        let remaining = total - done - chunk.len() as u64;
+        let (next, ()) = rayon::join(
+            || read_chunk(file, remaining),
+            || {
+                hashers
+                    .par_iter_mut()
+                    .for_each(|hasher| hasher.update(position, &chunk))
+            },
+        );
+
+        done += chunk.len() as u64;
+        if let Some(progress) = progress {
+            progress.update(done, total);
+        }
+        chunk = next?;
+    }
+
+    Ok(())
+}
+
+/// The hashes stored in the metadata.
+fn stored_hashes(info: &HeaderInfo) -> Result<BTreeMap<String, String>> {
+    match &info.header.metadata {
+        Some(metadata) => metadata.to_map(),
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+/// Compute the hashes of a file, reading it once, and compare them with the
+/// values stored in the metadata.
+///
+/// Only the part of the file needed by the requested hashes is read, e.g. only
+/// the data section for [`HashKind::ModelSpec`]. Hashes that need the whole
+/// file, like [`HashKind::AutoV2`], read the header too.
+pub fn compute_hashes<P: AsRef<Path>>(
+    path: P,
+    kinds: &[HashKind],
+    progress: Option<&Progress>,
+) -> Result<Vec<FileHash>> {
+    let info = LocalParser::new(path.as_ref()).parse_header_info()?;
+    hash_with_header(path.as_ref(), &info, kinds, progress)
+}
+
+/// Compute the hashes of the file, whose header was already parsed.
+fn hash_with_header(
+    path: &Path,
+    info: &HeaderInfo,
+    kinds: &[HashKind],
+    progress: Option<&Progress>,
+) -> Result<Vec<FileHash>> {
+    let data_start = info.data_start();
+
+    let mut kinds = kinds.to_vec();
+    kinds.sort();
+    kinds.dedup();
+
+    let mut hashers: Vec<RangeHasher> = Vec::new();
+    for kind in &kinds {
+        let target = kind.target();
+        if hashers.iter().any(|hasher| hasher.target == target) {
+            continue;
+        }
+        let hasher = match target {
+            Target::File => RangeHasher::new(target, 0, u64::MAX),
+            Target::Data | Target::DataBlake3 => RangeHasher::new(target, data_start, u64::MAX),
+            Target::Legacy => {
+                RangeHasher::new(target, LEGACY_OFFSET, LEGACY_OFFSET + LEGACY_LENGTH)
+            }
+            Target::TrainingLegacy => training_legacy_hasher(info)?,
+        };
+        hashers.push(hasher);
+    }
+
+    // skip the part of the file that is not hashed
+    let start = hashers
+        .iter()
+        .map(|hasher| hasher.start)
+        .min()
+        .unwrap_or(data_start)
+        .min(info.file_size);
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(start))?;
+    hash_file(
+        &mut file,
+        start,
+        info.file_size - start,
+        &mut hashers,
+        progress,
+    )?;
+
+    let stored = stored_hashes(info)?;
+    let digests = hashers
+        .into_iter()
+        .map(|hasher| (hasher.target, hasher.algorithm.finalize()))
+        .collect::<Vec<_>>();
+    let hashes = kinds
+        .into_iter()
+        .map(|kind| {
+            let (_, digest) = digests
+                .iter()
+                .find(|(target, _)| *target == kind.target())
+                .expect("the hasher of a requested hash");
+            FileHash {
+                kind,
+                computed: kind.format(digest),
+                stored: kind.metadata_key().and_then(|key| stored.get(key).cloned()),
+            }
+        })
+        .collect();
+
+    Ok(hashes)
+}
+
+/// Identifies a version of a file in the hash cache.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CacheKey {
+    size: u64,
+    modified_secs: u64,
+    modified_nanos: u32,
+    inode: u64,
+}
+
+impl CacheKey {
+    fn of(path: &Path) -> Result<Self> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?;
+        #[cfg(unix)]
+        let inode = {
+            use std::os::unix::fs::MetadataExt;
+            metadata.ino()
+        };
+        #[cfg(not(unix))]
+        let inode = 0;
+
+        Ok(Self {
+            size: metadata.len(),
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+            inode,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    #[serde(flatten)]
+    key: CacheKey,
+    /// The computed hashes by [`HashKind`] name.
+    hashes: BTreeMap<String, String>,
+}
+
+/// The hashes of the files of a directory, stored in [`HASH_CACHE_FILE`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct HashCache {
+    /// The entries by file name.
+    files: BTreeMap<String, CacheEntry>,
+}
+
+impl HashCache {
+    /// Load the cache of the directory. A missing or unreadable cache is empty.
+    fn load(dir: &Path) -> Self {
+        fs::read_to_string(dir.join(HASH_CACHE_FILE))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, dir: &Path) -> Result<()> {
+        let mut file = AtomicFile::create(dir.join(HASH_CACHE_FILE))?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        file.commit()
+    }
+
+    /// Store the entry of a file in the cache of the directory.
+    ///
+    /// The cache is read again right before it is saved, so that the entries
+    /// another run stored in the meantime, e.g. while this one was hashing a
+    /// large file, are kept.
+    fn update(dir: &Path, name: String, entry: CacheEntry) -> Result<()> {
+        let mut cache = HashCache::load(dir);
+        cache.files.insert(name, entry);
+        cache.save(dir)
+    }
+}
+
+/// The directory of the cache of a file, and the name of its entry.
+fn cache_location(path: &Path) -> Result<(&Path, String)> {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| anyhow::anyhow!("Invalid path: {}", path.display()))?;
+
+    Ok((dir, name))
+}
+
+/// Like [`compute_hashes`], but reuses the hashes cached in [`HASH_CACHE_FILE`]
+/// next to the file.
+///
+/// A cached hash is used if the size, modification time and inode of the
+/// file have not changed. Missing hashes are computed and added to the cache.
+/// The cache is best effort: if it cannot be written, e.g. in a read-only
+/// directory, the hashes are still returned.
+pub fn compute_hashes_cached<P: AsRef<Path>>(
+    path: P,
+    kinds: &[HashKind],
+    progress: Option<&Progress>,
+) -> Result<Vec<FileHash>> {
+    let path = path.as_ref();
+    let (dir, name) = cache_location(path)?;
+
+    let key = CacheKey::of(path)?;
+    let mut cached = match HashCache::load(dir).files.remove(&name) {
+        Some(entry) if entry.key == key => entry.hashes,
+        _ => BTreeMap::new(),
+    };
+
+    let info = LocalParser::new(path).parse_header_info()?;
+    let missing = kinds
+        .iter()
+        .copied()
+        .filter(|kind| !cached.contains_key(&kind.to_string()))
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        for hash in hash_with_header(path, &info, &missing, progress)? {
+            cached.insert(hash.kind.to_string(), hash.computed);
+        }
+        let entry = CacheEntry {
+            key,
+            hashes: cached.clone(),
+        };
+        // the hashes are valid even if they cannot be cached
+        let _ = HashCache::update(dir, name, entry);
+    }
+
+    let stored = stored_hashes(&info)?;
+    let mut kinds = kinds.to_vec();
+    kinds.sort();
+    kinds.dedup();
+    let hashes = kinds
+        .into_iter()
+        .map(|kind| FileHash {
+            kind,
+            computed: cached[&kind.to_string()].clone(),
+            stored: kind.metadata_key().and_then(|key| stored.get(key).cloned()),
+        })
+        .collect();
+
+    Ok(hashes)
+}
+
+/// Update the cache entry of a file after only its metadata was rewritten,
+/// e.g. to store the computed modelspec hash. Call it right after the write,
+/// before anything else can change the file.
+///
+/// The hashes of the data section are still valid and are kept under the new
+/// size and modification time. The others cover the header, so they are dropped.
+pub fn refresh_cached_hashes<P: AsRef<Path>>(path: P) -> Result<()> {
+    let path = path.as_ref();
+    let (dir, name) = cache_location(path)?;
+    let Some(entry) = HashCache::load(dir).files.remove(&name) else {
+        return Ok(());
+    };
+
+    let hashes = entry
+        .hashes
+        .into_iter()
+        .filter(|(kind, _)| {
+            kind.parse::<HashKind>()
+                .is_ok_and(|kind| matches!(kind.target(), Target::Data | Target::DataBlake3))
+        })
+        .collect();
+    let entry = CacheEntry {
+        key: CacheKey::of(path)?,
+        hashes,
+    };
+
+    HashCache::update(dir, name, entry)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::file::{Output, SafetensorsFile};
+    use crate::metadata::Dtype;
+    use crate::writer::fixtures::write_model;
+    use crate::writer::SafetensorsWriter;
+
+    fn sha256_hex(bytes: &[u8]) -> String {
+        hex::encode(Sha256::digest(bytes))
+    }
+
+    fn compute(path: &Path, kind: HashKind) -> FileHash {
+        compute_hashes(path, &[kind], None).unwrap().remove(0)
+    }
+
+    #[test]
+    fn test_modelspec_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.safetensors");
+        write_model(&path, &[("weight", Dtype::Uint8, b"abc")]);
+
+        // sha256("abc")
+        let expected = "0xba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let hash = compute(&path, HashKind::ModelSpec);
+        assert_eq!(hash.computed, expected);
+        assert_eq!(hash.matches(), None);
+
+        let safetensors = SafetensorsFile::new(&path);
+        safetensors
+            .set_metadata(
+                path.with_extension("tmp"),
+                &[(MODELSPEC_HASH_KEY.to_string(), "0xDEADBEEF".to_string())],
+            )
+            .unwrap();
+        let hash = compute(&path.with_extension("tmp"), HashKind::ModelSpec);
+        assert_eq!(hash.matches(), Some(false));
+
+        // the hash does not change when the header does
+        safetensors
+            .set_metadata(
+                Output::InPlace,
+                &[(MODELSPEC_HASH_KEY.to_string(), expected.to_uppercase())],
+            )
+            .unwrap();
+        let hash = compute(&path, HashKind::ModelSpec);
+        assert_eq!(hash.computed, expected);
+        assert_eq!(hash.matches(), Some(true));
+    }
+
+    #[test]
+    fn test_community_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lora.safetensors");
+        let training_path = dir.path().join("training.safetensors");
+        let data = (0..0x120000u32)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        let write = |path: &Path, metadata: &[(&str, &str)]| {
+            let mut writer = SafetensorsWriter::new().with_metadata(
+                metadata
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+            );
+            writer
+                .add_tensor(
+                    "weight",
+                    Dtype::Uint8,
+                    vec![data.len() as i64],
+                    data.clone().into(),
+                )
+                .unwrap();
+            writer.write(path).unwrap();
+        };
+        // as kohya-ss computes its hashes, with only the training metadata
+        write(&training_path, &[("ss_network_dim", "4")]);
+        let training_file = std::fs::read(&training_path).unwrap();
+        let legacy = sha256_hex(&training_file[0x100000..0x110000])[..8].to_string();
+        write(
+            &path,
+            &[
+                ("ss_network_dim", "4"),
+                (
+                    "modelspec.title",
+                    "a much longer header than the training one",
+                ),
+            ],
+        );
+        // the writer drops stored hashes, they are added to the header
+        SafetensorsFile::new(&path)
+            .set_metadata(
+                Output::InPlace,
+                &[
+                    ("sshs_legacy_hash".to_string(), legacy.clone()),
+                    ("sshs_model_hash".to_string(), "0000".to_string()),
+                ],
+            )
+            .unwrap();
+        let file = std::fs::read(&path).unwrap();
+
+        let hashes = compute_hashes(&path, &HashKind::ALL, None).unwrap();
+        let hash = |kind| hashes.iter().find(|hash| hash.kind == kind).unwrap();
+
+        assert_eq!(hash(HashKind::AutoV2).computed, sha256_hex(&file)[..10]);
+        assert_eq!(hash(HashKind::AutoV2).matches(), None);
+        assert_eq!(
+            hash(HashKind::A1111Legacy).computed,
+            sha256_hex(&file[0x100000..0x110000])[..8]
+        );
+        assert_eq!(hash(HashKind::SshsModel).computed, sha256_hex(&data));
+        assert_eq!(hash(HashKind::SshsModel).matches(), Some(false));
+        assert_eq!(hash(HashKind::SshsLegacy).computed, legacy);
+        assert_eq!(hash(HashKind::SshsLegacy).matches(), Some(true));
+        assert_ne!(
+            hash(HashKind::SshsLegacy).computed,
+            hash(HashKind::A1111Legacy).computed
+        );
+        assert_eq!(
+            hash(HashKind::ModelSpec).computed,
+            format!("0x{}", sha256_hex(&data))
+        );
+
+        assert_eq!(
+            hash(HashKind::Blake3).computed,
+            format!("0x{}", blake3::hash(&data).to_hex())
+        );
+        // BLAKE3 has no ModelSpec field, so it is not stored in the metadata
+        assert_eq!(hash(HashKind::Blake3).matches(), None);
+        assert!(HashKind::metadata_keys()
+            .filter(|key| key.starts_with("modelspec."))
+            .eq([MODELSPEC_HASH_KEY]));
+
+        // only the requested hashes, from a part of the file
+        let hashes = compute_hashes(&path, &[HashKind::A1111Legacy], None).unwrap();
+        assert_eq!(hashes, vec![hash(HashKind::A1111Legacy).clone()]);
+    }
+
+    /// A file as the reference serializer writes it: `__metadata__` first,
+    /// then the tensors in the given order, which must be the data order.
+    fn reference_file(metadata: &[(&str, &str)], tensors: &[(String, &str, Vec<u8>)]) -> Vec<u8> {
+        let metadata = metadata
+            .iter()
+            .map(|(key, value)| format!("\"{}\":\"{}\"", key, value))
+            .collect::<Vec<_>>();
+        let mut entries = vec![format!("\"__metadata__\":{{{}}}", metadata.join(","))];
+        let mut data: Vec<u8> = Vec::new();
+        for (name, dtype, bytes) in tensors {
+            entries.push(format!(
+                "\"{}\":{{\"dtype\":\"{}\",\"shape\":[{}],\"data_offsets\":[{},{}]}}",
+                name,
+                dtype,
+                bytes.len() / 2,
+                data.len(),
+                data.len() + bytes.len()
+            ));
+            data.extend(bytes);
+        }
+        let mut header = format!("{{{}}}", entries.join(",")).into_bytes();
+        header.resize(header.len().div_ceil(8) * 8, b' ');
+
+        let mut file = (header.len() as u64).to_le_bytes().to_vec();
+        file.extend(header);
+        file.extend(data);
+        file
+    }
+
+    #[test]
+    fn test_sshs_legacy_hash_data_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lora.safetensors");
+        // mixed dtypes, so that the data order differs from the name order,
+        // and a header that reaches into the hashed window
+        let tensor = |index: usize, dtype| {
+            let name = format!("lora_unet.{:05}.alpha", index);
+            (name, dtype, (index as u16).to_le_bytes().to_vec())
+        };
+        let tensors = (0..20000)
+            .step_by(2)
+            .map(|index| tensor(index, "BF16"))
+            .chain((1..20000).step_by(2).map(|index| tensor(index, "F16")))
+            .collect::<Vec<_>>();
+
+        let training = reference_file(&[("ss_network_dim", "4")], &tensors);
+        assert!(training.len() as u64 > LEGACY_OFFSET + LEGACY_LENGTH);
+        let expected = sha256_hex(&training[0x100000..0x110000])[..8].to_string();
+        std::fs::write(
+            &path,
+            reference_file(
+                &[("modelspec.title", "LoRA"), ("ss_network_dim", "4")],
+                &tensors,
+            ),
+        )
+        .unwrap();
+
+        assert_eq!(compute(&path, HashKind::SshsLegacy).computed, expected);
+    }
+
+    #[test]
+    fn test_hash_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.safetensors");
+        write_model(&path, &[("weight", Dtype::Uint8, b"abc")]);
+
+        let kinds = [HashKind::ModelSpec, HashKind::Blake3];
+        let hashes = compute_hashes_cached(&path, &kinds, None).unwrap();
+        assert_eq!(hashes, compute_hashes(&path, &kinds, None).unwrap());
+        let cache = HashCache::load(dir.path());
+        assert_eq!(cache.files["model.safetensors"].hashes.len(), 2);
+
+        // a cached hash is reused as long as the file is unchanged
+        let mut cache = cache;
+        let entry = cache.files.get_mut("model.safetensors").unwrap();
+        entry
+            .hashes
+            .insert("blake3".to_string(), "0xcached".to_string());
+        cache.save(dir.path()).unwrap();
+        let hashes = compute_hashes_cached(&path, &[HashKind::Blake3], None).unwrap();
+        assert_eq!(hashes[0].computed, "0xcached");
+
+        // and recomputed once the file changes
+        write_model(&path, &[("weight", Dtype::Uint8, b"abd")]);
+        let hashes = compute_hashes_cached(&path, &kinds, None).unwrap();
+        assert_eq!(hashes, compute_hashes(&path, &kinds, None).unwrap());
+        assert_ne!(hashes[1].computed, "0xcached");
+    }
+
+    #[test]
+    fn test_refresh_cached_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.safetensors");
+        write_model(&path, &[("weight", Dtype::Uint8, b"abc")]);
+
+        let kinds = [HashKind::ModelSpec, HashKind::AutoV2];
+        let hashes = compute_hashes_cached(&path, &kinds, None).unwrap();
+        SafetensorsFile::new(&path)
+            .set_metadata(
+                Output::InPlace,
+                &[(MODELSPEC_HASH_KEY.to_string(), hashes[0].computed.clone())],
+            )
+            .unwrap();
+
+        // the write made the entry stale
+        let key = CacheKey::of(&path).unwrap();
+        assert_ne!(
+            HashCache::load(dir.path()).files["model.safetensors"].key,
+            key
+        );
+
+        refresh_cached_hashes(&path).unwrap();
+        let entry = &HashCache::load(dir.path()).files["model.safetensors"];
+        assert_eq!(entry.key, key);
+        // the whole file hash covers the changed header
+        assert_eq!(entry.hashes.keys().collect::<Vec<_>>(), ["modelspec"]);
+        assert_eq!(
+            compute_hashes_cached(&path, &kinds, None).unwrap(),
+            compute_hashes(&path, &kinds, None).unwrap()
+        );
+
+        // files without an entry are left alone
+        refresh_cached_hashes(dir.path().join("other.safetensors")).unwrap();
+    }
+
+    #[test]
+    fn test_hash_cache_concurrent_update() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a.safetensors", "b.safetensors"] {
+            write_model(
+                &dir.path().join(name),
+                &[("weight", Dtype::Uint8, &name.as_bytes()[..1])],
+            );
+        }
+
+        // another run loaded the cache before this one stored its entry
+        let stale = HashCache::load(dir.path());
+        assert!(stale.files.is_empty());
+        compute_hashes_cached(dir.path().join("b.safetensors"), &[HashKind::Blake3], None).unwrap();
+        let path = dir.path().join("a.safetensors");
+        let entry = CacheEntry {
+            key: CacheKey::of(&path).unwrap(),
+            hashes: BTreeMap::from([("blake3".to_string(), "0xa".to_string())]),
+        };
+        HashCache::update(dir.path(), "a.safetensors".to_string(), entry).unwrap();
+
+        let cache = HashCache::load(dir.path());
+        assert_eq!(
+            cache.files.keys().collect::<Vec<_>>(),
+            ["a.safetensors", "b.safetensors"]
+        );
+    }
+}
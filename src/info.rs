@@ -0,0 +1,71 @@
+use std::collections::BTreeMap;
+
+use safemetadata::metadata::{Dtype, ModelSpec, TensorFormart};
+use safemetadata::parser::HeaderInfo;
+use safemetadata::validate::{validate, ValidationWarning};
+
+use crate::params::{ParamBreakdown, ParamCount};
+
+/// The overview of a safetensors file.
+#[derive(Debug, Clone)]
+pub struct Summary {
+    pub file_size: u64,
+    pub header_size: u64,
+    pub tensors: usize,
+    pub params: i64,
+    pub by_dtype: BTreeMap<Dtype, ParamCount>,
+    pub format: Option<TensorFormart>,
+    /// The modelspec, if the file declares `modelspec.sai_model_spec`.
+    pub model_spec: Option<ModelSpec>,
+    /// The number of metadata keys other than `format` and modelspec.
+    pub custom_keys: usize,
+    pub warnings: Vec<ValidationWarning>,
+}
+
+impl Summary {
+    pub fn new(info: &HeaderInfo) -> Self {
+        let breakdown = ParamBreakdown::new(&info.header.weights, 1);
+
+        let (format, model_spec, custom_keys) = match &info.header.metadata {
+            Some(metadata) => (
+                metadata.format.clone(),
+                metadata
+                    .model_spec
+                    .clone()
+                    .filter(|spec| spec.sai_model_spec.is_some()),
+                metadata.others.len(),
+            ),
+            None => (None, None, 0),
+        };
+
+        Self {
+            file_size: info.file_size,
+            header_size: info.header_size,
+            tensors: info.header.weights.len(),
+            params: breakdown.total.params,
+            by_dtype: breakdown.by_dtype,
+            format,
+            model_spec,
+            custom_keys,
+            warnings: validate(info),
+        }
+    }
+
+    /// The dtypes and their share of the parameters, e.g. `bfloat16 (99.98%), float32 (0.02%)`
+    pub fn dtype_distribution(&self) -> String {
+        let mut dtypes = self.by_dtype.iter().collect::<Vec<_>>();
+        dtypes.sort_by_key(|(_, count)| std::cmp::Reverse(count.params));
+
+        dtypes
+            .iter()
+            .map(|(dtype, count)| {
+                let percent = match self.params {
+                    0 => 0.,
+                    total => count.params as f64 / total as f64 * 100.,
+                };
+                format!("{} ({:.2}%)", dtype, percent)
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
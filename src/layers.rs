@@ -0,0 +1,221 @@
+use std::cmp::Reverse;
+
+use clap::ValueEnum;
+use safemetadata::metadata::{Dtype, Weight, Weights};
+use safemetadata::pattern::NamePattern;
+
+use crate::utils::natural_cmp;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LayerSort {
+    /// Lexicographic order of the tensor names
+    Name,
+    /// Tensor names with numbers compared by value (`layers.2` < `layers.10`)
+    Natural,
+    /// Byte size, largest first
+    Size,
+    /// Position in the data section
+    Offset,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LayerColumn {
+    /// Tensor name
+    Name,
+    /// Data type
+    Dtype,
+    /// Tensor shape
+    Shape,
+    /// Number of elements
+    Elements,
+    /// Byte size
+    Bytes,
+    /// Data offsets in the data section
+    Offsets,
+    /// Percentage of the total tensor bytes
+    Percent,
+}
+
+/// Which tensors to show and in which order.
+#[derive(Debug, Clone)]
+pub struct LayerQuery {
+    pub filter: Option<NamePattern>,
+    pub dtype: Option<Dtype>,
+    pub sort: LayerSort,
+    pub top: Option<usize>,
+}
+
+impl LayerQuery {
+    pub fn select<'a>(&self, weights: &'a Weights) -> Vec<(&'a String, &'a Weight)> {
+        let mut layers = weights
+            .iter()
+            .filter(|(name, weight)| {
+                let name_matches = match &self.filter {
+                    Some(filter) => filter.is_match(name),
+                    None => true,
+                };
+                let dtype_matches = match &self.dtype {
+                    Some(dtype) => weight.dtype == *dtype,
+                    None => true,
+                };
+
+                name_matches && dtype_matches
+            })
+            .collect::<Vec<_>>();
+
+        match self.sort {
+            LayerSort::Name => {} // BTreeMap is already sorted by name
+            LayerSort::Natural => layers.sort_by(|(a, _), (b, _)| natural_cmp(a, b)),
+            LayerSort::Size => layers.sort_by_key(|(_, weight)| Reverse(weight.byte_size())),
+            LayerSort::Offset => layers.sort_by_key(|(_, weight)| weight.data_offsets),
+        }
+
+        if let Some(top) = self.top {
+            layers.truncate(top);
+        }
+
+        layers
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn weights() -> Weights {
+        let weight = |dtype, shape: Vec<i64>, begin, end| Weight {
+            dtype,
+            shape,
+            data_offsets: [begin, end],
+        };
+
+        Weights::from([
+            (
+                "layers.10.weight".to_string(),
+                weight(Dtype::Fp32, vec![4], 0, 16),
+            ),
+            (
+                "layers.2.weight".to_string(),
+                weight(Dtype::Fp16, vec![2], 48, 52),
+            ),
+            (
+                "layers.1.weight".to_string(),
+                weight(Dtype::Fp32, vec![8], 16, 48),
+            ),
+            (
+                "lm_head.weight".to_string(),
+                weight(Dtype::Fp16, vec![1], 52, 54),
+            ),
+        ])
+    }
+
+    fn names(layers: &[(&String, &Weight)]) -> Vec<String> {
+        layers.iter().map(|(name, _)| name.to_string()).collect()
+    }
+
+    fn query(sort: LayerSort) -> LayerQuery {
+        LayerQuery {
+            filter: None,
+            dtype: None,
+            sort,
+            top: None,
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let weights = weights();
+
+        let layers = LayerQuery {
+            filter: Some(NamePattern::new("layers.*").unwrap()),
+            ..query(LayerSort::Name)
+        }
+        .select(&weights);
+        assert_eq!(
+            names(&layers),
+            ["layers.1.weight", "layers.10.weight", "layers.2.weight"]
+        );
+
+        let layers = LayerQuery {
+            filter: Some(NamePattern::new("layers.*").unwrap()),
+            dtype: Some(Dtype::Fp16),
+            ..query(LayerSort::Name)
+        }
+        .select(&weights);
+        assert_eq!(names(&layers), ["layers.2.weight"]);
+
+        let layers = LayerQuery {
+            filter: Some(NamePattern::new("re:^missing").unwrap()),
+            ..query(LayerSort::Name)
+        }
+        .select(&weights);
+        assert!(layers.is_empty());
+    }
+
+    #[test]
+    fn test_sort() {
+        let weights = weights();
+
+        assert_eq!(
+            names(&query(LayerSort::Name).select(&weights)),
+            [
+                "layers.1.weight",
+                "layers.10.weight",
+                "layers.2.weight",
+                "lm_head.weight"
+            ]
+        );
+        assert_eq!(
+            names(&query(LayerSort::Natural).select(&weights)),
+            [
+                "layers.1.weight",
+                "layers.2.weight",
+                "layers.10.weight",
+                "lm_head.weight"
+            ]
+        );
+        assert_eq!(
+            names(&query(LayerSort::Size).select(&weights)),
+            [
+                "layers.1.weight",
+                "layers.10.weight",
+                "layers.2.weight",
+                "lm_head.weight"
+            ]
+        );
+        assert_eq!(
+            names(&query(LayerSort::Offset).select(&weights)),
+            [
+                "layers.10.weight",
+                "layers.1.weight",
+                "layers.2.weight",
+                "lm_head.weight"
+            ]
+        );
+
+        let top = LayerQuery {
+            top: Some(2),
+            ..query(LayerSort::Size)
+        };
+        assert_eq!(
+            names(&top.select(&weights)),
+            ["layers.1.weight", "layers.10.weight"]
+        );
+    }
+
+    #[test]
+    fn test_columns() {
+        for column in LayerColumn::value_variants() {
+            let name = column.to_possible_value().unwrap();
+            assert_eq!(
+                LayerColumn::from_str(name.get_name(), true).unwrap(),
+                *column
+            );
+        }
+        assert_eq!(
+            LayerSort::from_str("natural", true).unwrap(),
+            LayerSort::Natural
+        );
+        assert!(LayerColumn::from_str("unknown", true).is_err());
+    }
+}
@@ -1,4 +1,20 @@
+pub mod atomic;
+pub mod clean;
+pub mod compare;
+pub mod convert;
+pub mod dedupe;
+pub mod document;
 pub mod fetch;
 pub mod file;
+pub mod hash;
+pub mod manifest;
+pub mod merge;
 pub mod metadata;
+mod named;
 pub mod parser;
+pub mod patch;
+pub mod pattern;
+pub mod progress;
+pub mod shard;
+pub mod validate;
+pub mod writer;
@@ -1,30 +1,57 @@
-mod fetch;
-mod metadata;
-mod parser;
+mod browse;
+mod info;
+mod layers;
+mod params;
+mod report;
+mod source;
+mod status;
 mod table;
 mod utils;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use anyhow::{Ok, Result};
+use anyhow::Result;
 use clap::{Parser, Subcommand};
 use hf_hub::{Cache, RepoType};
-use metadata::Header;
-use parser::{LocalParser, MetadataParser, RemoteParser};
-use safemetadata::file::SafetensorsFile;
-use table::InfoTable;
+use info::Summary;
+use layers::{LayerColumn, LayerQuery, LayerSort};
+use params::ParamBreakdown;
+use regex::Regex;
+use report::print_report;
+use safemetadata::clean::{CleanPreset, KeyFilter};
+use safemetadata::compare::{compare, SkipReason, Thresholds};
+use safemetadata::convert::DtypeConversion;
+use safemetadata::dedupe::Dedupe;
+use safemetadata::document::DocumentFormat;
+use safemetadata::file::{
+    extract_remote_tensors, ImportMode, Output, SafetensorsFile, WriteReport,
+};
+use safemetadata::hash::{
+    compute_hashes, compute_hashes_cached, refresh_cached_hashes, HashKind, MODELSPEC_HASH_KEY,
+};
+use safemetadata::manifest::{DigestAlgorithm, Manifest, TensorMismatch};
+use safemetadata::merge::{ConflictPolicy, Merger, MetadataMerge};
+use safemetadata::metadata::{Dtype, Header};
+use safemetadata::parser::{HeaderInfo, RemoteParser};
+use safemetadata::patch::{diff, MetadataPatch};
+use safemetadata::pattern::NamePattern;
+use safemetadata::shard::{parse_byte_size, shard, unshard};
+use source::{inspect_all, resolve_sources, Inspected, Source};
+use table::{BatchKind, BatchView, BreakdownView, CompareView, InfoTable, LayersView};
+use utils::{format_with_separators, pretty_byte_size, pretty_param_size};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
-struct CLI {
+struct Cli {
     #[command(subcommand)]
     command: Commands,
 }
 
 #[derive(Parser, Debug)]
 struct FileArgs {
-    /// The path of the safetensors file
-    file_path: String,
+    /// The paths of the safetensors files. Local paths can also be directories or glob patterns
+    #[clap(required = true)]
+    file_paths: Vec<String>,
 
     /// Repository id on HuggingFace hub
     #[clap(long, short)]
@@ -33,6 +60,104 @@ struct FileArgs {
     /// HuggingFace API token
     #[clap(long, short)]
     token: Option<String>,
+
+    /// Search directories recursively
+    #[clap(long)]
+    recursive: bool,
+}
+
+#[derive(Parser, Debug)]
+struct ParamsArgs {
+    #[command(flatten)]
+    file_args: FileArgs,
+
+    /// Use binary units (1K = 1024) instead of SI units (1K = 1000)
+    #[clap(long)]
+    binary: bool,
+
+    /// The number of name components that make up a module in the breakdown
+    #[clap(long, default_value_t = 1)]
+    depth: usize,
+}
+
+#[derive(Parser, Debug)]
+struct LayersArgs {
+    #[command(flatten)]
+    file_args: FileArgs,
+
+    /// Only show tensors whose names match the glob (or regex with a `re:` prefix)
+    #[clap(long, short)]
+    filter: Option<NamePattern>,
+
+    /// Only show tensors of the dtype
+    #[clap(long)]
+    dtype: Option<Dtype>,
+
+    /// Sort order of the tensors
+    #[clap(long, value_enum, default_value_t = LayerSort::Name)]
+    sort: LayerSort,
+
+    /// Only show the first N tensors after sorting
+    #[clap(long)]
+    top: Option<usize>,
+
+    /// Columns to show
+    #[clap(
+        long,
+        short,
+        value_enum,
+        value_delimiter = ',',
+        default_values_t = [LayerColumn::Name, LayerColumn::Dtype, LayerColumn::Shape]
+    )]
+    columns: Vec<LayerColumn>,
+}
+
+#[derive(Parser, Debug)]
+struct OutputArgs {
+    /// Output path. Must not be the input file
+    #[clap(long, short, required_unless_present = "in_place")]
+    output: Option<PathBuf>,
+
+    /// Modify the input file. Only the header is overwritten if the new header fits,
+    /// otherwise the file is rewritten to a temporary file and renamed over the input
+    #[clap(long, conflicts_with = "output")]
+    in_place: bool,
+
+    /// Keep the original file as <FILE_PATH>.bak when modifying it in place. If only the
+    /// header is overwritten, only the original header bytes are kept, as
+    /// <FILE_PATH>.header.bak
+    #[clap(long, requires = "in_place")]
+    backup: bool,
+
+    /// Reserve extra bytes in the header of rewritten files for later in-place edits
+    #[clap(long, default_value_t = 0)]
+    headroom: usize,
+}
+
+impl OutputArgs {
+    fn output(&self) -> Output {
+        match &self.output {
+            Some(path) if !self.in_place => Output::Path(path.clone()),
+            _ => Output::InPlace,
+        }
+    }
+
+    /// Run the write operation on the file, showing the progress of copying tensor data.
+    fn write<F, P: AsRef<Path>>(&self, file_path: P, write: F) -> Result<WriteReport>
+    where
+        F: FnOnce(&SafetensorsFile, Output) -> Result<WriteReport>,
+    {
+        let (bar, progress) = report::progress_bar();
+        let safetensors = SafetensorsFile::new(file_path)
+            .with_headroom(self.headroom)
+            .with_backup(self.backup)
+            .with_progress(progress);
+
+        let report = write(&safetensors, self.output());
+        bar.finish_and_clear();
+
+        report
+    }
 }
 
 #[derive(Parser, Debug)]
@@ -40,117 +165,1594 @@ struct CleanFileArgs {
     /// The path of the safetensors file
     file_path: PathBuf,
 
+    /// Keep the metadata keys matching the patterns (glob, or regex with `re:` prefix)
+    #[clap(long, value_delimiter = ',')]
+    keep: Vec<NamePattern>,
+
+    /// Remove only the metadata keys matching the patterns (glob, or regex with `re:` prefix)
+    #[clap(long, value_delimiter = ',')]
+    remove: Vec<NamePattern>,
+
+    /// Named set of keys to keep and remove: all, keep-format, keep-modelspec, training.
+    /// Can be combined with --keep, but not with --remove.
+    /// Removes all metadata if neither a preset nor patterns are given.
+    #[clap(long, conflicts_with = "remove")]
+    preset: Option<CleanPreset>,
+
+    #[clap(flatten)]
+    output_args: OutputArgs,
+}
+
+#[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct MetadataArgs {
+    #[command(subcommand)]
+    command: Option<MetadataCommands>,
+
+    #[command(flatten)]
+    file_args: FileArgs,
+}
+
+#[derive(Debug, Subcommand)]
+enum MetadataCommands {
+    /// Write the metadata to a JSON, TOML or YAML file
+    Export(ExportArgs),
+
+    /// Replace or merge the metadata with a JSON, TOML or YAML file
+    Import(ImportArgs),
+}
+
+#[derive(Parser, Debug)]
+struct ExportArgs {
+    #[command(flatten)]
+    file_args: FileArgs,
+
+    /// Output path. Prints to stdout if not given
+    #[clap(long, short)]
+    output: Option<PathBuf>,
+
+    /// The format of the output: json, toml or yaml. Guessed from the output path by default
+    #[clap(long, short)]
+    format: Option<DocumentFormat>,
+}
+
+#[derive(Parser, Debug)]
+struct ImportArgs {
+    /// The path of the safetensors file
+    file_path: PathBuf,
+
+    /// The path of the metadata file
+    input: PathBuf,
+
+    /// Keep the existing keys that are not in the metadata file
+    #[clap(long)]
+    merge: bool,
+
+    /// The format of the metadata file: json, toml or yaml. Guessed from the path by default
+    #[clap(long, short)]
+    format: Option<DocumentFormat>,
+
+    #[clap(flatten)]
+    output_args: OutputArgs,
+}
+
+#[derive(Parser, Debug)]
+#[command(mut_arg("output", |arg| {
+    arg.help("Output path. Only allowed with a single file")
+        .required_unless_present_any(["in_place", "dry_run"])
+}))]
+struct PatchArgs {
+    /// The paths of the safetensors files. Can also be directories or glob patterns
+    #[clap(required = true)]
+    file_paths: Vec<String>,
+
+    /// JSON Patch (array of operations) or JSON Merge Patch (object) to apply to the metadata
+    #[clap(long, short)]
+    patch: PathBuf,
+
+    /// Only show the changes, without writing any file
+    #[clap(long, conflicts_with_all = ["output", "in_place"])]
+    dry_run: bool,
+
+    #[clap(flatten)]
+    output_args: OutputArgs,
+
+    /// Search directories recursively
+    #[clap(long)]
+    recursive: bool,
+}
+
+#[derive(Parser, Debug)]
+struct TensorsArgs {
+    #[command(subcommand)]
+    command: TensorsCommands,
+}
+
+#[derive(Debug, Subcommand)]
+enum TensorsCommands {
+    /// Remove the tensors matching the patterns
+    Remove(RemoveTensorsArgs),
+
+    /// Rename the tensors matching the regular expression
+    Rename(RenameTensorsArgs),
+}
+
+#[derive(Parser, Debug)]
+struct RemoveTensorsArgs {
+    /// The path of the safetensors file
+    file_path: PathBuf,
+
+    /// Tensor names to remove (glob, or regex with `re:` prefix), e.g. `model_ema.*`
+    #[clap(value_name = "PATTERN", required = true)]
+    patterns: Vec<NamePattern>,
+
+    #[clap(flatten)]
+    output_args: OutputArgs,
+}
+
+#[derive(Parser, Debug)]
+struct RenameTensorsArgs {
+    /// The path of the safetensors file
+    file_path: PathBuf,
+
+    /// Regular expression to match in the tensor names
+    pattern: Regex,
+
+    /// The replacement, which can refer to capture groups, e.g. `$1`
+    replacement: String,
+
+    #[clap(flatten)]
+    output_args: OutputArgs,
+}
+
+#[derive(Parser, Debug)]
+struct ExtractArgs {
+    /// The path of the safetensors file, or the path in the repository with `--repo-id`
+    file_path: String,
+
+    /// Repository id on HuggingFace hub. Only the selected tensors are downloaded
+    #[clap(long, short)]
+    repo_id: Option<String>,
+
+    /// HuggingFace API token
+    #[clap(long, short)]
+    token: Option<String>,
+
+    /// Extract the tensors whose names start with the prefix, e.g. `first_stage_model.`
+    prefix: String,
+
+    /// Remove the prefix from the tensor names
+    #[clap(long)]
+    strip_prefix: bool,
+
+    #[clap(flatten)]
+    output_args: OutputArgs,
+}
+
+#[derive(Parser, Debug)]
+struct MergeArgs {
+    /// The paths of the safetensors files, in order of precedence for the conflict policy
+    #[clap(required = true, num_args = 2..)]
+    file_paths: Vec<PathBuf>,
+
     /// Output path
     #[clap(long, short)]
     output: PathBuf,
+
+    /// What to do with duplicate tensor names: error, prefer-first, prefer-last or prefix
+    /// (prefix the duplicates with the file name without the extension)
+    #[clap(long, default_value_t = ConflictPolicy::Error)]
+    on_conflict: ConflictPolicy,
+
+    /// How to combine the metadata: prefer-first, prefer-last, first-only, error or drop
+    #[clap(long, default_value_t = MetadataMerge::PreferFirst)]
+    metadata: MetadataMerge,
+
+    /// Reserve extra bytes in the header for later in-place edits
+    #[clap(long, default_value_t = 0)]
+    headroom: usize,
+}
+
+#[derive(Parser, Debug)]
+struct ShardArgs {
+    /// The path of the safetensors file
+    file_path: PathBuf,
+
+    /// Maximum tensor data per shard, e.g. `5GB` (powers of 1000) or `2GiB` (powers of 1024)
+    #[clap(long, value_parser = parse_byte_size)]
+    max_size: u64,
+
+    /// Directory of the shards and the index. Defaults to the directory of the input
+    #[clap(long, short)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Parser, Debug)]
+struct UnshardArgs {
+    /// The path of the index, e.g. `model.safetensors.index.json`
+    index_path: PathBuf,
+
+    /// Output path
+    #[clap(long, short)]
+    output: PathBuf,
+
+    /// Reserve extra bytes in the header for later in-place edits
+    #[clap(long, default_value_t = 0)]
+    headroom: usize,
+}
+
+#[derive(Parser, Debug)]
+struct ConvertArgs {
+    /// The path of the safetensors file
+    file_path: PathBuf,
+
+    /// Target dtype: f32, f16, bf16, f8_e4m3 or f8_e5m2
+    #[clap(long)]
+    dtype: Dtype,
+
+    /// Only convert the tensors matching the patterns (glob, or regex with `re:` prefix)
+    #[clap(long, value_delimiter = ',')]
+    include: Vec<NamePattern>,
+
+    /// Keep the tensors matching the patterns in their dtype, e.g. `*norm*`
+    #[clap(long, value_delimiter = ',')]
+    exclude: Vec<NamePattern>,
+
+    #[clap(flatten)]
+    output_args: OutputArgs,
+}
+
+#[derive(Parser, Debug)]
+struct HashArgs {
+    /// The paths of the safetensors files. Can also be directories or glob patterns
+    #[clap(required = true)]
+    file_paths: Vec<String>,
+
+    /// The hashes to compute: modelspec, autov2, a1111-legacy, sshs-model, sshs-legacy or blake3.
+    /// They are computed in one pass over the file
+    #[clap(long, value_delimiter = ',', default_values_t = [HashKind::ModelSpec])]
+    hashes: Vec<HashKind>,
+
+    /// Compute all hashes
+    #[clap(long, conflicts_with = "hashes")]
+    all: bool,
+
+    /// Write the computed hash to `modelspec.hash_sha256` if it is missing or different.
+    /// Only the header of the files is modified
+    #[clap(long)]
+    write: bool,
+
+    /// Keep the original files as <FILE>.bak when writing the hash, or only their header
+    /// bytes as <FILE>.header.bak if only the header is overwritten
+    #[clap(long, requires = "write")]
+    backup: bool,
+
+    /// Search directories recursively
+    #[clap(long)]
+    recursive: bool,
+
+    /// Always read the files, instead of reusing the hashes cached in
+    /// .safemetadata-hashes.json next to them
+    #[clap(long)]
+    no_cache: bool,
+}
+
+#[derive(Parser, Debug)]
+struct ManifestArgs {
+    #[command(subcommand)]
+    command: ManifestCommands,
+}
+
+#[derive(Debug, Subcommand)]
+enum ManifestCommands {
+    /// Write the name, dtype, shape, offsets and digest of each tensor to a JSON manifest
+    Create(ManifestCreateArgs),
+
+    /// Check the tensors of a file against a manifest
+    Verify(ManifestVerifyArgs),
+}
+
+#[derive(Parser, Debug)]
+struct ManifestCreateArgs {
+    /// The path of the safetensors file
+    file_path: PathBuf,
+
+    /// Output path. Prints to stdout if not given
+    #[clap(long, short)]
+    output: Option<PathBuf>,
+
+    /// The hash function of the digests: sha256 or blake3
+    #[clap(long, default_value_t = DigestAlgorithm::Sha256)]
+    algorithm: DigestAlgorithm,
+}
+
+#[derive(Parser, Debug)]
+struct ManifestVerifyArgs {
+    /// The path of the safetensors file
+    file_path: PathBuf,
+
+    /// The path of the manifest
+    manifest: PathBuf,
+}
+
+#[derive(Parser, Debug)]
+struct CompareArgs {
+    /// The path of the reference safetensors file
+    first: String,
+
+    /// The path of the safetensors file to check against the reference
+    second: String,
+
+    /// Repository id on HuggingFace hub of the reference file
+    #[clap(long)]
+    first_repo_id: Option<String>,
+
+    /// Repository id on HuggingFace hub of the other file
+    #[clap(long)]
+    second_repo_id: Option<String>,
+
+    /// HuggingFace API token
+    #[clap(long, short)]
+    token: Option<String>,
+
+    /// Fail if the maximum absolute difference of a tensor is larger
+    #[clap(long)]
+    max_abs_diff: Option<f64>,
+
+    /// Fail if the relative error (L2 norm of the difference over the L2 norm of the reference)
+    /// of a tensor is larger
+    #[clap(long, default_value_t = 1e-2)]
+    max_relative_error: f64,
+
+    /// Fail if the cosine similarity of a tensor is smaller
+    #[clap(long, default_value_t = 0.999)]
+    min_cosine: f64,
+}
+
+#[derive(Parser, Debug)]
+struct DuplicatesArgs {
+    /// The paths of the safetensors files. Can also be directories or glob patterns
+    #[clap(required = true)]
+    file_paths: Vec<String>,
+
+    /// Search directories recursively
+    #[clap(long)]
+    recursive: bool,
+}
+
+#[derive(Parser, Debug)]
+struct DedupeArgs {
+    /// The paths of the safetensors files. Can also be directories or glob patterns
+    #[clap(required = true)]
+    file_paths: Vec<String>,
+
+    /// Search directories recursively
+    #[clap(long)]
+    recursive: bool,
+
+    /// The number of tensors sampled in each file to find related files. 0 only finds
+    /// identical files. The first MiB of each sampled tensor is compared byte for byte,
+    /// so a full finetune that changed every tensor is not reported as related
+    #[clap(long, default_value_t = 64)]
+    sample_size: usize,
+
+    /// The share of identical sampled tensors from which files are reported as related
+    #[clap(long, default_value_t = 0.1)]
+    min_relatedness: f64,
+
+    /// Always read the files, instead of reusing the hashes cached in
+    /// .safemetadata-hashes.json next to them
+    #[clap(long)]
+    no_cache: bool,
+}
+
+/// Parse `KEY=VALUE`
+fn parse_key_value(s: &str) -> Result<(String, String)> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(anyhow::anyhow!("expected KEY=VALUE, got `{}`", s)),
+    }
+}
+
+#[derive(Parser, Debug)]
+struct SetArgs {
+    /// The path of the safetensors file
+    file_path: PathBuf,
+
+    /// Metadata entries to set
+    #[clap(value_name = "KEY=VALUE", required = true, value_parser = parse_key_value)]
+    entries: Vec<(String, String)>,
+
+    #[clap(flatten)]
+    output_args: OutputArgs,
+}
+
+#[derive(Parser, Debug)]
+struct UnsetArgs {
+    /// The path of the safetensors file
+    file_path: PathBuf,
+
+    /// Metadata keys to remove
+    #[clap(value_name = "KEY", required = true)]
+    keys: Vec<String>,
+
+    #[clap(flatten)]
+    output_args: OutputArgs,
+}
+
+#[derive(Parser, Debug)]
+struct RenameKeyArgs {
+    /// The path of the safetensors file
+    file_path: PathBuf,
+
+    /// The current metadata key
+    from: String,
+
+    /// The new metadata key
+    to: String,
+
+    #[clap(flatten)]
+    output_args: OutputArgs,
 }
 
 #[derive(Debug, Subcommand)]
 enum Commands {
+    /// Show the summary of the file
+    Info(FileArgs),
+
     /// Show the parameter sizes of the model
-    Params(FileArgs),
+    Params(ParamsArgs),
 
     /// Show the layers of the model
-    Layers(FileArgs),
+    Layers(LayersArgs),
+
+    /// Browse the tensors and metadata of the file interactively
+    Browse(FileArgs),
 
     /// Show the Stability AI Model Specification of the file
     #[clap(name = "modelspec")]
     ModelSpec(FileArgs),
 
-    /// Show metadata field
+    /// Show metadata field, or export and import it
     #[clap(name = "metadata")]
-    Metadata(FileArgs),
+    Metadata(MetadataArgs),
 
     /// Remove metadata field
     #[clap(name = "clean")]
     Clean(CleanFileArgs),
+
+    /// Set metadata keys
+    #[clap(name = "set")]
+    Set(SetArgs),
+
+    /// Remove metadata keys
+    #[clap(name = "unset")]
+    Unset(UnsetArgs),
+
+    /// Rename a metadata key
+    #[clap(name = "rename-key")]
+    RenameKey(RenameKeyArgs),
+
+    /// Apply a JSON Patch or JSON Merge Patch to the metadata
+    #[clap(name = "patch")]
+    Patch(PatchArgs),
+
+    /// Remove or rename tensors
+    #[clap(name = "tensors")]
+    Tensors(TensorsArgs),
+
+    /// Extract the tensors with a prefix into a new file
+    #[clap(name = "extract")]
+    Extract(ExtractArgs),
+
+    /// Combine the tensors of several files into one file
+    #[clap(name = "merge")]
+    Merge(MergeArgs),
+
+    /// Split a file into shards with a `.index.json`, like transformers does
+    #[clap(name = "shard")]
+    Shard(ShardArgs),
+
+    /// Join the shards of an index into a single file
+    #[clap(name = "unshard")]
+    Unshard(UnshardArgs),
+
+    /// Cast the floating-point tensors to another dtype
+    #[clap(name = "convert")]
+    Convert(ConvertArgs),
+
+    /// Compute the ModelSpec hash and the hashes of model sharing sites, and check them
+    /// against the metadata
+    #[clap(name = "hash")]
+    Hash(HashArgs),
+
+    /// Compare the values of the tensors with the same name in two files
+    #[clap(name = "compare")]
+    Compare(CompareArgs),
+
+    /// Find the tensors with identical data in each file, such as tied embeddings
+    #[clap(name = "duplicates")]
+    Duplicates(DuplicatesArgs),
+
+    /// Find the files with identical tensor data, and the related files such as finetunes
+    #[clap(name = "dedupe")]
+    Dedupe(DedupeArgs),
+
+    /// Create or verify a manifest of per-tensor hashes
+    #[clap(name = "manifest")]
+    Manifest(ManifestArgs),
 }
 
-fn parse_header(args: FileArgs) -> Result<Header> {
+/// Parse the headers of all files given in the arguments.
+fn inspect(args: FileArgs) -> Result<Vec<Inspected>> {
     let FileArgs {
-        file_path,
+        file_paths,
         repo_id,
         token,
+        recursive,
     } = args;
 
-    match repo_id {
-        Some(repo_id) => {
-            let token = match token {
-                Some(token) => Some(token),       // do nothing
-                None => Cache::default().token(), // load token from cache
-            };
-            let parser = RemoteParser::from_hub(&repo_id, RepoType::Model, &file_path, &token);
-            parser.parse_header()
+    let sources = resolve_sources(file_paths, repo_id, token, recursive);
+    if sources.is_empty() {
+        return Err(anyhow::anyhow!("No safetensors files found"));
+    }
+
+    Ok(inspect_all(sources))
+}
+
+/// Fail if any of the files could not be read.
+fn check_errors(files: &[Inspected]) -> Result<()> {
+    let failed = files.iter().filter(|file| file.result.is_err()).count();
+
+    match failed {
+        0 => Ok(()),
+        _ => Err(anyhow::anyhow!(
+            "{} of {} files could not be read",
+            failed,
+            files.len()
+        )),
+    }
+}
+
+fn print_params(info: &HeaderInfo, binary: bool, depth: usize) {
+    let breakdown = ParamBreakdown::new(&info.header.weights, depth);
+    let (params, unit) = pretty_param_size(breakdown.total.params, binary);
+
+    println!(
+        "Total parameters: {}{} params ({})",
+        params,
+        unit,
+        format_with_separators(breakdown.total.params)
+    );
+
+    println!("Parameters by dtype");
+    let view = BreakdownView {
+        label: "DType",
+        groups: &breakdown.by_dtype,
+        total: breakdown.total,
+        binary,
+    };
+    println!("{}", view.format_table());
+
+    println!("Parameters by module");
+    let view = BreakdownView {
+        label: "Module",
+        groups: &breakdown.by_module,
+        total: breakdown.total,
+        binary,
+    };
+    println!("{}", view.format_table());
+
+    let tensor_bytes = info.tensor_bytes();
+    let header_bytes = info.data_start(); // including the 8 bytes of the header size
+    let unreferenced_bytes = info.data_size().saturating_sub(tensor_bytes);
+    let overhead_bytes = info.file_size.saturating_sub(tensor_bytes);
+    let overhead_percent = match info.file_size {
+        0 => 0.,
+        file_size => overhead_bytes as f64 / file_size as f64 * 100.,
+    };
+
+    let pretty_bytes = |bytes: u64| {
+        format!(
+            "{} ({} bytes)",
+            pretty_byte_size(bytes),
+            format_with_separators(bytes as i64)
+        )
+    };
+    println!("Tensor data:  {}", pretty_bytes(tensor_bytes));
+    println!("Header:       {}", pretty_bytes(header_bytes));
+    println!("Unreferenced: {}", pretty_bytes(unreferenced_bytes));
+    println!("File size:    {}", pretty_bytes(info.file_size));
+    println!(
+        "Overhead:     {} ({:.2}% of the file)",
+        pretty_bytes(overhead_bytes),
+        overhead_percent
+    );
+}
+
+fn print_layers(header: &Header, query: &LayerQuery, columns: &[LayerColumn]) {
+    if let Some(metadata) = &header.metadata {
+        match &metadata.format {
+            Some(format) => {
+                println!("Tensor format: {}", format);
+            }
+            None => {
+                println!("Unknown tensor format")
+            }
+        };
+    }
+
+    let layers = query.select(&header.weights);
+    let total_bytes = header.weights.values().map(|w| w.byte_size()).sum();
+
+    if layers.len() < header.weights.len() {
+        println!(
+            "Showing {} of {} tensors",
+            layers.len(),
+            header.weights.len()
+        );
+    }
+
+    let view = LayersView {
+        layers,
+        columns: columns.to_vec(),
+        total_bytes,
+    };
+    println!("{}", view.format_table());
+}
+
+fn print_modelspec(header: &Header) {
+    if let Some(metadata) = &header.metadata {
+        if let Some(modelspec) = &metadata.model_spec {
+            println!("Stability AI Model Metadata Standard Specification");
+            println!("{}", modelspec.format_table());
+        } else {
+            println!("No modelspec found in the file.");
         }
-        None => {
-            let parser = LocalParser::new(&file_path);
-            parser.parse_header()
+    } else {
+        println!("No metadata found in the file.");
+    }
+}
+
+fn print_metadata(header: &Header) {
+    if let Some(metadata) = &header.metadata {
+        println!("Metadata");
+        println!("{}", metadata.format_table());
+    } else {
+        println!("No metadata found in the file.");
+    }
+}
+
+/// Write the metadata of a single file to the output, or stdout.
+fn export_metadata(args: ExportArgs) -> Result<()> {
+    let ExportArgs {
+        file_args,
+        output,
+        format,
+    } = args;
+
+    let files = inspect(file_args)?;
+    if files.len() != 1 {
+        return Err(anyhow::anyhow!("Only one file can be exported at a time"));
+    }
+    let info = files.into_iter().next().unwrap().result?;
+
+    let format = match (format, &output) {
+        (Some(format), _) => format,
+        (None, Some(output)) => DocumentFormat::from_path(output)?,
+        (None, None) => DocumentFormat::Json,
+    };
+    let map = match &info.header.metadata {
+        Some(metadata) => metadata.to_map()?,
+        None => Default::default(),
+    };
+    let text = format.serialize(&map)?;
+
+    match output {
+        Some(output) => std::fs::write(output, text)?,
+        None => print!("{}", text),
+    }
+
+    Ok(())
+}
+
+/// Apply the patch to each file, printing the changes.
+fn patch_files(args: PatchArgs) -> Result<()> {
+    let PatchArgs {
+        file_paths,
+        patch,
+        dry_run,
+        output_args,
+        recursive,
+    } = args;
+
+    let patch = MetadataPatch::parse(&std::fs::read_to_string(&patch)?)?;
+
+    let sources = resolve_sources(file_paths, None, None, recursive);
+    if sources.is_empty() {
+        return Err(anyhow::anyhow!("No safetensors files found"));
+    }
+    if output_args.output.is_some() && sources.len() > 1 {
+        return Err(anyhow::anyhow!(
+            "--output can only be used with a single file, use --in-place instead"
+        ));
+    }
+
+    let mut failed = 0;
+    for (name, source) in &sources {
+        if sources.len() > 1 {
+            println!("==> {} <==", name);
+        }
+
+        let result = source
+            .as_ref()
+            .map_err(|err| anyhow::anyhow!("{:#}", err))
+            .and_then(|source| {
+                if dry_run {
+                    let map = match source.parser().parse_header()?.metadata {
+                        Some(metadata) => metadata.to_map()?,
+                        None => Default::default(),
+                    };
+                    return Ok(WriteReport {
+                        changes: diff(&map, &patch.apply(&map)?),
+                        ..Default::default()
+                    });
+                }
+
+                output_args.write(&source.file_path, |safetensors, output| {
+                    safetensors.patch_metadata(output, &patch)
+                })
+            });
+
+        match result {
+            Ok(report) if report.changes.is_empty() => {
+                println!("No changes.")
+            }
+            Ok(report) if dry_run => {
+                for change in report.changes {
+                    println!("{}", change);
+                }
+            }
+            Ok(report) => print_report(&report),
+            Err(err) => {
+                println!("Error: {:#}", err);
+                failed += 1;
+            }
         }
+        if sources.len() > 1 {
+            println!();
+        }
+    }
+
+    match failed {
+        0 => Ok(()),
+        _ => Err(anyhow::anyhow!(
+            "{} of {} files could not be patched",
+            failed,
+            sources.len()
+        )),
     }
 }
 
-fn main() -> Result<()> {
-    let args = CLI::parse();
+fn hash_files(args: HashArgs) -> Result<()> {
+    let HashArgs {
+        file_paths,
+        hashes,
+        all,
+        write,
+        backup,
+        recursive,
+        no_cache,
+    } = args;
 
-    match args.command {
-        Commands::Params(file_args) => {
-            let header = parse_header(file_args)?;
+    let kinds = match all {
+        true => HashKind::ALL.to_vec(),
+        false => hashes,
+    };
+    if write && !kinds.contains(&HashKind::ModelSpec) {
+        return Err(anyhow::anyhow!(
+            "--write stores {}, so the modelspec hash must be computed",
+            MODELSPEC_HASH_KEY
+        ));
+    }
 
-            let params = header.weights.values().fold(0, |sum: i64, weight| {
-                sum + weight.shape.iter().product::<i64>()
+    let sources = resolve_sources(file_paths, None, None, recursive);
+    if sources.is_empty() {
+        return Err(anyhow::anyhow!("No safetensors files found"));
+    }
+
+    let (mut failed, mut mismatched) = (0, 0);
+    for (name, source) in &sources {
+        let result = source
+            .as_ref()
+            .map_err(|err| anyhow::anyhow!("{:#}", err))
+            .and_then(|source| {
+                let (bar, progress) = report::progress_bar();
+                let hashes = match no_cache {
+                    true => compute_hashes(&source.file_path, &kinds, Some(&progress)),
+                    false => compute_hashes_cached(&source.file_path, &kinds, Some(&progress)),
+                };
+                bar.finish_and_clear();
+                hashes.map(|hashes| (&source.file_path, hashes))
             });
-            let (params, unit) = utils::pretty_param_size(params);
+        let (file_path, hashes) = match result {
+            Ok(result) => result,
+            Err(err) => {
+                println!("{}: Error: {:#}", name, err);
+                failed += 1;
+                continue;
+            }
+        };
 
-            println!("Total parameters: {}{} params", params, unit);
+        if kinds.len() > 1 {
+            println!("==> {} <==", name);
+        }
+        for hash in &hashes {
+            let status = match (hash.kind.metadata_key(), hash.matches()) {
+                (Some(key), Some(true)) => format!(" (matches {})", key),
+                (Some(key), Some(false)) => {
+                    eprintln!(
+                        "Warning: {}: {} is {}, which does not match the computed {}",
+                        name,
+                        key,
+                        hash.stored.as_deref().unwrap_or_default(),
+                        hash.computed
+                    );
+                    format!(" (does not match {})", key)
+                }
+                (Some(key), None) => format!(" (no {})", key),
+                (None, _) => String::new(),
+            };
+            match kinds.len() {
+                1 => println!("{}: {}{}", name, hash.computed, status),
+                _ => println!("{:<13} {}{}", hash.kind.to_string(), hash.computed, status),
+            }
         }
-        Commands::Layers(file_args) => {
-            let header = parse_header(file_args)?;
 
-            if let Some(metadata) = header.metadata {
-                match metadata.format {
-                    Some(format) => {
-                        println!("Tensor format: {}", format);
+        let modelspec = hashes
+            .iter()
+            .find(|hash| hash.kind == HashKind::ModelSpec)
+            .filter(|hash| hash.matches() != Some(true));
+        let mut written = false;
+        if let (true, Some(hash)) = (write, modelspec) {
+            let entries = [(MODELSPEC_HASH_KEY.to_string(), hash.computed.clone())];
+            let result = SafetensorsFile::new(file_path)
+                .with_backup(backup)
+                .set_metadata(Output::InPlace, &entries);
+            match result {
+                Ok(report) => {
+                    print_report(&report);
+                    written = true;
+                    if !no_cache {
+                        // the cache is best effort, a stale entry is only hashed again
+                        let _ = refresh_cached_hashes(file_path);
                     }
-                    None => {
-                        println!("Unknown tensor format")
-                    }
-                };
+                }
+                Err(err) => {
+                    println!("Error: {:#}", err);
+                    failed += 1;
+                }
+            }
+        }
+        if !status::unfixed_mismatches(&hashes, written).is_empty() {
+            mismatched += 1;
+        }
+        if kinds.len() > 1 && sources.len() > 1 {
+            println!();
+        }
+    }
+
+    status::hash_status(sources.len(), failed, mismatched)
+}
+
+/// Print the differences of the tensors of two files, failing if a tensor is
+/// not within the thresholds or has a different shape.
+fn compare_files(args: CompareArgs) -> Result<()> {
+    let CompareArgs {
+        first,
+        second,
+        first_repo_id,
+        second_repo_id,
+        token,
+        max_abs_diff,
+        max_relative_error,
+        min_cosine,
+    } = args;
+
+    let token = match (&token, &first_repo_id, &second_repo_id) {
+        (None, Some(_), _) | (None, _, Some(_)) => Cache::default().token(),
+        _ => token,
+    };
+    let first = Source {
+        file_path: first,
+        repo_id: first_repo_id,
+        token: token.clone(),
+    };
+    let second = Source {
+        file_path: second,
+        repo_id: second_repo_id,
+        token,
+    };
+    let thresholds = Thresholds {
+        max_abs_diff,
+        max_relative_error: Some(max_relative_error),
+        min_cosine_similarity: Some(min_cosine),
+    };
+
+    let (bar, progress) = report::progress_bar();
+    let comparison = compare(
+        first.parser().as_ref(),
+        second.parser().as_ref(),
+        Some(&progress),
+    );
+    bar.finish_and_clear();
+    let comparison = comparison?;
+
+    if !comparison.tensors.is_empty() {
+        let view = CompareView {
+            tensors: &comparison.tensors,
+            thresholds,
+        };
+        println!("{}", view.format_table());
+    }
+    for (name, reason) in &comparison.skipped {
+        match reason {
+            SkipReason::Shape { first, second } => {
+                println!("! {}: shape {:?} vs {:?}", name, first, second)
             }
+            SkipReason::Dtype(dtype) => println!("? {}: {} cannot be compared", name, dtype),
+        }
+    }
+    for name in &comparison.only_in_first {
+        println!("- {}: only in {}", name, first.display_name());
+    }
+    for name in &comparison.only_in_second {
+        println!("+ {}: only in {}", name, second.display_name());
+    }
 
-            println!("{}", header.weights.format_table());
+    status::compare_status(&comparison, &thresholds)?;
+    println!(
+        "All {} compared tensors are within the thresholds.",
+        comparison.tensors.len()
+    );
+
+    Ok(())
+}
+
+/// Print the groups of identical tensors of each file and the bytes that
+/// storing them once would save.
+fn find_duplicates(args: DuplicatesArgs) -> Result<()> {
+    let DuplicatesArgs {
+        file_paths,
+        recursive,
+    } = args;
+
+    let sources = resolve_sources(file_paths, None, None, recursive);
+    if sources.is_empty() {
+        return Err(anyhow::anyhow!("No safetensors files found"));
+    }
+
+    let mut failed = 0;
+    for (name, source) in &sources {
+        if sources.len() > 1 {
+            println!("==> {} <==", name);
         }
-        Commands::ModelSpec(file_args) => {
-            let header = parse_header(file_args)?;
+        let result = source
+            .as_ref()
+            .map_err(|err| anyhow::anyhow!("{:#}", err))
+            .and_then(|source| {
+                let (bar, progress) = report::progress_bar();
+                let manifest =
+                    Manifest::create(&source.file_path, DigestAlgorithm::Blake3, Some(&progress));
+                bar.finish_and_clear();
+                manifest
+            });
+        let manifest = match result {
+            Ok(manifest) => manifest,
+            Err(err) => {
+                println!("Error: {:#}", err);
+                failed += 1;
+                continue;
+            }
+        };
 
-            if let Some(metadata) = header.metadata {
-                if let Some(modelspec) = metadata.model_spec {
-                    println!("Stability AI Model Metadata Standard Specification");
-                    println!("{}", modelspec.format_table());
-                } else {
-                    println!("No modelspec found in the file.");
+        let duplicates = manifest.duplicates();
+        for group in &duplicates {
+            println!(
+                "{} identical tensors of {} (saves {}):",
+                group.tensors.len(),
+                pretty_byte_size(group.byte_size),
+                pretty_byte_size(group.savings())
+            );
+            for entry in &group.tensors {
+                println!("  {} {} {:?}", entry.name, entry.dtype, entry.shape);
+            }
+        }
+        match duplicates.is_empty() {
+            true => println!("No duplicate tensors."),
+            false => println!(
+                "Identical tensors in {} groups, {} could be saved.",
+                duplicates.len(),
+                pretty_byte_size(duplicates.iter().map(|group| group.savings()).sum())
+            ),
+        }
+        if sources.len() > 1 {
+            println!();
+        }
+    }
+
+    if failed > 0 {
+        return Err(anyhow::anyhow!(
+            "{} of {} files could not be read",
+            failed,
+            sources.len()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Print the groups of files with identical tensor data and the related files.
+fn dedupe_files(args: DedupeArgs) -> Result<()> {
+    let DedupeArgs {
+        file_paths,
+        recursive,
+        sample_size,
+        min_relatedness,
+        no_cache,
+    } = args;
+
+    let mut paths = Vec::new();
+    for (name, source) in resolve_sources(file_paths, None, None, recursive) {
+        match source {
+            Ok(source) => paths.push(PathBuf::from(source.file_path)),
+            Err(err) => println!("{}: Error: {:#}", name, err),
+        }
+    }
+    if paths.is_empty() {
+        return Err(anyhow::anyhow!("No safetensors files found"));
+    }
+
+    let (bar, progress) = report::progress_bar();
+    let report = Dedupe::new(&paths)
+        .with_sample_size(sample_size)
+        .with_min_relatedness(min_relatedness)
+        .with_cache(!no_cache)
+        .with_progress(progress)
+        .run();
+    bar.finish_and_clear();
+    let report = report?;
+
+    for group in &report.identical {
+        println!(
+            "Identical tensor data, {} each (saves {}):",
+            pretty_byte_size(group.data_size),
+            pretty_byte_size(group.savings())
+        );
+        for path in &group.paths {
+            println!("  {}", path.display());
+        }
+    }
+    if !report.related.is_empty() {
+        println!("Related files (identical sampled tensors):");
+        for related in &report.related {
+            println!(
+                "  {:>5.1}% ({} of {})  {}  {}",
+                related.relatedness() * 100.,
+                related.identical,
+                related.common,
+                related.first.display(),
+                related.second.display()
+            );
+        }
+    }
+    for (path, err) in &report.errors {
+        println!("{}: Error: {}", path.display(), err);
+    }
+
+    println!(
+        "{} files, {} groups of identical files, {} could be saved.",
+        paths.len(),
+        report.identical.len(),
+        pretty_byte_size(report.identical.iter().map(|group| group.savings()).sum())
+    );
+
+    Ok(())
+}
+
+/// Print how the tensors differ from the manifest, failing if any does.
+fn verify_manifest(args: ManifestVerifyArgs) -> Result<()> {
+    let manifest = Manifest::read(&args.manifest)?;
+    let (bar, progress) = report::progress_bar();
+    let report = manifest.verify(&args.file_path, Some(&progress));
+    bar.finish_and_clear();
+    let report = report?;
+
+    for mismatch in &report.mismatches {
+        match mismatch {
+            TensorMismatch::Missing(entry) => println!("- {}: missing", entry.name),
+            TensorMismatch::Unexpected(entry) => println!("+ {}: not in the manifest", entry.name),
+            TensorMismatch::Changed { expected, actual } => {
+                let mut changes = Vec::new();
+                if expected.dtype != actual.dtype {
+                    changes.push(format!("dtype {} -> {}", expected.dtype, actual.dtype));
+                }
+                if expected.shape != actual.shape {
+                    changes.push(format!("shape {:?} -> {:?}", expected.shape, actual.shape));
                 }
+                if expected.digest != actual.digest {
+                    changes.push("data differs".to_string());
+                }
+                println!("~ {}: {}", expected.name, changes.join(", "));
+            }
+        }
+    }
+
+    status::verify_status(&report)?;
+    println!(
+        "All {} tensors match the manifest ({}).",
+        report.matched, manifest.algorithm
+    );
+
+    Ok(())
+}
+
+/// Print the output of each file under its name.
+fn print_each(files: &[Inspected], print: impl Fn(&HeaderInfo)) -> Result<()> {
+    for file in files {
+        println!("==> {} <==", file.name);
+        match &file.result {
+            Ok(info) => print(info),
+            Err(err) => println!("Error: {:#}", err),
+        }
+        println!();
+    }
+
+    check_errors(files)
+}
+
+fn main() -> Result<()> {
+    let args = Cli::parse();
+
+    match args.command {
+        Commands::Info(file_args) => {
+            let files = inspect(file_args)?;
+
+            if files.len() == 1 {
+                let info = files.into_iter().next().unwrap().result?;
+                let summary = Summary::new(&info);
+                println!("{}", summary.format_table());
             } else {
-                println!("No metadata found in the file.");
+                let view = BatchView {
+                    kind: BatchKind::Info,
+                    files: &files,
+                };
+                println!("{}", view.format_table());
+                check_errors(&files)?;
             }
         }
-        Commands::Metadata(file_args) => {
-            let header = parse_header(file_args)?;
+        Commands::Params(params_args) => {
+            let ParamsArgs {
+                file_args,
+                binary,
+                depth,
+            } = params_args;
+            let files = inspect(file_args)?;
+
+            if files.len() == 1 {
+                let info = files.into_iter().next().unwrap().result?;
+                print_params(&info, binary, depth);
+            } else {
+                let view = BatchView {
+                    kind: BatchKind::Params { binary },
+                    files: &files,
+                };
+                println!("{}", view.format_table());
+                check_errors(&files)?;
+            }
+        }
+        Commands::Layers(layers_args) => {
+            let LayersArgs {
+                file_args,
+                filter,
+                dtype,
+                sort,
+                top,
+                columns,
+            } = layers_args;
+            let files = inspect(file_args)?;
+
+            let query = LayerQuery {
+                filter,
+                dtype,
+                sort,
+                top,
+            };
 
-            if let Some(metadata) = header.metadata {
-                println!("Metadata");
-                println!("{}", metadata.format_table());
+            if files.len() == 1 {
+                let info = files.into_iter().next().unwrap().result?;
+                print_layers(&info.header, &query, &columns);
             } else {
-                println!("No metadata found in the file.");
+                print_each(&files, |info| print_layers(&info.header, &query, &columns))?;
             }
         }
+        Commands::Browse(file_args) => {
+            let files = inspect(file_args)?;
+            if files.len() != 1 {
+                return Err(anyhow::anyhow!("Only one file can be browsed at a time"));
+            }
+
+            let file = files.into_iter().next().unwrap();
+            let info = file.result?;
+            browse::browse(file.name, &info)?;
+        }
+        Commands::ModelSpec(file_args) => {
+            let files = inspect(file_args)?;
+
+            if files.len() == 1 {
+                let info = files.into_iter().next().unwrap().result?;
+                print_modelspec(&info.header);
+            } else {
+                let view = BatchView {
+                    kind: BatchKind::ModelSpec,
+                    files: &files,
+                };
+                println!("{}", view.format_table());
+                check_errors(&files)?;
+            }
+        }
+        Commands::Metadata(metadata_args) => match metadata_args.command {
+            None => {
+                let files = inspect(metadata_args.file_args)?;
+
+                if files.len() == 1 {
+                    let info = files.into_iter().next().unwrap().result?;
+                    print_metadata(&info.header);
+                } else {
+                    print_each(&files, |info| print_metadata(&info.header))?;
+                }
+            }
+            Some(MetadataCommands::Export(export_args)) => export_metadata(export_args)?,
+            Some(MetadataCommands::Import(import_args)) => {
+                let ImportArgs {
+                    file_path,
+                    input,
+                    merge,
+                    format,
+                    output_args,
+                } = import_args;
+
+                let format = match format {
+                    Some(format) => format,
+                    None => DocumentFormat::from_path(&input)?,
+                };
+                let entries = format.deserialize(&std::fs::read_to_string(&input)?)?;
+                let mode = match merge {
+                    true => ImportMode::Merge,
+                    false => ImportMode::Replace,
+                };
+
+                let report = output_args.write(&file_path, |safetensors, output| {
+                    safetensors.import_metadata(output, entries, mode)
+                })?;
+                print_report(&report);
+
+                println!("Metadata imported successfully.");
+            }
+        },
         Commands::Clean(file_args) => {
-            let CleanFileArgs { file_path, output } = file_args;
+            let CleanFileArgs {
+                file_path,
+                keep,
+                remove,
+                preset,
+                output_args,
+            } = file_args;
+
+            let filter = match preset {
+                Some(preset) => KeyFilter::from_preset(preset).with_keep(keep),
+                None => KeyFilter::new(keep, remove),
+            };
+
+            let report = output_args.write(&file_path, |safetensors, output| {
+                safetensors.clean_metadata(output, &filter)
+            })?;
+            print_report(&report);
+
+            match report.removed_keys().len() {
+                0 => println!("No metadata keys removed."),
+                removed => println!("Removed {} metadata keys.", removed),
+            }
+        }
+        Commands::Set(set_args) => {
+            let SetArgs {
+                file_path,
+                entries,
+                output_args,
+            } = set_args;
+
+            let report = output_args.write(&file_path, |safetensors, output| {
+                safetensors.set_metadata(output, &entries)
+            })?;
+            print_report(&report);
+
+            println!("Metadata updated successfully.");
+        }
+        Commands::Unset(unset_args) => {
+            let UnsetArgs {
+                file_path,
+                keys,
+                output_args,
+            } = unset_args;
+
+            let report = output_args.write(&file_path, |safetensors, output| {
+                safetensors.unset_metadata(output, &keys)
+            })?;
+            print_report(&report);
+
+            println!("Metadata updated successfully.");
+        }
+        Commands::RenameKey(rename_key_args) => {
+            let RenameKeyArgs {
+                file_path,
+                from,
+                to,
+                output_args,
+            } = rename_key_args;
+
+            let report = output_args.write(&file_path, |safetensors, output| {
+                safetensors.rename_metadata_key(output, &from, &to)
+            })?;
+            print_report(&report);
+
+            println!("Metadata updated successfully.");
+        }
+        Commands::Patch(patch_args) => patch_files(patch_args)?,
+        Commands::Tensors(tensors_args) => match tensors_args.command {
+            TensorsCommands::Remove(remove_args) => {
+                let RemoveTensorsArgs {
+                    file_path,
+                    patterns,
+                    output_args,
+                } = remove_args;
+
+                let report = output_args.write(&file_path, |safetensors, output| {
+                    safetensors.remove_tensors(output, &patterns)
+                })?;
+                for name in &report.removed_tensors {
+                    println!("- {}", name);
+                }
+                print_report(&report);
+
+                println!("Removed {} tensors.", report.removed_tensors.len());
+            }
+            TensorsCommands::Rename(rename_args) => {
+                let RenameTensorsArgs {
+                    file_path,
+                    pattern,
+                    replacement,
+                    output_args,
+                } = rename_args;
+
+                let report = output_args.write(&file_path, |safetensors, output| {
+                    safetensors.rename_tensors(output, &pattern, &replacement)
+                })?;
+                for (old, new) in &report.renamed_tensors {
+                    println!("~ {} -> {}", old, new);
+                }
+                print_report(&report);
+
+                println!("Renamed {} tensors.", report.renamed_tensors.len());
+            }
+        },
+        Commands::Extract(extract_args) => {
+            let ExtractArgs {
+                file_path,
+                repo_id,
+                token,
+                prefix,
+                strip_prefix,
+                output_args,
+            } = extract_args;
+
+            let report = match repo_id {
+                Some(repo_id) => {
+                    let output_path = match (&output_args.output, output_args.in_place) {
+                        (Some(path), false) => path,
+                        _ => {
+                            return Err(anyhow::anyhow!(
+                                "Remote files cannot be modified in place. Use --output instead."
+                            ))
+                        }
+                    };
+                    let token = token.or_else(|| Cache::default().token());
+                    let parser =
+                        RemoteParser::from_hub(&repo_id, RepoType::Model, &file_path, &token);
 
-            let safetensors = SafetensorsFile::new(&file_path);
+                    let (bar, progress) = report::progress_bar();
+                    let report = extract_remote_tensors(
+                        &parser,
+                        output_path,
+                        &prefix,
+                        strip_prefix,
+                        output_args.headroom,
+                        Some(progress),
+                    );
+                    bar.finish_and_clear();
+                    report?
+                }
+                None => output_args.write(file_path, |safetensors, output| {
+                    safetensors.extract_tensors(output, &prefix, strip_prefix)
+                })?,
+            };
+            print_report(&report);
+
+            println!("Extracted {} tensors.", report.tensors_written);
+        }
+        Commands::Merge(merge_args) => {
+            let MergeArgs {
+                file_paths,
+                output,
+                on_conflict,
+                metadata,
+                headroom,
+            } = merge_args;
+
+            let (bar, progress) = report::progress_bar();
+            let report = Merger::new(&file_paths)
+                .with_conflict_policy(on_conflict)
+                .with_metadata_merge(metadata)
+                .with_headroom(headroom)
+                .with_progress(progress)
+                .write(&output);
+            bar.finish_and_clear();
+            let report = report?;
+
+            for name in &report.removed_tensors {
+                println!("- {}", name);
+            }
+            for (old, new) in &report.renamed_tensors {
+                println!("~ {} -> {}", old, new);
+            }
+            print_report(&report);
+
+            println!(
+                "Merged {} tensors from {} files.",
+                report.tensors_written,
+                file_paths.len()
+            );
+        }
+        Commands::Shard(shard_args) => {
+            let ShardArgs {
+                file_path,
+                max_size,
+                output,
+            } = shard_args;
+            let output_dir = match output {
+                Some(output) => output,
+                None => file_path.parent().map(PathBuf::from).unwrap_or_default(),
+            };
+
+            let (bar, progress) = report::progress_bar();
+            let report = shard(&file_path, &output_dir, max_size, Some(progress));
+            bar.finish_and_clear();
+            let report = report?;
+
+            for shard in &report.shards {
+                println!(
+                    "{}: {} tensors, {}",
+                    shard.output.display(),
+                    shard.tensors_written,
+                    pretty_byte_size(shard.bytes_copied)
+                );
+            }
+            println!(
+                "Wrote {} shards and {} ({} of tensor data).",
+                report.shards.len(),
+                report.index_path.display(),
+                pretty_byte_size(report.total_size)
+            );
+        }
+        Commands::Unshard(unshard_args) => {
+            let UnshardArgs {
+                index_path,
+                output,
+                headroom,
+            } = unshard_args;
 
-            safetensors.clear_metadata(&output)?;
+            let (bar, progress) = report::progress_bar();
+            let report = unshard(&index_path, &output, headroom, Some(progress));
+            bar.finish_and_clear();
+            let report = report?;
 
-            println!("Metadata removed successfully.");
+            for name in &report.removed_tensors {
+                println!("- {} (not in the index)", name);
+            }
+            print_report(&report);
+
+            println!("Joined {} tensors.", report.tensors_written);
+        }
+        Commands::Hash(hash_args) => hash_files(hash_args)?,
+        Commands::Compare(compare_args) => compare_files(compare_args)?,
+        Commands::Duplicates(duplicates_args) => find_duplicates(duplicates_args)?,
+        Commands::Dedupe(dedupe_args) => dedupe_files(dedupe_args)?,
+        Commands::Manifest(manifest_args) => match manifest_args.command {
+            ManifestCommands::Create(create_args) => {
+                let ManifestCreateArgs {
+                    file_path,
+                    output,
+                    algorithm,
+                } = create_args;
+
+                let (bar, progress) = report::progress_bar();
+                let manifest = Manifest::create(&file_path, algorithm, Some(&progress));
+                bar.finish_and_clear();
+                let manifest = manifest?;
+
+                match output {
+                    Some(output) => {
+                        manifest.write(&output)?;
+                        println!(
+                            "Wrote the {} digests of {} tensors to {}",
+                            manifest.algorithm,
+                            manifest.tensors.len(),
+                            output.display()
+                        );
+                    }
+                    None => print!("{}", manifest.to_json()?),
+                }
+            }
+            ManifestCommands::Verify(verify_args) => verify_manifest(verify_args)?,
+        },
+        Commands::Convert(convert_args) => {
+            let ConvertArgs {
+                file_path,
+                dtype,
+                include,
+                exclude,
+                output_args,
+            } = convert_args;
+
+            let conversion = DtypeConversion::new(dtype)?
+                .with_include(include)
+                .with_exclude(exclude);
+            let report = output_args.write(&file_path, |safetensors, output| {
+                safetensors.convert_tensors(output, &conversion)
+            })?;
+
+            for conversion in &report.converted_tensors {
+                println!(
+                    "~ {}: {} -> {}",
+                    conversion.name, conversion.from, conversion.to
+                );
+                if conversion.has_issues() {
+                    println!(
+                        "  warning: {} overflowed, {} underflowed to zero, {} NaN (of {} values)",
+                        conversion.overflow,
+                        conversion.underflow,
+                        conversion.nan,
+                        conversion.elements
+                    );
+                }
+            }
+            print_report(&report);
+
+            let with_issues = report
+                .converted_tensors
+                .iter()
+                .filter(|conversion| conversion.has_issues())
+                .count();
+            println!(
+                "Converted {} tensors to {}.",
+                report.converted_tensors.len(),
+                dtype
+            );
+            if with_issues > 0 {
+                eprintln!(
+                    "Warning: {} tensors have values that overflowed, underflowed or are NaN.",
+                    with_issues
+                );
+            }
         }
     }
 
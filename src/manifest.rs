@@ -0,0 +1,412 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anyhow::Result;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::atomic::AtomicFile;
+use crate::metadata::Dtype;
+use crate::named::named_enum;
+use crate::parser::{LocalParser, MetadataParser};
+use crate::progress::Progress;
+
+/// The size of the chunks of tensor data read and hashed at once.
+const DIGEST_CHUNK_SIZE: usize = 8 << 20;
+
+/// The hash function of the tensor digests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DigestAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+named_enum!(DigestAlgorithm, "digest algorithm", {
+    Sha256 => "sha256",
+    Blake3 => "blake3",
+});
+
+impl DigestAlgorithm {
+    /// The lowercase hex digest of everything the reader returns.
+    fn digest<R: Read>(&self, reader: &mut R, length: u64, done: &dyn Fn(u64)) -> Result<String> {
+        let mut buffer = vec![0; DIGEST_CHUNK_SIZE.min(length as usize)];
+        let mut sha256 = Sha256::new();
+        let mut blake3 = blake3::Hasher::new();
+        let mut remaining = length;
+
+        while remaining > 0 {
+            let chunk = &mut buffer[..DIGEST_CHUNK_SIZE.min(remaining as usize)];
+            reader.read_exact(chunk)?;
+            match self {
+                DigestAlgorithm::Sha256 => sha256.update(&*chunk),
+                DigestAlgorithm::Blake3 => {
+                    blake3.update(chunk);
+                }
+            }
+            remaining -= chunk.len() as u64;
+            done(chunk.len() as u64);
+        }
+
+        let digest = match self {
+            DigestAlgorithm::Sha256 => hex::encode(sha256.finalize()),
+            DigestAlgorithm::Blake3 => blake3.finalize().to_hex().to_string(),
+        };
+
+        Ok(digest)
+    }
+}
+
+/// A tensor of the manifest, with the digest of its data.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TensorEntry {
+    pub name: String,
+    pub dtype: Dtype,
+    pub shape: Vec<i64>,
+    pub data_offsets: [i64; 2],
+    pub digest: String,
+}
+
+/// The per-tensor hashes of a safetensors file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub algorithm: DigestAlgorithm,
+
+    /// The tensors, in the order of their data.
+    pub tensors: Vec<TensorEntry>,
+}
+
+/// How a tensor of the file differs from the manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TensorMismatch {
+    /// In the manifest, but not in the file.
+    Missing(TensorEntry),
+    /// In the file, but not in the manifest.
+    Unexpected(TensorEntry),
+    /// The dtype, shape or data differ.
+    Changed {
+        expected: TensorEntry,
+        actual: TensorEntry,
+    },
+}
+
+impl TensorMismatch {
+    pub fn name(&self) -> &str {
+        match self {
+            TensorMismatch::Missing(entry) | TensorMismatch::Unexpected(entry) => &entry.name,
+            TensorMismatch::Changed { expected, .. } => &expected.name,
+        }
+    }
+}
+
+/// The result of [`Manifest::verify`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestReport {
+    /// The number of tensors that match the manifest.
+    pub matched: usize,
+    /// The tensors that differ, by name.
+    pub mismatches: Vec<TensorMismatch>,
+}
+
+impl ManifestReport {
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// Tensors of a file with identical data, e.g. tied embeddings and `lm_head`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateGroup {
+    pub digest: String,
+    /// The byte size of each tensor.
+    pub byte_size: u64,
+    /// The tensors, in the order of their data.
+    pub tensors: Vec<TensorEntry>,
+}
+
+impl DuplicateGroup {
+    /// The bytes saved by storing the data once. Tensors that already share
+    /// their data offsets are stored once.
+    pub fn savings(&self) -> u64 {
+        let ranges = self
+            .tensors
+            .iter()
+            .map(|entry| entry.data_offsets)
+            .collect::<BTreeSet<_>>();
+        (ranges.len() as u64 - 1) * self.byte_size
+    }
+}
+
+impl Manifest {
+    /// Hash the data of each tensor of the file.
+    ///
+    /// The tensors are hashed in parallel, each reading its own range of the file.
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        algorithm: DigestAlgorithm,
+        progress: Option<&Progress>,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let info = LocalParser::new(path).parse_header_info()?;
+        let data_start = info.data_start();
+
+        let mut weights = info.header.weights.iter().collect::<Vec<_>>();
+        weights.sort_by_key(|(name, weight)| (weight.data_offsets[0], *name));
+
+        let total = info.tensor_bytes();
+        let done = AtomicU64::new(0);
+        if let Some(progress) = progress {
+            progress.update(0, total);
+        }
+        let report = |length: u64| {
+            let done = done.fetch_add(length, Ordering::Relaxed) + length;
+            if let Some(progress) = progress {
+                progress.update(done, total);
+            }
+        };
+
+        let tensors = weights
+            .into_par_iter()
+            .map(|(name, weight)| {
+                let length = weight.byte_size() as u64;
+                let mut file = File::open(path)?;
+                file.seek(SeekFrom::Start(data_start + weight.data_offsets[0] as u64))?;
+                let digest = algorithm
+                    .digest(&mut file, length, &report)
+                    .map_err(|err| anyhow::anyhow!("{}: {}", name, err))?;
+
+                Ok(TensorEntry {
+                    name: name.clone(),
+                    dtype: weight.dtype,
+                    shape: weight.shape.clone(),
+                    data_offsets: weight.data_offsets,
+                    digest,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { algorithm, tensors })
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let text = fs::read_to_string(path.as_ref())?;
+        serde_json::from_str(&text)
+            .map_err(|err| anyhow::anyhow!("{}: {}", path.as_ref().display(), err))
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)? + "\n")
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut file = AtomicFile::create(path)?;
+        file.write_all(self.to_json()?.as_bytes())?;
+        file.commit()
+    }
+
+    /// Hash the tensors of the file with the algorithm of the manifest and
+    /// report the tensors that differ.
+    ///
+    /// Only the name, dtype, shape and digest are compared: a tensor whose
+    /// data moved to other offsets still matches.
+    pub fn verify<P: AsRef<Path>>(
+        &self,
+        path: P,
+        progress: Option<&Progress>,
+    ) -> Result<ManifestReport> {
+        let actual = Manifest::create(path, self.algorithm, progress)?;
+        Ok(self.compare(&actual))
+    }
+
+    /// The groups of tensors with identical data, the largest savings first.
+    ///
+    /// The dtypes and shapes of a group may differ. Empty tensors are ignored.
+    pub fn duplicates(&self) -> Vec<DuplicateGroup> {
+        let mut groups: BTreeMap<&str, Vec<&TensorEntry>> = BTreeMap::new();
+        for entry in &self.tensors {
+            if entry.data_offsets[1] > entry.data_offsets[0] {
+                groups.entry(&entry.digest).or_default().push(entry);
+            }
+        }
+
+        let mut duplicates = groups
+            .into_iter()
+            .filter(|(_, tensors)| tensors.len() > 1)
+            .map(|(digest, tensors)| DuplicateGroup {
+                digest: digest.to_string(),
+                byte_size: (tensors[0].data_offsets[1] - tensors[0].data_offsets[0]) as u64,
+                tensors: tensors.into_iter().cloned().collect(),
+            })
+            .collect::<Vec<_>>();
+        duplicates.sort_by(|a, b| {
+            (b.savings(), &a.tensors[0].name).cmp(&(a.savings(), &b.tensors[0].name))
+        });
+
+        duplicates
+    }
+
+    /// Compare the tensors of another manifest with this one.
+    pub fn compare(&self, actual: &Manifest) -> ManifestReport {
+        let mut actual_tensors = actual
+            .tensors
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut matched = 0;
+        let mut mismatches = Vec::new();
+        for expected in &self.tensors {
+            match actual_tensors.remove(expected.name.as_str()) {
+                None => mismatches.push(TensorMismatch::Missing(expected.clone())),
+                Some(actual)
+                    if actual.dtype == expected.dtype
+                        && actual.shape == expected.shape
+                        && actual.digest == expected.digest =>
+                {
+                    matched += 1
+                }
+                Some(actual) => mismatches.push(TensorMismatch::Changed {
+                    expected: expected.clone(),
+                    actual: actual.clone(),
+                }),
+            }
+        }
+        mismatches.extend(
+            actual_tensors
+                .into_values()
+                .map(|entry| TensorMismatch::Unexpected(entry.clone())),
+        );
+        mismatches.sort_by(|a, b| a.name().cmp(b.name()));
+
+        ManifestReport {
+            matched,
+            mismatches,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::writer::fixtures::write_model;
+
+    #[test]
+    fn test_create_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.safetensors");
+        write_model(
+            &path,
+            &[
+                ("b", Dtype::Uint8, b"abc".to_vec()),
+                ("a", Dtype::Uint8, b"".to_vec()),
+            ],
+        );
+
+        let manifest = Manifest::create(&path, DigestAlgorithm::Sha256, None).unwrap();
+        assert_eq!(manifest.tensors.len(), 2);
+        let b = manifest.tensors.iter().find(|t| t.name == "b").unwrap();
+        assert_eq!(b.shape, vec![3]);
+        assert_eq!(
+            b.digest,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let manifest = Manifest::create(&path, DigestAlgorithm::Blake3, None).unwrap();
+        let b = manifest.tensors.iter().find(|t| t.name == "b").unwrap();
+        assert_eq!(b.digest, blake3::hash(b"abc").to_hex().as_str());
+
+        let manifest_path = dir.path().join("manifest.json");
+        manifest.write(&manifest_path).unwrap();
+        assert_eq!(Manifest::read(&manifest_path).unwrap(), manifest);
+    }
+
+    #[test]
+    fn test_verify_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.safetensors");
+        write_model(
+            &path,
+            &[
+                ("a", Dtype::Uint8, b"abc".to_vec()),
+                ("b", Dtype::Uint8, b"def".to_vec()),
+                ("c", Dtype::Uint8, b"ghi".to_vec()),
+            ],
+        );
+        let manifest = Manifest::create(&path, DigestAlgorithm::Sha256, None).unwrap();
+
+        let report = manifest.verify(&path, None).unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.matched, 3);
+
+        // the tensors moved, but only b changed
+        write_model(
+            &path,
+            &[
+                ("d", Dtype::Uint8, b"jkl".to_vec()),
+                ("c", Dtype::Uint8, b"ghi".to_vec()),
+                ("b", Dtype::Uint8, b"dex".to_vec()),
+            ],
+        );
+        let report = manifest.verify(&path, None).unwrap();
+        assert_eq!(report.matched, 1);
+        let mismatches = report
+            .mismatches
+            .iter()
+            .map(|mismatch| match mismatch {
+                TensorMismatch::Missing(entry) => format!("-{}", entry.name),
+                TensorMismatch::Unexpected(entry) => format!("+{}", entry.name),
+                TensorMismatch::Changed { expected, .. } => format!("~{}", expected.name),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(mismatches, vec!["-a", "~b", "+d"]);
+    }
+
+    #[test]
+    fn test_duplicates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.safetensors");
+        let embedding = [7u8; 32];
+        write_model(
+            &path,
+            &[
+                ("embed_tokens.weight", Dtype::Uint8, embedding.to_vec()),
+                ("norm.weight", Dtype::Uint8, b"abcd".to_vec()),
+                ("lm_head.weight", Dtype::Uint8, embedding.to_vec()),
+                ("empty", Dtype::Uint8, b"".to_vec()),
+                ("empty2", Dtype::Uint8, b"".to_vec()),
+                ("shared.0", Dtype::Uint8, b"wxyz".to_vec()),
+                ("shared.1", Dtype::Uint8, b"wxyz".to_vec()),
+                ("shared.2", Dtype::Uint8, b"wxyz".to_vec()),
+            ],
+        );
+
+        let manifest = Manifest::create(&path, DigestAlgorithm::Blake3, None).unwrap();
+        let duplicates = manifest.duplicates();
+        let names = duplicates
+            .iter()
+            .map(|group| {
+                let names = group.tensors.iter().map(|entry| entry.name.as_str());
+                names.collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec![
+                vec!["embed_tokens.weight", "lm_head.weight"],
+                vec!["shared.0", "shared.1", "shared.2"],
+            ]
+        );
+        assert_eq!(duplicates[0].savings(), 32);
+        assert_eq!(duplicates[1].savings(), 8);
+
+        // tensors that already share their data are stored once
+        let mut group = duplicates[1].clone();
+        group.tensors[1].data_offsets = group.tensors[0].data_offsets;
+        assert_eq!(group.savings(), 4);
+    }
+}
@@ -0,0 +1,413 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::file::WriteReport;
+use crate::hash::HashKind;
+use crate::named::named_enum;
+use crate::parser::{HeaderInfo, LocalParser, MetadataParser};
+use crate::progress::Progress;
+use crate::writer::{SafetensorsWriter, TensorSource};
+
+/// What to do when several files have a tensor with the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    /// Fail without writing anything.
+    #[default]
+    Error,
+    /// Keep the tensor of the first file that has it.
+    PreferFirst,
+    /// Keep the tensor of the last file that has it.
+    PreferLast,
+    /// Keep all of them, prefixed with the name of their file, e.g. `unet.time_embed.weight`.
+    Prefix,
+}
+
+named_enum!(ConflictPolicy, "conflict policy", {
+    Error => "error",
+    PreferFirst => "prefer-first",
+    PreferLast => "prefer-last",
+    Prefix => "prefix",
+});
+
+/// How the metadata of the files is combined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MetadataMerge {
+    /// Keep the keys of all files; the first file wins on conflicting values.
+    #[default]
+    PreferFirst,
+    /// Keep the keys of all files; the last file wins on conflicting values.
+    PreferLast,
+    /// Keep only the metadata of the first file.
+    FirstOnly,
+    /// Keep the keys of all files and fail on conflicting values.
+    Error,
+    /// Write no metadata.
+    Drop,
+}
+
+named_enum!(MetadataMerge, "metadata merge strategy", {
+    PreferFirst => "prefer-first",
+    PreferLast => "prefer-last",
+    FirstOnly => "first-only",
+    Error => "error",
+    Drop => "drop",
+});
+
+impl MetadataMerge {
+    /// Combine the metadata of the files, in order.
+    pub fn apply(&self, maps: Vec<BTreeMap<String, String>>) -> Result<BTreeMap<String, String>> {
+        let mut merged = BTreeMap::new();
+
+        for (index, map) in maps.into_iter().enumerate() {
+            for (key, value) in map {
+                match (self, merged.get(&key)) {
+                    (MetadataMerge::Drop, _) => {}
+                    (MetadataMerge::FirstOnly, _) if index > 0 => {}
+                    (MetadataMerge::PreferFirst, Some(_)) => {}
+                    (MetadataMerge::Error, Some(existing)) if existing != &value => {
+                        return Err(anyhow::anyhow!(
+                            "Conflicting values for the metadata key {}: {:?} and {:?}",
+                            key,
+                            existing,
+                            value
+                        ));
+                    }
+                    _ => {
+                        merged.insert(key, value);
+                    }
+                }
+            }
+        }
+
+        Ok(merged)
+    }
+}
+
+/// The name used to prefix the tensors of a file, i.e. its file name without the extension.
+fn source_name(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Combines the tensors of several safetensors files into one file.
+///
+/// The tensor data is copied directly from the byte ranges of the inputs.
+/// In the report, `removed_tensors` lists the duplicates that were dropped and
+/// `renamed_tensors` the prefixed ones. The stored hashes of the inputs are
+/// dropped before their metadata is combined.
+#[derive(Debug, Clone)]
+pub struct Merger {
+    paths: Vec<PathBuf>,
+    conflict_policy: ConflictPolicy,
+    metadata_merge: MetadataMerge,
+    headroom: usize,
+    progress: Option<Progress>,
+}
+
+impl Merger {
+    pub fn new<P: AsRef<Path>>(paths: &[P]) -> Self {
+        Self {
+            paths: paths
+                .iter()
+                .map(|path| path.as_ref().to_path_buf())
+                .collect(),
+            conflict_policy: ConflictPolicy::default(),
+            metadata_merge: MetadataMerge::default(),
+            headroom: 0,
+            progress: None,
+        }
+    }
+
+    pub fn with_conflict_policy(mut self, conflict_policy: ConflictPolicy) -> Self {
+        self.conflict_policy = conflict_policy;
+        self
+    }
+
+    pub fn with_metadata_merge(mut self, metadata_merge: MetadataMerge) -> Self {
+        self.metadata_merge = metadata_merge;
+        self
+    }
+
+    /// Reserve extra bytes in the header, so that later edits can be done in place.
+    pub fn with_headroom(mut self, headroom: usize) -> Self {
+        self.headroom = headroom;
+        self
+    }
+
+    /// Report the bytes of tensor data copied.
+    pub fn with_progress(mut self, progress: Progress) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// The indices of the files that have each tensor name.
+    fn owners(infos: &[HeaderInfo]) -> HashMap<&str, Vec<usize>> {
+        let mut owners = HashMap::<&str, Vec<usize>>::new();
+        for (index, info) in infos.iter().enumerate() {
+            for name in info.header.weights.keys() {
+                owners.entry(name).or_default().push(index);
+            }
+        }
+        owners
+    }
+
+    fn check_conflicts(&self, owners: &HashMap<&str, Vec<usize>>) -> Result<()> {
+        let mut conflicts = owners
+            .iter()
+            .filter(|(_, indices)| indices.len() > 1)
+            .collect::<Vec<_>>();
+        if conflicts.is_empty() {
+            return Ok(());
+        }
+        conflicts.sort();
+
+        match self.conflict_policy {
+            ConflictPolicy::Error => {
+                let (name, indices) = conflicts[0];
+                Err(anyhow::anyhow!(
+                    "{} duplicate tensor names, e.g. {} in {} and {}. Choose a conflict policy to merge them.",
+                    conflicts.len(),
+                    name,
+                    self.paths[indices[0]].display(),
+                    self.paths[indices[1]].display()
+                ))
+            }
+            ConflictPolicy::Prefix => {
+                for (_, indices) in conflicts {
+                    for (i, first) in indices.iter().enumerate() {
+                        for second in &indices[i + 1..] {
+                            let name = source_name(&self.paths[*first]);
+                            if name == source_name(&self.paths[*second]) {
+                                return Err(anyhow::anyhow!(
+                                    "{} and {} have the same name {}, so their tensors cannot be prefixed",
+                                    self.paths[*first].display(),
+                                    self.paths[*second].display(),
+                                    name
+                                ));
+                            }
+                        }
+                    }
+                }
+                Ok(())
+            }
+            ConflictPolicy::PreferFirst | ConflictPolicy::PreferLast => Ok(()),
+        }
+    }
+
+    /// Write the merged file.
+    pub fn write<P: AsRef<Path>>(self, output_path: P) -> Result<WriteReport> {
+        if self.paths.is_empty() {
+            return Err(anyhow::anyhow!("No files to merge"));
+        }
+
+        let infos = self
+            .paths
+            .iter()
+            .map(|path| {
+                LocalParser::new(path)
+                    .parse_header_info()
+                    .map_err(|err| anyhow::anyhow!("{}: {}", path.display(), err))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let owners = Self::owners(&infos);
+        self.check_conflicts(&owners)?;
+
+        // the stored hashes only apply to the data of their own file
+        let maps = infos
+            .iter()
+            .map(|info| {
+                let mut map = match &info.header.metadata {
+                    Some(metadata) => metadata.to_map()?,
+                    None => BTreeMap::new(),
+                };
+                for key in HashKind::metadata_keys() {
+                    map.remove(key);
+                }
+                Ok(map)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut writer = SafetensorsWriter::new()
+            .with_headroom(self.headroom)
+            .with_metadata(self.metadata_merge.apply(maps)?);
+        if let Some(progress) = &self.progress {
+            writer = writer.with_progress(progress.clone());
+        }
+
+        let mut removed_tensors = Vec::new();
+        let mut renamed_tensors = Vec::new();
+        for (index, (path, info)) in self.paths.iter().zip(&infos).enumerate() {
+            let prefix = source_name(path);
+            let selection = writer.add_tensors_from(
+                info,
+                |name| {
+                    let indices = &owners[name];
+                    if indices.len() == 1 {
+                        return Some(name.to_string());
+                    }
+                    match self.conflict_policy {
+                        ConflictPolicy::PreferFirst if indices[0] != index => None,
+                        ConflictPolicy::PreferLast if indices[indices.len() - 1] != index => None,
+                        ConflictPolicy::Prefix => Some(format!("{}.{}", prefix, name)),
+                        _ => Some(name.to_string()),
+                    }
+                },
+                |offset, length| TensorSource::File {
+                    path: path.clone(),
+                    offset,
+                    length,
+                },
+            )?;
+            removed_tensors.extend(selection.skipped);
+            renamed_tensors.extend(selection.renamed);
+        }
+
+        let report = writer.write(output_path)?;
+
+        Ok(WriteReport {
+            removed_tensors,
+            renamed_tensors,
+            ..report
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::metadata::Dtype;
+    use crate::validate::validate;
+
+    fn write_file(path: &Path, metadata: &[(&str, &str)], tensors: &[(&str, u8)]) {
+        let mut writer = SafetensorsWriter::new().with_metadata(
+            metadata
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        );
+        for (name, value) in tensors {
+            writer
+                .add_tensor(name, Dtype::Uint8, vec![2], vec![*value; 2].into())
+                .unwrap();
+        }
+        writer.write(path).unwrap();
+    }
+
+    fn read_tensors(path: &Path) -> BTreeMap<String, u8> {
+        let info = LocalParser::new(path).parse_header_info().unwrap();
+        assert_eq!(validate(&info), vec![]);
+        let data = std::fs::read(path).unwrap();
+        info.header
+            .weights
+            .iter()
+            .map(|(name, weight)| {
+                let offset = info.data_start() as usize + weight.data_offsets[0] as usize;
+                (name.clone(), data[offset])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_merge() {
+        let dir = tempfile::tempdir().unwrap();
+        let unet = dir.path().join("unet.safetensors");
+        let vae = dir.path().join("vae.safetensors");
+        let output = dir.path().join("merged.safetensors");
+        write_file(
+            &unet,
+            &[("format", "pt"), ("title", "unet")],
+            &[("a", 1), ("shared", 2)],
+        );
+        write_file(
+            &vae,
+            &[("format", "pt"), ("title", "vae")],
+            &[("b", 3), ("shared", 4)],
+        );
+        let paths = [&unet, &vae];
+
+        assert!(Merger::new(&paths).write(&output).is_err());
+        assert!(!output.exists());
+
+        let report = Merger::new(&paths)
+            .with_conflict_policy(ConflictPolicy::PreferFirst)
+            .write(&output)
+            .unwrap();
+        assert_eq!(report.tensors_written, 3);
+        assert_eq!(report.removed_tensors, vec!["shared"]);
+        let tensors = read_tensors(&output);
+        assert_eq!((tensors["a"], tensors["b"], tensors["shared"]), (1, 3, 2));
+
+        Merger::new(&paths)
+            .with_conflict_policy(ConflictPolicy::PreferLast)
+            .write(&output)
+            .unwrap();
+        assert_eq!(read_tensors(&output)["shared"], 4);
+
+        let report = Merger::new(&paths)
+            .with_conflict_policy(ConflictPolicy::Prefix)
+            .with_metadata_merge(MetadataMerge::PreferLast)
+            .write(&output)
+            .unwrap();
+        assert_eq!(report.renamed_tensors.len(), 2);
+        let tensors = read_tensors(&output);
+        assert_eq!(
+            tensors.keys().collect::<Vec<_>>(),
+            vec!["a", "b", "unet.shared", "vae.shared"]
+        );
+        assert_eq!((tensors["unet.shared"], tensors["vae.shared"]), (2, 4));
+
+        let info = LocalParser::new(&output).parse_header_info().unwrap();
+        let metadata = info.header.metadata.unwrap().to_map().unwrap();
+        assert_eq!(metadata["title"], "vae");
+
+        // the hashes of the inputs are neither kept nor conflicting
+        write_file(&unet, &[("sshs_model_hash", "1111")], &[("a", 1)]);
+        write_file(&vae, &[("sshs_model_hash", "2222")], &[("b", 3)]);
+        Merger::new(&paths)
+            .with_metadata_merge(MetadataMerge::Error)
+            .write(&output)
+            .unwrap();
+        let info = LocalParser::new(&output).parse_header_info().unwrap();
+        assert!(info.header.metadata.is_none());
+    }
+
+    #[test]
+    fn test_metadata_merge() {
+        let maps = vec![
+            BTreeMap::from([
+                ("format".to_string(), "pt".to_string()),
+                ("title".to_string(), "first".to_string()),
+            ]),
+            BTreeMap::from([
+                ("format".to_string(), "pt".to_string()),
+                ("title".to_string(), "last".to_string()),
+                ("author".to_string(), "someone".to_string()),
+            ]),
+        ];
+
+        let merged = MetadataMerge::PreferFirst.apply(maps.clone()).unwrap();
+        assert_eq!((merged["title"].as_str(), merged.len()), ("first", 3));
+        let merged = MetadataMerge::PreferLast.apply(maps.clone()).unwrap();
+        assert_eq!((merged["title"].as_str(), merged.len()), ("last", 3));
+        let merged = MetadataMerge::FirstOnly.apply(maps.clone()).unwrap();
+        assert_eq!(merged, maps[0]);
+        assert!(MetadataMerge::Drop.apply(maps.clone()).unwrap().is_empty());
+        assert!(MetadataMerge::Error.apply(maps.clone()).is_err());
+        assert!(MetadataMerge::Error
+            .apply(vec![maps[0].clone(), maps[0].clone()])
+            .is_ok());
+
+        assert_eq!(
+            "first-only".parse::<MetadataMerge>().unwrap(),
+            MetadataMerge::FirstOnly
+        );
+        assert_eq!(
+            "prefix".parse::<ConflictPolicy>().unwrap(),
+            ConflictPolicy::Prefix
+        );
+        assert!("unknown".parse::<ConflictPolicy>().is_err());
+    }
+}
@@ -1,8 +1,10 @@
 // ref: https://huggingface.co/docs/safetensors/index#format
 
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::BTreeMap;
 use std::fmt::Display;
+use std::str::FromStr;
 
 // {name: weight}
 pub type Weights = BTreeMap<String, Weight>;
@@ -10,7 +12,7 @@ pub type Weights = BTreeMap<String, Weight>;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Header {
     /// Metadata information about the model.
-    #[serde(rename = "__metadata__")]
+    #[serde(rename = "__metadata__", skip_serializing_if = "Option::is_none")]
     pub metadata: Option<Metadata>,
 
     /// The model's weights, stored as a map from tensor names to weights.
@@ -27,7 +29,19 @@ pub struct Weight {
     pub data_offsets: [i64; 2], // [begin, end]
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl Weight {
+    /// The number of elements in the tensor.
+    pub fn num_elements(&self) -> i64 {
+        self.shape.iter().product::<i64>()
+    }
+
+    /// The number of bytes the tensor occupies in the data section.
+    pub fn byte_size(&self) -> i64 {
+        self.data_offsets[1] - self.data_offsets[0]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum Dtype {
     #[serde(rename = "F64")]
     Fp64,
@@ -80,6 +94,46 @@ impl Display for Dtype {
     }
 }
 
+impl Dtype {
+    /// The size of a single element in bits.
+    pub fn bits(&self) -> usize {
+        match self {
+            Dtype::Fp64 | Dtype::Int64 => 64,
+            Dtype::Fp32 | Dtype::Int32 => 32,
+            Dtype::Fp16 | Dtype::Bf16 | Dtype::Int16 => 16,
+            Dtype::Int8 | Dtype::Uint8 | Dtype::Bool | Dtype::Fp8E4M3 | Dtype::Fp8E5M2 => 8,
+            Dtype::Int4 | Dtype::Uint4 => 4,
+        }
+    }
+}
+
+impl FromStr for Dtype {
+    type Err = anyhow::Error;
+
+    /// Accepts both the safetensors names (`F16`) and the display names (`float16`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let dtype = match s.to_lowercase().as_str() {
+            "f64" | "float64" => Dtype::Fp64,
+            "f32" | "float32" => Dtype::Fp32,
+            "f16" | "float16" | "fp16" => Dtype::Fp16,
+            "bf16" | "bfloat16" => Dtype::Bf16,
+            "i64" | "int64" => Dtype::Int64,
+            "i32" | "int32" => Dtype::Int32,
+            "i16" | "int16" => Dtype::Int16,
+            "i8" | "int8" => Dtype::Int8,
+            "i4" | "int4" => Dtype::Int4,
+            "u8" | "uint8" => Dtype::Uint8,
+            "u4" | "uint4" => Dtype::Uint4,
+            "bool" => Dtype::Bool,
+            "f8_e4m3" | "float8_e4m3" | "fp8_e4m3" => Dtype::Fp8E4M3,
+            "f8_e5m2" | "float8_e5m2" | "fp8_e5m2" => Dtype::Fp8E5M2,
+            _ => return Err(anyhow::anyhow!("Unknown dtype: {}", s)),
+        };
+
+        Ok(dtype)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TensorFormart {
     #[serde(rename = "pt")]
@@ -108,12 +162,15 @@ impl Display for TensorFormart {
 
 /// Stability AI Model Metadata Standard.
 /// See https://github.com/Stability-AI/ModelSpec?tab=readme-ov-file#specification
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ModelSpec {
     /// Mandatory identifier key, indicates the presence and version of this specification.
     /// Trainer tools that support the spec should automatically emit this key,
     /// set to the version they support.
-    #[serde(rename = "modelspec.sai_model_spec")]
+    #[serde(
+        rename = "modelspec.sai_model_spec",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub sai_model_spec: Option<String>,
 
     /// The specific classifier of the model's architecture,
@@ -129,7 +186,10 @@ pub struct ModelSpec {
     /// a LoRA trained to be applied to a `stable-diffusion-v1` model.
     /// Implementations are not required to parse this separator
     /// unless it is useful to them to process it.
-    #[serde(rename = "modelspec.architecture")]
+    #[serde(
+        rename = "modelspec.architecture",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub architecture: Option<String>,
 
     /// A reliably static string that identifies the standard
@@ -139,7 +199,10 @@ pub struct ModelSpec {
     /// `https://github.com/Stability-AI/generative-models` or
     /// any other string, as long as you do not change it between
     /// different models of the same format.
-    #[serde(rename = "modelspec.implementation")]
+    #[serde(
+        rename = "modelspec.implementation",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub implementation: Option<String>,
 
     /// A title unique to the specific model. Generally for end-user training
@@ -147,7 +210,7 @@ pub struct ModelSpec {
     /// provided as just eg the original file name or training run name.
     /// Inference UIs are encouraged to display this title to users in any
     /// model selector tools.
-    #[serde(rename = "modelspec.title")]
+    #[serde(rename = "modelspec.title", skip_serializing_if = "Option::is_none")]
     pub title: Option<String>,
 
     /// A user-friendly textual description of the model. This may describe what
@@ -158,16 +221,19 @@ pub struct ModelSpec {
     /// end users. Usage of markdown formatting is encouraged, and UIs are
     /// encouraged to format the markdown properly (displaying as plaintext is
     /// also acceptable where markdown is not possible).
-    #[serde(rename = "modelspec.description")]
+    #[serde(
+        rename = "modelspec.description",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub description: Option<String>,
 
     /// The name or identity of the company or individual that created a model.
     /// Can even be a username or personal profile link.
-    #[serde(rename = "modelspec.author")]
+    #[serde(rename = "modelspec.author", skip_serializing_if = "Option::is_none")]
     pub author: Option<String>,
 
     /// The precise date that a model was created or published, in any ISO-8601-compliant format.
-    #[serde(rename = "modelspec.date")]
+    #[serde(rename = "modelspec.date", skip_serializing_if = "Option::is_none")]
     pub date: Option<String>,
 
     /// A hash of all tensor content (ie excluding the header section), with 0x prefix,
@@ -180,19 +246,25 @@ pub struct ModelSpec {
     /// strongly encouraged to calculate the hash and emit it correctly automatically
     /// whenever saving a model. This is not a MUST because hash algorithms may change
     /// with time, and the format should not be locked in to just one.
-    #[serde(rename = "modelspec.hash_sha256")]
+    #[serde(
+        rename = "modelspec.hash_sha256",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub hash_sha256: Option<String>,
 
     /// A minimum required version of the specified `implementation` codebase.
     /// This can be an actual version ID (eg `2.0.0`) or a commit hash.
-    #[serde(rename = "modelspec.implementation_version")]
+    #[serde(
+        rename = "modelspec.implementation_version",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub implementation_version: Option<String>,
 
     /// If the model is under any form of license terms or restrictions, they should be
     /// clearly identified here. The model creator may at their own discretion (A) provide
     /// the name of the license, (B) provide a link to the license terms, or (C) emit
     /// the license terms in full in this slot.
-    #[serde(rename = "modelspec.license")]
+    #[serde(rename = "modelspec.license", skip_serializing_if = "Option::is_none")]
     pub license: Option<String>,
 
     /// Usage hint(s) for the model, where applicable. This field should be short,
@@ -200,7 +272,10 @@ pub struct ModelSpec {
     /// the model. Inference UIs are encouraged to make this information readily visible
     /// to the user when it is present. For example, a small SD finetune model would use
     /// this to list trigger words.
-    #[serde(rename = "modelspec.usage_hint")]
+    #[serde(
+        rename = "modelspec.usage_hint",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub usage_hint: Option<String>,
 
     /// A (very small!) thumbnail icon in data-image format to be provided as a preview
@@ -208,7 +283,10 @@ pub struct ModelSpec {
     /// kilobytes, and don't get officially limited until 100 megabytes, so a small jpeg in
     /// data-image format does not significantly increase the size. 256x256 is a recommended
     /// size and aspect ratio (square).
-    #[serde(rename = "modelspec.thumbnail")]
+    #[serde(
+        rename = "modelspec.thumbnail",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub thumbnail: Option<String>,
 
     /// An optional user-specified comma-separated list of category/tag labels for a finetuned
@@ -217,14 +295,17 @@ pub struct ModelSpec {
     /// to organize and allow easy filtering by tag. When in doubt on what tag(s) to use,
     /// the model maker is encouraged to look at the category label on other similar models,
     /// or choose a new label if they're the first to make a model in a category.
-    #[serde(rename = "modelspec.tags")]
+    #[serde(rename = "modelspec.tags", skip_serializing_if = "Option::is_none")]
     pub tags: Option<String>,
 
     /// If the model was created by merging other models, you may provide a comma-separated
     /// list of the source models here. More details about merging or creation process
     /// may be included in the `description` key or in nonstandard keys. Models that do not
     /// provide this key are presumed to have been uniquely trained rather than merged.
-    #[serde(rename = "modelspec.merged_from")]
+    #[serde(
+        rename = "modelspec.merged_from",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub merged_from: Option<String>,
 
     // Image Generation Models
@@ -236,7 +317,10 @@ pub struct ModelSpec {
     /// this key. Note that adapter and component models can leave this off.
     ///
     /// Example: `512x512`, `1024x1024`
-    #[serde(rename = "modelspec.resolution")]
+    #[serde(
+        rename = "modelspec.resolution",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub resolution: Option<String>,
 
     /// For image generation adapter models (eg LoRA) especially,
@@ -244,17 +328,26 @@ pub struct ModelSpec {
     /// it should be placed here. Inference UIs are welcomed to
     /// auto-emit this phrase into the prompt if it is present
     /// (encouraged to make this behavior optional to the user where possible).
-    #[serde(rename = "modelspec.trigger_phrase")]
+    #[serde(
+        rename = "modelspec.trigger_phrase",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub trigger_phrase: Option<String>,
 
     /// In Stable Diffusion, `v` or `epsilon`. Other model classes
     /// may have their own concepts that apply.
-    #[serde(rename = "modelspec.prediction_type")]
+    #[serde(
+        rename = "modelspec.prediction_type",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub prediction_type: Option<String>,
 
     /// If a model is tuned on a sub-section of possible timesteps
     /// (Timestep-Expert Models), identify it here, in the format `<min>,<max>``.
-    #[serde(rename = "modelspec.timestep_range")]
+    #[serde(
+        rename = "modelspec.timestep_range",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub timestep_range: Option<String>,
 
     /// (Specialty) for "clip skip" in Stable Diffusion models,
@@ -262,14 +355,20 @@ pub struct ModelSpec {
     /// applied where relevant to identify that a non-standard
     /// layer of an encoder model should be used (so for example
     /// value `2` in an SD model indicates `clip_skip=2` should be used).
-    #[serde(rename = "modelspec.encoder_layer")]
+    #[serde(
+        rename = "modelspec.encoder_layer",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub encoder_layer: Option<String>,
 
     /// (Specialty) for "ControlNet" or similar model-adapter types
     /// that require preprocessing, this is an indicator of the
     /// preprocessing type, as a simple text identifier. Should not
     /// identify exact tool (eg "MiDaS"), just the broad type (eg "depth").
-    #[serde(rename = "modelspec.preprocessor")]
+    #[serde(
+        rename = "modelspec.preprocessor",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub preprocessor: Option<String>,
 
     /// (Specialty) for "Textual-Inversion" or similar input-embedding
@@ -277,52 +376,91 @@ pub struct ModelSpec {
     /// the embedding is meant for Negative Prompts, or false to
     /// indicate it's meant for (Positive) Prompts. A UI implementation
     /// may use this key to apply embeddings correctly with less user-intervention.
-    #[serde(rename = "modelspec.is_negative_embedding")]
-    pub is_negative_embedding: Option<bool>,
+    ///
+    /// Kept as written, e.g. `true` or `True`, so that it round-trips unchanged.
+    /// See [`ModelSpec::negative_embedding`] for the boolean value.
+    #[serde(
+        rename = "modelspec.is_negative_embedding",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub is_negative_embedding: Option<String>,
 
     /// (Specialty) for UNet based models that have special DType requirements
     /// (eg incompatible with fp16 but works with bf16) for inference,
     /// a comma-separated list of known-good types. Inference engines are
     /// recommended to ensure a compatible type is used when this is specified.
-    #[serde(rename = "modelspec.unet_dtype")]
+    #[serde(
+        rename = "modelspec.unet_dtype",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub unet_dtype: Option<String>,
 
     /// (Specialty) for latent models with a VAE that has special DType requirements
     /// (eg incompatible with fp16 but works with bf16) for inference,
     /// a comma-separated list of known-good types. Inference engines are
     /// recommended to ensure a compatible type is used when this is specified.
-    #[serde(rename = "modelspec.vae_dtype")]
+    #[serde(
+        rename = "modelspec.vae_dtype",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub vae_dtype: Option<String>,
 
     // Text-Prediction Models
     /// The format the data is in - needed due to the variety of specialty formats
     /// and quantization methods (often not accurately reflected in tensor data type,
     /// as eg there are different definitions of 4bit data).
-    #[serde(rename = "modelspec.data_format")]
+    #[serde(
+        rename = "modelspec.data_format",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub data_format: Option<String>,
 
     /// What `type of format` the model is intended to work in (writing stories vs
     /// question-and-answer chat vs coding). Should constrain to the enumerated
     /// examples unless a new format type has been created that is not yet listed.
-    #[serde(rename = "modelspec.format_type")]
+    #[serde(
+        rename = "modelspec.format_type",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub format_type: Option<String>,
 
     /// The primary human language(s) the model is trained to understand, in standard
     /// language code format, as a comma-separated list.
-    #[serde(rename = "modelspec.language")]
+    #[serde(rename = "modelspec.language", skip_serializing_if = "Option::is_none")]
     pub language: Option<String>,
 
     /// For formats where a specific template is trained in, it should be given here,
     /// as a string that identifies %%SYSTEM%%, %%USER%%, and %%AI%%.
     /// Some templates may exclude 'system' or add additional keys. Inferencing tools
     /// are encouraged to be lenient if the format does not match expectations.
-    #[serde(rename = "modelspec.format_template")]
+    #[serde(
+        rename = "modelspec.format_template",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub format_template: Option<String>,
 }
 
+impl ModelSpec {
+    /// `is_negative_embedding` as a boolean, ignoring case. `None` if it is
+    /// not set or not `true`/`false`.
+    pub fn negative_embedding(&self) -> Option<bool> {
+        match self
+            .is_negative_embedding
+            .as_deref()?
+            .to_lowercase()
+            .as_str()
+        {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Metadata {
     /// The tensor format of the model.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub format: Option<TensorFormart>,
 
     /// Stability AI Model Metadata Standard.
@@ -331,5 +469,70 @@ pub struct Metadata {
 
     /// Other metadata information.
     #[serde(flatten)]
-    pub others: HashMap<String, String>,
+    pub others: BTreeMap<String, String>,
+}
+
+impl Metadata {
+    /// The metadata as plain key-value pairs, as stored in the file.
+    pub fn to_map(&self) -> Result<BTreeMap<String, String>> {
+        let value = serde_json::to_value(self)?;
+        let map = serde_json::from_value(value)?;
+
+        Ok(map)
+    }
+
+    /// Build the metadata from plain key-value pairs.
+    pub fn from_map(map: BTreeMap<String, String>) -> Result<Self> {
+        let value = serde_json::to_value(map)?;
+        let metadata = serde_json::from_value(value)?;
+
+        Ok(metadata)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn metadata(is_negative_embedding: &str) -> Metadata {
+        Metadata::from_map(BTreeMap::from([
+            ("modelspec.title".to_string(), "Embedding".to_string()),
+            (
+                "modelspec.is_negative_embedding".to_string(),
+                is_negative_embedding.to_string(),
+            ),
+        ]))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_negative_embedding() {
+        for (value, expected) in [
+            ("true", Some(true)),
+            ("false", Some(false)),
+            ("True", Some(true)),
+            ("FALSE", Some(false)),
+            ("yes", None),
+            ("", None),
+        ] {
+            let metadata = metadata(value);
+            let model_spec = metadata.model_spec.as_ref().unwrap();
+            assert_eq!(model_spec.negative_embedding(), expected, "{:?}", value);
+
+            // the value is written back as it was read
+            assert_eq!(
+                metadata.to_map().unwrap()["modelspec.is_negative_embedding"],
+                value
+            );
+        }
+
+        let model_spec = Metadata::from_map(BTreeMap::from([(
+            "modelspec.title".to_string(),
+            "Embedding".to_string(),
+        )]))
+        .unwrap()
+        .model_spec
+        .unwrap();
+        assert_eq!(model_spec.negative_embedding(), None);
+    }
 }
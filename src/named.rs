@@ -0,0 +1,53 @@
+/// Implement `ALL`, `Display` and `FromStr` for a fieldless enum whose
+/// variants are selected by name, e.g. on the command line.
+///
+/// `ALL` lists the variants in the given order, which is also the order of
+/// the names in the error of `FromStr`:
+///
+/// ```ignore
+/// named_enum!(DigestAlgorithm, "digest algorithm", {
+///     Sha256 => "sha256",
+///     Blake3 => "blake3",
+/// });
+/// ```
+macro_rules! named_enum {
+    (@one $variant:ident) => {
+        1
+    };
+    ($type:ident, $what:literal, { $($variant:ident => $name:literal),+ $(,)? }) => {
+        impl $type {
+            pub const ALL: [$type; 0 $(+ $crate::named::named_enum!(@one $variant))+] =
+                [$($type::$variant),+];
+        }
+
+        impl std::str::FromStr for $type {
+            type Err = anyhow::Error;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                $type::ALL
+                    .into_iter()
+                    .find(|value| value.to_string() == s)
+                    .ok_or_else(|| {
+                        let names = $type::ALL.map(|value| value.to_string());
+                        anyhow::anyhow!(
+                            "Unknown {}: {} (expected one of {})",
+                            $what,
+                            s,
+                            names.join(", ")
+                        )
+                    })
+            }
+        }
+
+        impl std::fmt::Display for $type {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                let name = match self {
+                    $($type::$variant => $name),+
+                };
+                write!(f, "{}", name)
+            }
+        }
+    };
+}
+
+pub(crate) use named_enum;
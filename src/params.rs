@@ -0,0 +1,79 @@
+use std::collections::BTreeMap;
+
+use safemetadata::metadata::{Dtype, Weights};
+
+/// Parameter and byte counts of a group of tensors.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ParamCount {
+    pub tensors: usize,
+    pub params: i64,
+    pub bytes: i64,
+}
+
+impl ParamCount {
+    fn add(&mut self, params: i64, bytes: i64) {
+        self.tensors += 1;
+        self.params += params;
+        self.bytes += bytes;
+    }
+}
+
+/// Parameter counts of the whole model, grouped by dtype and by module.
+#[derive(Debug, Clone)]
+pub struct ParamBreakdown {
+    pub total: ParamCount,
+    pub by_dtype: BTreeMap<Dtype, ParamCount>,
+    pub by_module: BTreeMap<String, ParamCount>,
+}
+
+/// The module of the tensor, i.e. the first `depth` components of its name.
+///
+/// `model.layers.0.mlp.up_proj.weight` with depth 2 is `model.layers`.
+fn module_name(name: &str, depth: usize) -> String {
+    let components = name.split('.').collect::<Vec<_>>();
+    // keep at least the last component out, which is the tensor itself
+    let depth = depth.min(components.len().saturating_sub(1)).max(1);
+
+    components[..depth].join(".")
+}
+
+impl ParamBreakdown {
+    pub fn new(weights: &Weights, module_depth: usize) -> Self {
+        let mut total = ParamCount::default();
+        let mut by_dtype = BTreeMap::<Dtype, ParamCount>::new();
+        let mut by_module = BTreeMap::<String, ParamCount>::new();
+
+        for (name, weight) in weights.iter() {
+            let params = weight.num_elements();
+            let bytes = weight.byte_size();
+
+            total.add(params, bytes);
+            by_dtype.entry(weight.dtype).or_default().add(params, bytes);
+            by_module
+                .entry(module_name(name, module_depth))
+                .or_default()
+                .add(params, bytes);
+        }
+
+        Self {
+            total,
+            by_dtype,
+            by_module,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_module_name() {
+        let name = "model.layers.0.mlp.up_proj.weight";
+        assert_eq!(module_name(name, 1), "model");
+        assert_eq!(module_name(name, 2), "model.layers");
+        assert_eq!(module_name(name, 100), "model.layers.0.mlp.up_proj");
+        assert_eq!(module_name("lm_head.weight", 2), "lm_head");
+        assert_eq!(module_name("logit_scale", 1), "logit_scale");
+    }
+}
@@ -1,4 +1,4 @@
-use super::fetch::fetch_remote_bytes;
+use super::fetch::{fetch_remote_bytes, fetch_remote_range, RemoteFile};
 use super::metadata::Header;
 use anyhow::Result;
 use hf_hub::api::sync::Api;
@@ -9,6 +9,10 @@ use std::{fs::File, path::Path};
 
 // ref: https://huggingface.co/docs/safetensors/index#format
 
+/// The number of bytes to fetch first from remote files, which covers the header of most files.
+/// ref: https://huggingface.co/docs/safetensors/metadata_parsing
+const INITIAL_FETCH_SIZE: u64 = 100 * 1024;
+
 /// Read the specified range of bytes of the safetensors file.
 fn read_buffer<P: AsRef<Path>>(path: &P, start: u64, end: u64) -> Result<Vec<u8>> {
     let mut file = File::open(path)?;
@@ -21,9 +25,63 @@ fn read_buffer<P: AsRef<Path>>(path: &P, start: u64, end: u64) -> Result<Vec<u8>
     Ok(buffer.to_vec())
 }
 
+/// The parsed header together with the sizes of the file sections.
+#[derive(Debug, Clone)]
+pub struct HeaderInfo {
+    pub header: Header,
+
+    /// The size of the header chunk, excluding the 8 bytes of the size itself.
+    pub header_size: u64,
+
+    /// The size of the whole file.
+    pub file_size: u64,
+}
+
+impl HeaderInfo {
+    /// The position where the data section (the third part) starts.
+    pub fn data_start(&self) -> u64 {
+        8 + self.header_size
+    }
+
+    /// The size of the data section.
+    pub fn data_size(&self) -> u64 {
+        self.file_size.saturating_sub(self.data_start())
+    }
+
+    /// The total byte size of all tensors referenced by the header.
+    pub fn tensor_bytes(&self) -> u64 {
+        self.header
+            .weights
+            .values()
+            .map(|weight| weight.byte_size() as u64)
+            .sum()
+    }
+}
+
 /// Metadata parser trait
 pub trait MetadataParser {
     fn parse_header(&self) -> Result<Header>;
+
+    /// Parse the header along with the header size and the file size.
+    fn parse_header_info(&self) -> Result<HeaderInfo>;
+
+    /// Stream a byte range of the file.
+    fn open_range(&self, start: u64, length: u64) -> Result<Box<dyn Read + Send>>;
+
+    /// Stream the data of a tensor of the parsed header.
+    fn open_tensor(&self, info: &HeaderInfo, name: &str) -> Result<Box<dyn Read + Send>> {
+        let weight = info
+            .header
+            .weights
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("No tensor named {}", name))?;
+        let length = weight.byte_size() as u64;
+        if length == 0 {
+            return Ok(Box::new(std::io::empty()));
+        }
+
+        self.open_range(info.data_start() + weight.data_offsets[0] as u64, length)
+    }
 }
 
 /// Read safetensors files from the local file system.
@@ -66,13 +124,32 @@ impl LocalParser {
 impl MetadataParser for LocalParser {
     /// Parse the header of the safetensors file
     fn parse_header(&self) -> Result<Header> {
-        let header_size = &self.get_header_size()?;
-        let header_buffer = &self.get_header_buffer(&header_size)?;
+        let header_size = self.get_header_size()?;
+        let header_buffer = self.get_header_buffer(&header_size)?;
 
         let header: Header = serde_json::from_slice(&header_buffer)?;
 
         Ok(header)
     }
+
+    fn parse_header_info(&self) -> Result<HeaderInfo> {
+        let header_size = self.get_header_size()?;
+        let header = self.parse_header()?;
+        let file_size = std::fs::metadata(&self.path)?.len();
+
+        Ok(HeaderInfo {
+            header,
+            header_size,
+            file_size,
+        })
+    }
+
+    fn open_range(&self, start: u64, length: u64) -> Result<Box<dyn Read + Send>> {
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(start))?;
+
+        Ok(Box::new(file.take(length)))
+    }
 }
 
 #[cfg(test)]
@@ -86,7 +163,7 @@ mod test_local {
     fn get_hub_file(repo_id: &str, repo_type: RepoType, filename: &str) -> Result<PathBuf> {
         let api = Api::new()?;
 
-        let repo = api.repo(Repo::new((&repo_id).to_string(), repo_type));
+        let repo = api.repo(Repo::new(repo_id.to_string(), repo_type));
 
         let filepath = repo.get(filename).unwrap(); // if the cache is available, it will not download again
 
@@ -133,10 +210,7 @@ pub struct RemoteParser {
 
 impl RemoteParser {
     pub fn new(url: String) -> Self {
-        Self {
-            url: url,
-            token: None,
-        }
+        Self { url, token: None }
     }
 
     pub fn from_hub(
@@ -156,16 +230,68 @@ impl RemoteParser {
     }
 }
 
-impl MetadataParser for RemoteParser {
-    fn parse_header(&self) -> Result<Header> {
-        let header_size_buffer = fetch_remote_bytes(&self.url, &self.token, 0, 8)?; // passing the start index and the length of bytes
-        let header_size = u64::from_le_bytes(header_size_buffer.try_into().unwrap());
+impl RemoteParser {
+    /// The file, to read the tensor data with range requests.
+    pub fn remote_file(&self) -> RemoteFile {
+        RemoteFile::new(&self.url, &self.token)
+    }
+
+    /// Fetch and parse the header.
+    ///
+    /// The first request speculatively fetches enough bytes to contain the whole header
+    /// of most files, so usually only one request is needed.
+    /// Returns the header, the header size and the file size if the server reported it.
+    fn fetch_header(&self) -> Result<(Header, u64, Option<u64>)> {
+        let initial = fetch_remote_range(&self.url, &self.token, 0, INITIAL_FETCH_SIZE)?;
+        if initial.bytes.len() < 8 {
+            return Err(anyhow::anyhow!("The remote file is too small"));
+        }
 
-        let header_buffer = fetch_remote_bytes(&self.url, &self.token, 8, header_size)?;
+        let header_size = u64::from_le_bytes(initial.bytes[..8].try_into().unwrap());
+        let header_end = 8 + header_size;
+        let fetched = initial.bytes.len() as u64;
+
+        let header_buffer = if fetched >= header_end {
+            initial.bytes[8..header_end as usize].to_vec()
+        } else {
+            // fetch the rest of the header
+            let mut buffer = initial.bytes[8..].to_vec();
+            buffer.extend(fetch_remote_bytes(
+                &self.url,
+                &self.token,
+                fetched,
+                header_end - fetched,
+            )?);
+            buffer
+        };
         let header: Header = serde_json::from_slice(&header_buffer)?;
 
+        Ok((header, header_size, initial.total_size))
+    }
+}
+
+impl MetadataParser for RemoteParser {
+    fn parse_header(&self) -> Result<Header> {
+        let (header, _, _) = self.fetch_header()?;
+
         Ok(header)
     }
+
+    fn parse_header_info(&self) -> Result<HeaderInfo> {
+        let (header, header_size, file_size) = self.fetch_header()?;
+        let file_size = file_size
+            .ok_or_else(|| anyhow::anyhow!("Failed to get the size of the remote file"))?;
+
+        Ok(HeaderInfo {
+            header,
+            header_size,
+            file_size,
+        })
+    }
+
+    fn open_range(&self, start: u64, length: u64) -> Result<Box<dyn Read + Send>> {
+        Ok(Box::new(self.remote_file().open_range(start, length)?))
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,174 @@
+use anyhow::Result;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use crate::document::from_value;
+
+/// A patch document for the `__metadata__` field.
+#[derive(Debug, Clone)]
+pub enum MetadataPatch {
+    /// RFC 6902 JSON Patch, e.g. `[{"op": "replace", "path": "/license", "value": "mit"}]`
+    Json(json_patch::Patch),
+    /// RFC 7396 JSON Merge Patch, e.g. `{"license": "mit", "ss_dataset_dirs": null}`
+    Merge(Value),
+}
+
+impl MetadataPatch {
+    /// Parse a patch document. Arrays are JSON Patches and objects are merge patches.
+    pub fn parse(text: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(text)?;
+
+        match value {
+            Value::Array(_) => Ok(MetadataPatch::Json(serde_json::from_value(value)?)),
+            Value::Object(_) => Ok(MetadataPatch::Merge(value)),
+            _ => Err(anyhow::anyhow!(
+                "Expected a JSON Patch (array) or a JSON Merge Patch (object)"
+            )),
+        }
+    }
+
+    /// Apply the patch to the metadata entries.
+    pub fn apply(&self, map: &BTreeMap<String, String>) -> Result<BTreeMap<String, String>> {
+        let mut document = serde_json::to_value(map)?;
+
+        match self {
+            MetadataPatch::Json(patch) => json_patch::patch(&mut document, patch)?,
+            MetadataPatch::Merge(patch) => json_patch::merge(&mut document, patch),
+        }
+
+        from_value(document)
+    }
+}
+
+/// A difference between two versions of the metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetadataChange {
+    Added {
+        key: String,
+        value: String,
+    },
+    Removed {
+        key: String,
+        value: String,
+    },
+    Changed {
+        key: String,
+        old: String,
+        new: String,
+    },
+}
+
+impl Display for MetadataChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MetadataChange::Added { key, value } => write!(f, "+ {}: {:?}", key, value),
+            MetadataChange::Removed { key, value } => write!(f, "- {}: {:?}", key, value),
+            MetadataChange::Changed { key, old, new } => {
+                write!(f, "~ {}: {:?} -> {:?}", key, old, new)
+            }
+        }
+    }
+}
+
+/// The changes from `old` to `new`, ordered by key.
+pub fn diff(old: &BTreeMap<String, String>, new: &BTreeMap<String, String>) -> Vec<MetadataChange> {
+    let mut keys = old.keys().chain(new.keys()).collect::<Vec<_>>();
+    keys.sort();
+    keys.dedup();
+
+    keys.into_iter()
+        .filter_map(|key| match (old.get(key), new.get(key)) {
+            (None, Some(value)) => Some(MetadataChange::Added {
+                key: key.clone(),
+                value: value.clone(),
+            }),
+            (Some(value), None) => Some(MetadataChange::Removed {
+                key: key.clone(),
+                value: value.clone(),
+            }),
+            (Some(old), Some(new)) if old != new => Some(MetadataChange::Changed {
+                key: key.clone(),
+                old: old.clone(),
+                new: new.clone(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn metadata() -> BTreeMap<String, String> {
+        BTreeMap::from([
+            ("format".to_string(), "pt".to_string()),
+            ("license".to_string(), "mit".to_string()),
+            ("ss_dataset_dirs".to_string(), "{}".to_string()),
+        ])
+    }
+
+    #[test]
+    fn test_json_patch() {
+        let patch = MetadataPatch::parse(
+            r#"[
+                {"op": "test", "path": "/format", "value": "pt"},
+                {"op": "move", "from": "/license", "path": "/modelspec.license"},
+                {"op": "remove", "path": "/ss_dataset_dirs"}
+            ]"#,
+        )
+        .unwrap();
+        assert!(matches!(patch, MetadataPatch::Json(_)));
+
+        let old = metadata();
+        let new = patch.apply(&old).unwrap();
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                MetadataChange::Removed {
+                    key: "license".to_string(),
+                    value: "mit".to_string()
+                },
+                MetadataChange::Added {
+                    key: "modelspec.license".to_string(),
+                    value: "mit".to_string()
+                },
+                MetadataChange::Removed {
+                    key: "ss_dataset_dirs".to_string(),
+                    value: "{}".to_string()
+                },
+            ]
+        );
+
+        let failing =
+            MetadataPatch::parse(r#"[{"op": "test", "path": "/format", "value": "tf"}]"#).unwrap();
+        assert!(failing.apply(&old).is_err());
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        let patch =
+            MetadataPatch::parse(r#"{"license": "apache-2.0", "ss_dataset_dirs": null}"#).unwrap();
+        assert!(matches!(patch, MetadataPatch::Merge(_)));
+
+        let old = metadata();
+        let new = patch.apply(&old).unwrap();
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                MetadataChange::Changed {
+                    key: "license".to_string(),
+                    old: "mit".to_string(),
+                    new: "apache-2.0".to_string()
+                },
+                MetadataChange::Removed {
+                    key: "ss_dataset_dirs".to_string(),
+                    value: "{}".to_string()
+                },
+            ]
+        );
+
+        assert!(MetadataPatch::parse("1").is_err());
+    }
+}
@@ -0,0 +1,76 @@
+use anyhow::Result;
+use globset::{Glob, GlobMatcher};
+use regex::Regex;
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// A pattern to match tensor names or metadata keys.
+///
+/// Patterns prefixed with `re:` are treated as regular expressions
+/// (unanchored, so `re:lm_head` matches any name containing `lm_head`).
+/// Everything else is a glob that must match the whole name,
+/// e.g. `model.layers.*.mlp.*`.
+#[derive(Debug, Clone)]
+pub enum NamePattern {
+    Glob(GlobMatcher),
+    Regex(Regex),
+}
+
+impl NamePattern {
+    pub fn new(pattern: &str) -> Result<Self> {
+        match pattern.strip_prefix("re:") {
+            Some(regex) => Ok(Self::Regex(Regex::new(regex)?)),
+            None => Ok(Self::Glob(Glob::new(pattern)?.compile_matcher())),
+        }
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        match self {
+            Self::Glob(glob) => glob.is_match(name),
+            Self::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+impl FromStr for NamePattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+impl Display for NamePattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Glob(glob) => write!(f, "{}", glob.glob()),
+            Self::Regex(regex) => write!(f, "re:{}", regex),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_name_pattern() {
+        let glob = NamePattern::new("model.layers.*.mlp.*").unwrap();
+        assert!(glob.is_match("model.layers.0.mlp.up_proj.weight"));
+        assert!(!glob.is_match("model.layers.0.self_attn.q_proj.weight"));
+
+        let exact = NamePattern::new("lm_head.weight").unwrap();
+        assert!(exact.is_match("lm_head.weight"));
+        assert!(!exact.is_match("model.lm_head.weight"));
+
+        let regex = NamePattern::new("re:lm_head").unwrap();
+        assert!(regex.is_match("lm_head.weight"));
+        assert!(regex.is_match("model.lm_head.weight"));
+
+        let anchored = NamePattern::new(r"re:^ss_\w+$").unwrap();
+        assert!(anchored.is_match("ss_network_dim"));
+        assert!(!anchored.is_match("modelspec.title"));
+
+        assert!(NamePattern::new("re:(").is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use std::fmt::Debug;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+/// The size of the buffer used to copy tensor data.
+const COPY_BUFFER_SIZE: usize = 8 * 1024 * 1024;
+
+/// A callback for long-running operations, called with the number of bytes
+/// processed so far and the total number of bytes.
+#[derive(Clone)]
+pub struct Progress(Arc<dyn Fn(u64, u64) + Send + Sync>);
+
+impl Progress {
+    pub fn new<F: Fn(u64, u64) + Send + Sync + 'static>(callback: F) -> Self {
+        Self(Arc::new(callback))
+    }
+
+    pub fn update(&self, done: u64, total: u64) {
+        (self.0)(done, total)
+    }
+}
+
+impl Debug for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Progress")
+    }
+}
+
+/// Copy `total` bytes from the reader to the writer, reporting the progress.
+///
+/// Fails if the reader ends before `total` bytes are copied.
+pub fn copy_with_progress<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    total: u64,
+    progress: Option<&Progress>,
+) -> io::Result<u64> {
+    let mut buffer = vec![0; COPY_BUFFER_SIZE.min(total as usize).max(1)];
+    let mut copied = 0;
+
+    if let Some(progress) = progress {
+        progress.update(0, total);
+    }
+    while copied < total {
+        let length = buffer.len().min((total - copied) as usize);
+        reader.read_exact(&mut buffer[..length])?;
+        writer.write_all(&buffer[..length])?;
+        copied += length as u64;
+
+        if let Some(progress) = progress {
+            progress.update(copied, total);
+        }
+    }
+
+    Ok(copied)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_copy_with_progress() {
+        let data = (0..100u8).collect::<Vec<_>>();
+        let updates = Arc::new(Mutex::new(Vec::new()));
+        let progress = {
+            let updates = updates.clone();
+            Progress::new(move |done, total| updates.lock().unwrap().push((done, total)))
+        };
+
+        let mut output = Vec::new();
+        let copied = copy_with_progress(&mut &data[..], &mut output, 60, Some(&progress)).unwrap();
+
+        assert_eq!(copied, 60);
+        assert_eq!(output, data[..60]);
+        assert_eq!(updates.lock().unwrap().first(), Some(&(0, 60)));
+        assert_eq!(updates.lock().unwrap().last(), Some(&(60, 60)));
+
+        let mut output = Vec::new();
+        assert!(copy_with_progress(&mut &data[..], &mut output, 200, None).is_err());
+    }
+}
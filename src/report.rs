@@ -0,0 +1,60 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use safemetadata::file::WriteReport;
+use safemetadata::progress::Progress;
+
+use crate::utils::pretty_byte_size;
+
+/// A progress bar on stderr for copying tensor data. Hidden if stderr is not a terminal.
+pub fn progress_bar() -> (ProgressBar, Progress) {
+    let bar = ProgressBar::new(0);
+    bar.set_style(
+        ProgressStyle::with_template(
+            "{bar:40} {bytes}/{total_bytes} ({bytes_per_sec}, {eta} remaining)",
+        )
+        .expect("valid progress template"),
+    );
+
+    let progress = {
+        let bar = bar.clone();
+        Progress::new(move |done, total| {
+            bar.set_length(total);
+            bar.set_position(done);
+        })
+    };
+
+    (bar, progress)
+}
+
+/// Print the metadata changes and what was written.
+pub fn print_report(report: &WriteReport) {
+    for change in &report.changes {
+        println!("{}", change);
+    }
+
+    if report.rewritten && report.old_header_size == 0 {
+        // a new file that was not derived from a single input
+        println!(
+            "Wrote {}: header {} bytes, {} of tensor data",
+            report.output.display(),
+            report.new_header_size,
+            pretty_byte_size(report.bytes_copied)
+        );
+    } else if report.rewritten {
+        println!(
+            "Wrote {}: header {} -> {} bytes, {} of tensor data copied",
+            report.output.display(),
+            report.old_header_size,
+            report.new_header_size,
+            pretty_byte_size(report.bytes_copied)
+        );
+    } else {
+        println!(
+            "Overwrote the header of {} in place ({} bytes)",
+            report.output.display(),
+            report.new_header_size
+        );
+    }
+    if let Some(backup) = &report.backup {
+        println!("Backup: {}", backup.display());
+    }
+}
@@ -0,0 +1,351 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::atomic::AtomicFile;
+use crate::file::WriteReport;
+use crate::parser::{LocalParser, MetadataParser};
+use crate::progress::Progress;
+use crate::writer::{SafetensorsWriter, TensorSource};
+
+const SAFETENSORS_EXTENSION: &str = "safetensors";
+
+/// Parse a byte size such as `5GB`, `500MB`, `2GiB` or `1000`.
+///
+/// Like transformers, `KB`/`MB`/`GB`/`TB` are powers of 1000 and
+/// `KiB`/`MiB`/`GiB`/`TiB` are powers of 1024.
+pub fn parse_byte_size(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" | "k" => 1000,
+        "mb" | "m" => 1000u64.pow(2),
+        "gb" | "g" => 1000u64.pow(3),
+        "tb" | "t" => 1000u64.pow(4),
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => return Err(anyhow::anyhow!("Unknown size unit: {}", unit)),
+    };
+    let number = number
+        .parse::<f64>()
+        .map_err(|_| anyhow::anyhow!("Invalid size: {}", s))?;
+
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// The metadata of a sharded checkpoint index.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IndexMetadata {
+    /// The total byte size of the tensors.
+    pub total_size: u64,
+
+    #[serde(flatten)]
+    pub others: BTreeMap<String, Value>,
+}
+
+/// The `model.safetensors.index.json` file of a sharded checkpoint, as
+/// written by transformers and diffusers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShardIndex {
+    pub metadata: IndexMetadata,
+
+    /// The file name of the shard that contains each tensor.
+    pub weight_map: BTreeMap<String, String>,
+}
+
+impl ShardIndex {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let text = fs::read_to_string(path.as_ref())?;
+        serde_json::from_str(&text)
+            .map_err(|err| anyhow::anyhow!("{}: {}", path.as_ref().display(), err))
+    }
+
+    /// The shard file names, sorted.
+    pub fn shard_files(&self) -> Vec<&String> {
+        let files = self.weight_map.values().collect::<BTreeSet<_>>();
+        files.into_iter().collect()
+    }
+}
+
+/// The result of [`shard`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShardReport {
+    pub index_path: PathBuf,
+    pub total_size: u64,
+    pub shards: Vec<WriteReport>,
+}
+
+/// The file name of a shard, e.g. `model-00001-of-00003.safetensors`.
+pub fn shard_file_name(stem: &str, index: usize, count: usize) -> String {
+    format!(
+        "{}-{:05}-of-{:05}.{}",
+        stem,
+        index + 1,
+        count,
+        SAFETENSORS_EXTENSION
+    )
+}
+
+/// The file name of the index, e.g. `model.safetensors.index.json`.
+pub fn index_file_name(stem: &str) -> String {
+    format!("{}.{}.index.json", stem, SAFETENSORS_EXTENSION)
+}
+
+/// Forward the progress of one file as part of the progress of several files.
+fn offset_progress(progress: &Option<Progress>, base: u64, total: u64) -> Option<Progress> {
+    progress
+        .clone()
+        .map(|progress| Progress::new(move |done, _| progress.update(base + done, total)))
+}
+
+/// Split a safetensors file into shards of at most `max_size` bytes of tensor
+/// data, and write the index next to them in `output_dir`.
+///
+/// The tensors keep the order of the input file. A new shard is started when
+/// the next tensor does not fit; a tensor larger than `max_size` gets a shard
+/// of its own. Every shard keeps the metadata of the input, except the stored
+/// hashes, which apply to the data of the whole file.
+pub fn shard<P: AsRef<Path>, Q: AsRef<Path>>(
+    input_path: P,
+    output_dir: Q,
+    max_size: u64,
+    progress: Option<Progress>,
+) -> Result<ShardReport> {
+    let input_path = input_path.as_ref();
+    let output_dir = output_dir.as_ref();
+    if max_size == 0 {
+        return Err(anyhow::anyhow!("The maximum shard size must not be zero"));
+    }
+
+    let info = LocalParser::new(input_path).parse_header_info()?;
+    let metadata = match &info.header.metadata {
+        Some(metadata) => metadata.to_map()?,
+        None => BTreeMap::new(),
+    };
+    let stem = input_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .ok_or_else(|| anyhow::anyhow!("Invalid input path: {}", input_path.display()))?;
+
+    let mut tensors = info.header.weights.iter().collect::<Vec<_>>();
+    tensors.sort_by_key(|(name, weight)| (weight.data_offsets[0], *name));
+
+    let mut groups: Vec<Vec<&str>> = Vec::new();
+    let mut group_size = 0;
+    for (name, weight) in tensors {
+        let size = weight.byte_size() as u64;
+        match groups.last_mut() {
+            Some(group) if group_size + size <= max_size => {
+                group.push(name);
+                group_size += size;
+            }
+            _ => {
+                groups.push(vec![name]);
+                group_size = size;
+            }
+        }
+    }
+    if groups.is_empty() {
+        return Err(anyhow::anyhow!("{} has no tensors", input_path.display()));
+    }
+
+    fs::create_dir_all(output_dir)?;
+    let total_size = info.tensor_bytes();
+    let mut weight_map = BTreeMap::new();
+    let mut shards = Vec::new();
+    let mut written = 0;
+
+    for (index, group) in groups.iter().enumerate() {
+        let file_name = shard_file_name(&stem, index, groups.len());
+        let names = group.iter().copied().collect::<HashSet<_>>();
+
+        let mut writer = SafetensorsWriter::new().with_metadata(metadata.clone());
+        if let Some(progress) = offset_progress(&progress, written, total_size) {
+            writer = writer.with_progress(progress);
+        }
+        writer.add_tensors_from(
+            &info,
+            |name| names.contains(name).then(|| name.to_string()),
+            |offset, length| TensorSource::File {
+                path: input_path.to_path_buf(),
+                offset,
+                length,
+            },
+        )?;
+
+        let report = writer.write(output_dir.join(&file_name))?;
+        written += report.bytes_copied;
+        for name in group {
+            weight_map.insert(name.to_string(), file_name.clone());
+        }
+        shards.push(report);
+    }
+
+    let index = ShardIndex {
+        metadata: IndexMetadata {
+            total_size,
+            others: BTreeMap::new(),
+        },
+        weight_map,
+    };
+    let index_path = output_dir.join(index_file_name(&stem));
+    let mut index_file = AtomicFile::create(&index_path)?;
+    index_file.write_all(serde_json::to_string_pretty(&index)?.as_bytes())?;
+    index_file.write_all(b"\n")?;
+    index_file.commit()?;
+
+    Ok(ShardReport {
+        index_path,
+        total_size,
+        shards,
+    })
+}
+
+/// Join the shards listed in an index into a single file.
+///
+/// Fails if a tensor of the weight map is missing from its shard. Tensors of
+/// the shards that are not in the weight map are skipped and listed in
+/// `removed_tensors`. The metadata of the first shard is kept, except the
+/// stored hashes.
+pub fn unshard<P: AsRef<Path>, Q: AsRef<Path>>(
+    index_path: P,
+    output_path: Q,
+    headroom: usize,
+    progress: Option<Progress>,
+) -> Result<WriteReport> {
+    let index_path = index_path.as_ref();
+    let index = ShardIndex::read(index_path)?;
+    let dir = index_path.parent().unwrap_or(Path::new(""));
+
+    let mut writer = SafetensorsWriter::new().with_headroom(headroom);
+    if let Some(progress) = progress {
+        writer = writer.with_progress(progress);
+    }
+
+    let mut removed_tensors = Vec::new();
+    for (position, file_name) in index.shard_files().into_iter().enumerate() {
+        let path = dir.join(file_name);
+        let info = LocalParser::new(&path)
+            .parse_header_info()
+            .map_err(|err| anyhow::anyhow!("{}: {}", path.display(), err))?;
+
+        if position == 0 {
+            if let Some(metadata) = &info.header.metadata {
+                writer = writer.with_metadata(metadata.to_map()?);
+            }
+        }
+        if let Some(missing) = index
+            .weight_map
+            .iter()
+            .find(|(name, file)| *file == file_name && !info.header.weights.contains_key(*name))
+        {
+            return Err(anyhow::anyhow!(
+                "{} is listed in the index but not found in {}",
+                missing.0,
+                path.display()
+            ));
+        }
+
+        let selection = writer.add_tensors_from(
+            &info,
+            |name| (index.weight_map.get(name) == Some(file_name)).then(|| name.to_string()),
+            |offset, length| TensorSource::File {
+                path: path.clone(),
+                offset,
+                length,
+            },
+        )?;
+        removed_tensors.extend(selection.skipped);
+    }
+
+    let report = writer.write(output_path)?;
+
+    Ok(WriteReport {
+        removed_tensors,
+        ..report
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::file::SafetensorsFile;
+    use crate::metadata::Dtype;
+    use crate::validate::validate;
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(parse_byte_size("5GB").unwrap(), 5_000_000_000);
+        assert_eq!(parse_byte_size("500MB").unwrap(), 500_000_000);
+        assert_eq!(parse_byte_size("2GiB").unwrap(), 2 << 30);
+        assert_eq!(parse_byte_size("1.5 kb").unwrap(), 1500);
+        assert_eq!(parse_byte_size("100").unwrap(), 100);
+        assert!(parse_byte_size("5XB").is_err());
+        assert!(parse_byte_size("GB").is_err());
+    }
+
+    #[test]
+    fn test_shard_and_unshard() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("model.safetensors");
+        let mut writer = SafetensorsWriter::new()
+            .with_metadata(BTreeMap::from([("format".to_string(), "pt".to_string())]));
+        for (name, size) in [("a", 40), ("b", 30), ("c", 30), ("d", 120), ("e", 10)] {
+            let bytes = (0..size).map(|i| i as u8).collect::<Vec<_>>();
+            writer
+                .add_tensor(name, Dtype::Uint8, vec![size], bytes.into())
+                .unwrap();
+        }
+        writer.write(&input).unwrap();
+        let hashed = dir.path().join("hashed.safetensors");
+        let hash_key = "modelspec.hash_sha256".to_string();
+        SafetensorsFile::new(&input)
+            .set_metadata(&hashed, &[(hash_key.clone(), "0x1234".to_string())])
+            .unwrap();
+
+        let shard_dir = dir.path().join("sharded");
+        let report = shard(&hashed, &shard_dir, 100, None).unwrap();
+        assert_eq!(report.total_size, 230);
+        assert_eq!(report.shards.len(), 3);
+        assert_eq!(
+            report.index_path,
+            shard_dir.join("hashed.safetensors.index.json")
+        );
+        for shard in &report.shards {
+            assert_eq!(shard.removed_keys(), vec![&hash_key]);
+        }
+
+        let index = ShardIndex::read(&report.index_path).unwrap();
+        assert_eq!(index.metadata.total_size, 230);
+        assert_eq!(index.weight_map["a"], "hashed-00001-of-00003.safetensors");
+        assert_eq!(index.weight_map["c"], "hashed-00001-of-00003.safetensors");
+        assert_eq!(index.weight_map["d"], "hashed-00002-of-00003.safetensors");
+        assert_eq!(index.weight_map["e"], "hashed-00003-of-00003.safetensors");
+        for file_name in index.shard_files() {
+            let info = LocalParser::new(shard_dir.join(file_name))
+                .parse_header_info()
+                .unwrap();
+            assert_eq!(validate(&info), vec![]);
+            let metadata = info.header.metadata.unwrap().to_map().unwrap();
+            assert_eq!(metadata.keys().collect::<Vec<_>>(), vec!["format"]);
+        }
+
+        let output = dir.path().join("unsharded.safetensors");
+        let report = unshard(&report.index_path, &output, 0, None).unwrap();
+        assert_eq!(report.tensors_written, 5);
+        assert_eq!(report.bytes_copied, 230);
+        assert_eq!(fs::read(&output).unwrap(), fs::read(&input).unwrap());
+    }
+}
@@ -0,0 +1,333 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use globset::GlobBuilder;
+use hf_hub::{Cache, RepoType};
+use rayon::prelude::*;
+use safemetadata::parser::{HeaderInfo, LocalParser, MetadataParser, RemoteParser};
+use walkdir::WalkDir;
+
+const SAFETENSORS_EXTENSION: &str = "safetensors";
+
+/// A single safetensors file, either local or on the HuggingFace hub.
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub file_path: String,
+    pub repo_id: Option<String>,
+    pub token: Option<String>,
+}
+
+impl Source {
+    pub fn parser(&self) -> Box<dyn MetadataParser + Send + Sync> {
+        match &self.repo_id {
+            Some(repo_id) => {
+                let parser =
+                    RemoteParser::from_hub(repo_id, RepoType::Model, &self.file_path, &self.token);
+                Box::new(parser)
+            }
+            None => {
+                let parser = LocalParser::new(&self.file_path);
+                Box::new(parser)
+            }
+        }
+    }
+
+    /// The name shown in the output
+    pub fn display_name(&self) -> String {
+        match &self.repo_id {
+            Some(repo_id) => format!("{}/{}", repo_id, self.file_path),
+            None => self.file_path.clone(),
+        }
+    }
+}
+
+/// The result of parsing the header of a source.
+pub struct Inspected {
+    pub name: String,
+    pub result: Result<HeaderInfo>,
+}
+
+fn is_safetensors(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .is_some_and(|extension| extension == SAFETENSORS_EXTENSION)
+}
+
+/// List the safetensors files in the directory.
+fn list_directory(dir: &Path, recursive: bool) -> Vec<PathBuf> {
+    let max_depth = if recursive { usize::MAX } else { 1 };
+
+    WalkDir::new(dir)
+        .max_depth(max_depth)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.into_path())
+        .filter(|path| is_safetensors(path))
+        .collect()
+}
+
+/// Whether the path has glob metacharacters.
+fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '[', '{'])
+}
+
+/// The files and directories matching the glob pattern, sorted by name.
+///
+/// The pattern is matched below the directory of its leading components
+/// without metacharacters. `*` does not match `/`, `**` matches any number
+/// of directories.
+fn expand_glob(pattern: &str) -> Result<Vec<PathBuf>> {
+    let components = Path::new(pattern).components().collect::<Vec<_>>();
+    let split = components
+        .iter()
+        .position(|component| is_glob(&component.as_os_str().to_string_lossy()))
+        .unwrap_or(components.len());
+    let base = components[..split].iter().collect::<PathBuf>();
+    let rest = components[split..]
+        .iter()
+        .collect::<PathBuf>()
+        .to_string_lossy()
+        .to_string();
+
+    let matcher = GlobBuilder::new(&rest)
+        .literal_separator(true)
+        .build()?
+        .compile_matcher();
+    let max_depth = match rest.contains("**") {
+        true => usize::MAX,
+        false => components.len() - split,
+    };
+    let dir = match base.as_os_str().is_empty() {
+        true => Path::new("."),
+        false => base.as_path(),
+    };
+
+    let paths = WalkDir::new(dir)
+        .min_depth(1)
+        .max_depth(max_depth)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let relative = entry.path().strip_prefix(dir).ok()?;
+            matcher.is_match(relative).then(|| base.join(relative))
+        })
+        .collect();
+
+    Ok(paths)
+}
+
+/// Expand the local path arguments into safetensors files.
+///
+/// Each argument can be a file, a directory or a glob pattern.
+fn expand_local_path(path: &str, recursive: bool) -> Result<Vec<PathBuf>> {
+    let as_path = Path::new(path);
+
+    if as_path.is_dir() {
+        return Ok(list_directory(as_path, recursive));
+    }
+    if as_path.exists() || !is_glob(path) {
+        return Ok(vec![as_path.to_path_buf()]);
+    }
+
+    let mut paths = Vec::new();
+    for entry in expand_glob(path)? {
+        if entry.is_dir() {
+            paths.extend(list_directory(&entry, recursive));
+        } else {
+            paths.push(entry);
+        }
+    }
+    if paths.is_empty() {
+        return Err(anyhow::anyhow!("No files matched"));
+    }
+
+    Ok(paths)
+}
+
+/// Resolve the command line arguments into sources.
+///
+/// Arguments that cannot be resolved (e.g. globs without any matches) are
+/// returned as errors so that the other files can still be processed.
+pub fn resolve_sources(
+    file_paths: Vec<String>,
+    repo_id: Option<String>,
+    token: Option<String>,
+    recursive: bool,
+) -> Vec<(String, Result<Source>)> {
+    match repo_id {
+        Some(repo_id) => {
+            let token = match token {
+                Some(token) => Some(token),       // do nothing
+                None => Cache::default().token(), // load token from cache
+            };
+
+            file_paths
+                .into_iter()
+                .map(|file_path| {
+                    let source = Source {
+                        file_path,
+                        repo_id: Some(repo_id.clone()),
+                        token: token.clone(),
+                    };
+                    (source.display_name(), Ok(source))
+                })
+                .collect()
+        }
+        None => file_paths
+            .into_iter()
+            .flat_map(|path| match expand_local_path(&path, recursive) {
+                Ok(paths) => paths
+                    .into_iter()
+                    .map(|path| {
+                        let source = Source {
+                            file_path: path.to_string_lossy().to_string(),
+                            repo_id: None,
+                            token: None,
+                        };
+                        (source.display_name(), Ok(source))
+                    })
+                    .collect(),
+                Err(err) => vec![(path, Err(err))],
+            })
+            .collect(),
+    }
+}
+
+/// Parse the headers of all sources in parallel, keeping the order.
+pub fn inspect_all(sources: Vec<(String, Result<Source>)>) -> Vec<Inspected> {
+    sources
+        .into_par_iter()
+        .map(|(name, source)| {
+            let result = source.and_then(|source| source.parser().parse_header_info());
+            Inspected { name, result }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    /// `a.safetensors`, `b.safetensors`, `notes.txt`, `sub/c.safetensors`
+    /// and `sub/deeper/d.safetensors`.
+    fn create_tree() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("sub/deeper")).unwrap();
+        for name in [
+            "a.safetensors",
+            "b.safetensors",
+            "notes.txt",
+            "sub/c.safetensors",
+            "sub/deeper/d.safetensors",
+        ] {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+        dir
+    }
+
+    fn expand(dir: &Path, path: &str, recursive: bool) -> Vec<String> {
+        let path = dir.join(path).to_string_lossy().to_string();
+        expand_local_path(&path, recursive)
+            .unwrap()
+            .into_iter()
+            .map(|path| {
+                let relative = path.strip_prefix(dir).unwrap();
+                relative.to_string_lossy().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_expand_directory() {
+        let dir = create_tree();
+
+        assert_eq!(
+            expand(dir.path(), "", false),
+            vec!["a.safetensors", "b.safetensors"]
+        );
+        assert_eq!(
+            expand(dir.path(), "", true),
+            vec![
+                "a.safetensors",
+                "b.safetensors",
+                "sub/c.safetensors",
+                "sub/deeper/d.safetensors"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expand_glob() {
+        let dir = create_tree();
+
+        assert_eq!(
+            expand(dir.path(), "*.safetensors", false),
+            vec!["a.safetensors", "b.safetensors"]
+        );
+        assert_eq!(
+            expand(dir.path(), "[b-z].safetensors", false),
+            vec!["b.safetensors"]
+        );
+        // `*` does not match `/`, `**` matches any number of directories
+        assert_eq!(
+            expand(dir.path(), "*/*.safetensors", false),
+            vec!["sub/c.safetensors"]
+        );
+        assert_eq!(
+            expand(dir.path(), "**/*.safetensors", false),
+            vec![
+                "a.safetensors",
+                "b.safetensors",
+                "sub/c.safetensors",
+                "sub/deeper/d.safetensors"
+            ]
+        );
+        // matched directories are listed like directory arguments
+        assert_eq!(expand(dir.path(), "su?", false), vec!["sub/c.safetensors"]);
+        assert_eq!(
+            expand(dir.path(), "su?", true),
+            vec!["sub/c.safetensors", "sub/deeper/d.safetensors"]
+        );
+    }
+
+    #[test]
+    fn test_resolve_sources() {
+        let dir = create_tree();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+
+        let sources = resolve_sources(
+            vec![
+                path("a.safetensors"),
+                path("missing.safetensors"),
+                path("*.gguf"),
+            ],
+            None,
+            None,
+            false,
+        );
+        assert_eq!(sources.len(), 3);
+        assert_eq!(sources[0].0, path("a.safetensors"));
+        // missing files are passed on and fail when they are read
+        let missing = sources[1].1.as_ref().unwrap();
+        assert_eq!(missing.file_path, path("missing.safetensors"));
+        assert!(missing.parser().parse_header().is_err());
+        // globs without matches are errors
+        assert_eq!(sources[2].0, path("*.gguf"));
+        assert!(sources[2].1.is_err());
+
+        // remote paths are not expanded
+        let sources = resolve_sources(
+            vec!["*.safetensors".to_string()],
+            Some("user/model".to_string()),
+            Some("token".to_string()),
+            true,
+        );
+        let source = sources[0].1.as_ref().unwrap();
+        assert_eq!(source.display_name(), "user/model/*.safetensors");
+        assert_eq!(source.token.as_deref(), Some("token"));
+    }
+}
@@ -0,0 +1,233 @@
+use anyhow::Result;
+use safemetadata::compare::{Comparison, SkipReason, Thresholds};
+use safemetadata::hash::{FileHash, HashKind};
+use safemetadata::manifest::ManifestReport;
+
+/// The hashes that do not match the value stored in the metadata, except the
+/// modelspec hash if `hash --write` replaced it.
+pub fn unfixed_mismatches(hashes: &[FileHash], modelspec_written: bool) -> Vec<&FileHash> {
+    hashes
+        .iter()
+        .filter(|hash| hash.matches() == Some(false))
+        .filter(|hash| !(modelspec_written && hash.kind == HashKind::ModelSpec))
+        .collect()
+}
+
+/// The exit status of `hash`: it fails if a file could not be hashed or
+/// updated, or if a file has a mismatched hash that was not fixed.
+pub fn hash_status(files: usize, failed: usize, mismatched: usize) -> Result<()> {
+    if failed > 0 {
+        return Err(anyhow::anyhow!(
+            "{} of {} files could not be hashed or updated",
+            failed,
+            files
+        ));
+    }
+    if mismatched > 0 {
+        return Err(anyhow::anyhow!(
+            "{} of {} files have a hash that does not match the metadata",
+            mismatched,
+            files
+        ));
+    }
+
+    Ok(())
+}
+
+/// The exit status of `compare`: it fails if a tensor is not within the
+/// thresholds or has a different shape, or if no tensor was compared at all.
+pub fn compare_status(comparison: &Comparison, thresholds: &Thresholds) -> Result<()> {
+    let failed = comparison
+        .tensors
+        .iter()
+        .filter(|tensor| !tensor.passes(thresholds))
+        .count();
+    let shape_mismatches = comparison
+        .skipped
+        .iter()
+        .filter(|(_, reason)| matches!(reason, SkipReason::Shape { .. }))
+        .count();
+    if failed > 0 || shape_mismatches > 0 {
+        return Err(anyhow::anyhow!(
+            "{} of {} tensors are not within the thresholds, {} have a different shape",
+            failed,
+            comparison.tensors.len(),
+            shape_mismatches
+        ));
+    }
+    if comparison.tensors.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No tensors were compared: the files have no tensors in common that can be decoded"
+        ));
+    }
+
+    Ok(())
+}
+
+/// The exit status of `manifest verify`: it fails if any tensor is missing,
+/// unexpected or different from the manifest.
+pub fn verify_status(report: &ManifestReport) -> Result<()> {
+    if !report.is_ok() {
+        return Err(anyhow::anyhow!(
+            "{} tensors differ from the manifest, {} match",
+            report.mismatches.len(),
+            report.matched
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use safemetadata::compare::TensorComparison;
+    use safemetadata::manifest::{TensorEntry, TensorMismatch};
+    use safemetadata::metadata::Dtype;
+
+    fn hash(kind: HashKind, computed: &str, stored: Option<&str>) -> FileHash {
+        FileHash {
+            kind,
+            computed: computed.to_string(),
+            stored: stored.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_unfixed_mismatches() {
+        let hashes = [
+            hash(HashKind::ModelSpec, "0xab", Some("0xcd")),
+            hash(HashKind::SshsModel, "ab", Some("AB")),
+            hash(HashKind::AutoV2, "ab", None),
+        ];
+        assert_eq!(unfixed_mismatches(&hashes, false), [&hashes[0]]);
+        assert!(unfixed_mismatches(&hashes, true).is_empty());
+
+        // --write only stores the modelspec hash
+        let hashes = [
+            hash(HashKind::ModelSpec, "0xab", Some("0xcd")),
+            hash(HashKind::SshsModel, "ab", Some("cd")),
+            hash(HashKind::SshsLegacy, "ab", Some("cd")),
+        ];
+        assert_eq!(unfixed_mismatches(&hashes, true), [&hashes[1], &hashes[2]]);
+    }
+
+    #[test]
+    fn test_hash_status() {
+        assert!(hash_status(2, 0, 0).is_ok());
+        assert!(hash_status(2, 1, 0).is_err());
+        let err = hash_status(2, 0, 1).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "1 of 2 files have a hash that does not match the metadata"
+        );
+    }
+
+    fn tensor(name: &str, relative_error: f64, nan_mismatches: u64) -> TensorComparison {
+        TensorComparison {
+            name: name.to_string(),
+            first_dtype: Dtype::Fp32,
+            second_dtype: Dtype::Fp16,
+            elements: 4,
+            max_abs_diff: relative_error,
+            mean_abs_diff: relative_error,
+            cosine_similarity: 1.0,
+            relative_error,
+            nan_mismatches,
+            inf_mismatches: 0,
+        }
+    }
+
+    #[test]
+    fn test_compare_status() {
+        let thresholds = Thresholds {
+            max_relative_error: Some(1e-2),
+            ..Default::default()
+        };
+        let comparison = |tensors, skipped| Comparison {
+            tensors,
+            skipped,
+            only_in_first: vec!["extra".to_string()],
+            only_in_second: vec![],
+        };
+
+        let passing = comparison(vec![tensor("a", 1e-3, 0)], vec![]);
+        assert!(compare_status(&passing, &thresholds).is_ok());
+
+        // tensors that cannot be decoded are reported, but do not fail
+        let skipped = comparison(
+            vec![tensor("a", 1e-3, 0)],
+            vec![("b".to_string(), SkipReason::Dtype(Dtype::Int8))],
+        );
+        assert!(compare_status(&skipped, &thresholds).is_ok());
+
+        let over = comparison(vec![tensor("a", 1e-1, 0), tensor("b", 1e-3, 0)], vec![]);
+        let err = compare_status(&over, &thresholds).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "1 of 2 tensors are not within the thresholds, 0 have a different shape"
+        );
+
+        let nan = comparison(vec![tensor("a", 0.0, 1)], vec![]);
+        assert!(compare_status(&nan, &thresholds).is_err());
+
+        let shape = comparison(
+            vec![tensor("a", 1e-3, 0)],
+            vec![(
+                "b".to_string(),
+                SkipReason::Shape {
+                    first: vec![2],
+                    second: vec![3],
+                },
+            )],
+        );
+        assert!(compare_status(&shape, &thresholds).is_err());
+
+        let nothing = comparison(
+            vec![],
+            vec![("b".to_string(), SkipReason::Dtype(Dtype::Int8))],
+        );
+        let err = compare_status(&nothing, &thresholds).unwrap_err();
+        assert!(err.to_string().starts_with("No tensors were compared"));
+    }
+
+    #[test]
+    fn test_verify_status() {
+        let entry = TensorEntry {
+            name: "weight".to_string(),
+            dtype: Dtype::Fp16,
+            shape: vec![2],
+            data_offsets: [0, 4],
+            digest: "ab".to_string(),
+        };
+
+        let report = ManifestReport {
+            matched: 3,
+            mismatches: vec![],
+        };
+        assert!(verify_status(&report).is_ok());
+
+        let mismatches = [
+            TensorMismatch::Missing(entry.clone()),
+            TensorMismatch::Unexpected(entry.clone()),
+            TensorMismatch::Changed {
+                expected: entry.clone(),
+                actual: TensorEntry {
+                    digest: "cd".to_string(),
+                    ..entry.clone()
+                },
+            },
+        ];
+        for mismatch in mismatches {
+            let report = ManifestReport {
+                matched: 2,
+                mismatches: vec![mismatch],
+            };
+            let err = verify_status(&report).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                "1 tensors differ from the manifest, 2 match"
+            );
+        }
+    }
+}
@@ -1,5 +1,14 @@
-use crate::metadata::{Metadata, ModelSpec, Weights};
+use crate::info::Summary;
+use crate::layers::LayerColumn;
+use crate::params::ParamCount;
+use crate::source::Inspected;
+use crate::utils::{format_with_separators, pretty_byte_size, pretty_param_size};
+use safemetadata::compare::{TensorComparison, Thresholds};
+use safemetadata::metadata::{Metadata, ModelSpec, Weight, Weights};
+use safemetadata::parser::HeaderInfo;
 use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt::Display;
 use std::vec;
 use tabled::{
     builder::Builder,
@@ -8,11 +17,14 @@ use tabled::{
 };
 use terminal_size::{terminal_size, Height as TerminalHeight, Width as TerminalWidth};
 
-fn get_terminal_size() -> (usize, usize) {
-    let (TerminalWidth(width), TerminalHeight(height)) =
-        terminal_size().expect("failed to obtain a terminal size");
+// used when the output is not a terminal (e.g. piped to a file)
+const DEFAULT_TERMINAL_SIZE: (usize, usize) = (120, 40);
 
-    (width as usize, height as usize)
+fn get_terminal_size() -> (usize, usize) {
+    match terminal_size() {
+        Some((TerminalWidth(width), TerminalHeight(height))) => (width as usize, height as usize),
+        None => DEFAULT_TERMINAL_SIZE,
+    }
 }
 
 pub trait InfoTable {
@@ -44,7 +56,7 @@ impl InfoTable for Metadata {
 
         builder.push_record(vec!["Key".to_string(), "Value".to_string()]);
 
-        let value = serde_json::to_value(&self).unwrap();
+        let value = serde_json::to_value(self).unwrap();
 
         if let Value::Object(map) = value {
             for (key, value) in map {
@@ -56,9 +68,7 @@ impl InfoTable for Metadata {
             }
         }
 
-        let table = self.build_table(builder);
-
-        table
+        self.build_table(builder)
     }
 }
 
@@ -82,9 +92,336 @@ impl InfoTable for Weights {
             ]);
         }
 
-        let table = self.build_table(builder);
+        self.build_table(builder)
+    }
+}
+
+/// Selected layers with the columns to show.
+pub struct LayersView<'a> {
+    pub layers: Vec<(&'a String, &'a Weight)>,
+    pub columns: Vec<LayerColumn>,
+    /// The total byte size of all tensors in the file.
+    pub total_bytes: i64,
+}
+
+impl InfoTable for LayersView<'_> {
+    fn format_table(&self) -> Table {
+        let mut builder = self.create_builder();
+
+        builder.push_record(self.columns.iter().map(|column| match column {
+            LayerColumn::Name => "Parameter Name".to_string(),
+            LayerColumn::Dtype => "DType".to_string(),
+            LayerColumn::Shape => "Shape".to_string(),
+            LayerColumn::Elements => "Elements".to_string(),
+            LayerColumn::Bytes => "Bytes".to_string(),
+            LayerColumn::Offsets => "Offsets".to_string(),
+            LayerColumn::Percent => "%".to_string(),
+        }));
+
+        for (name, weight) in self.layers.iter() {
+            builder.push_record(self.columns.iter().map(|column| match column {
+                LayerColumn::Name => name.to_string(),
+                LayerColumn::Dtype => weight.dtype.to_string(),
+                LayerColumn::Shape => format!("{:?}", weight.shape),
+                LayerColumn::Elements => weight.num_elements().to_string(),
+                LayerColumn::Bytes => pretty_byte_size(weight.byte_size() as u64),
+                LayerColumn::Offsets => {
+                    format!("{}..{}", weight.data_offsets[0], weight.data_offsets[1])
+                }
+                LayerColumn::Percent => {
+                    let percent = match self.total_bytes {
+                        0 => 0.,
+                        total => weight.byte_size() as f64 / total as f64 * 100.,
+                    };
+                    format!("{:.2}%", percent)
+                }
+            }));
+        }
+
+        self.build_table(builder)
+    }
+}
+
+/// The differences of the tensors of two files, checked against the thresholds.
+pub struct CompareView<'a> {
+    pub tensors: &'a [TensorComparison],
+    pub thresholds: Thresholds,
+}
+
+impl InfoTable for CompareView<'_> {
+    fn format_table(&self) -> Table {
+        let mut builder = self.create_builder();
+
+        builder.push_record([
+            "Parameter Name",
+            "DType",
+            "Max Abs Diff",
+            "Mean Abs Diff",
+            "Cosine",
+            "Rel Error",
+            "Result",
+        ]);
+        for tensor in self.tensors {
+            let dtype = match tensor.first_dtype == tensor.second_dtype {
+                true => tensor.first_dtype.to_string(),
+                false => format!("{} -> {}", tensor.first_dtype, tensor.second_dtype),
+            };
+            let mismatches = [
+                (tensor.nan_mismatches, "NaN"),
+                (tensor.inf_mismatches, "infinity"),
+            ]
+            .into_iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, kind)| format!("{} {} mismatches", count, kind))
+            .collect::<Vec<_>>();
+            let result = match (tensor.passes(&self.thresholds), mismatches.is_empty()) {
+                (true, _) => "ok".to_string(),
+                (false, true) => "FAIL".to_string(),
+                (false, false) => format!("FAIL ({})", mismatches.join(", ")),
+            };
+            builder.push_record([
+                tensor.name.clone(),
+                dtype,
+                format!("{:.3e}", tensor.max_abs_diff),
+                format!("{:.3e}", tensor.mean_abs_diff),
+                format!("{:.6}", tensor.cosine_similarity),
+                format!("{:.3e}", tensor.relative_error),
+                result,
+            ]);
+        }
+
+        self.build_table(builder)
+    }
+}
+
+/// Parameter counts grouped by `K` (e.g. dtype or module name).
+pub struct BreakdownView<'a, K> {
+    /// The header of the group column.
+    pub label: &'a str,
+    pub groups: &'a BTreeMap<K, ParamCount>,
+    pub total: ParamCount,
+    /// Use binary units (1K = 1024) instead of SI units.
+    pub binary: bool,
+}
+
+impl<K: Display> InfoTable for BreakdownView<'_, K> {
+    fn format_table(&self) -> Table {
+        let mut builder = self.create_builder();
+
+        builder.push_record(vec![
+            self.label.to_string(),
+            "Tensors".to_string(),
+            "Parameters".to_string(),
+            "Exact".to_string(),
+            "%".to_string(),
+            "Bytes".to_string(),
+        ]);
+
+        for (group, count) in self.groups.iter() {
+            let (params, unit) = pretty_param_size(count.params, self.binary);
+            let percent = match self.total.params {
+                0 => 0.,
+                total => count.params as f64 / total as f64 * 100.,
+            };
+
+            builder.push_record(vec![
+                group.to_string(),
+                count.tensors.to_string(),
+                format!("{}{}", params, unit),
+                format_with_separators(count.params),
+                format!("{:.2}%", percent),
+                pretty_byte_size(count.bytes as u64),
+            ]);
+        }
+
+        self.build_table(builder)
+    }
+}
+
+impl InfoTable for Summary {
+    fn format_table(&self) -> Table {
+        let mut builder = self.create_builder();
+
+        builder.push_record(vec!["Key".to_string(), "Value".to_string()]);
+
+        let (params, unit) = pretty_param_size(self.params, false);
+        let model_spec = match &self.model_spec {
+            Some(spec) => format!(
+                "yes (title: {}, architecture: {})",
+                spec.title.as_deref().unwrap_or("-"),
+                spec.architecture.as_deref().unwrap_or("-")
+            ),
+            None => "no".to_string(),
+        };
+        let warnings = match self.warnings.len() {
+            0 => "none".to_string(),
+            _ => self
+                .warnings
+                .iter()
+                .map(|warning| warning.to_string())
+                .collect::<Vec<_>>()
+                .join("\n"),
+        };
+
+        let rows = vec![
+            ("File size", pretty_byte_size(self.file_size)),
+            ("Header size", pretty_byte_size(self.header_size)),
+            ("Tensors", self.tensors.to_string()),
+            (
+                "Parameters",
+                format!(
+                    "{}{} ({})",
+                    params,
+                    unit,
+                    format_with_separators(self.params)
+                ),
+            ),
+            ("DTypes", self.dtype_distribution()),
+            (
+                "Tensor format",
+                match &self.format {
+                    Some(format) => format.to_string(),
+                    None => "unknown".to_string(),
+                },
+            ),
+            ("ModelSpec", model_spec),
+            ("Custom metadata keys", self.custom_keys.to_string()),
+            ("Warnings", warnings),
+        ];
+
+        for (key, value) in rows {
+            builder.push_record(vec![key.to_string(), value]);
+        }
+
+        self.build_table(builder)
+    }
+}
+
+/// Which columns to show for each file.
+#[derive(Debug, Clone, Copy)]
+pub enum BatchKind {
+    Info,
+    Params { binary: bool },
+    ModelSpec,
+}
+
+/// One row per file, with the error if the file could not be read.
+pub struct BatchView<'a> {
+    pub kind: BatchKind,
+    pub files: &'a [Inspected],
+}
+
+impl BatchView<'_> {
+    fn headers(&self) -> Vec<&'static str> {
+        match self.kind {
+            BatchKind::Info => vec![
+                "File",
+                "Size",
+                "Tensors",
+                "Parameters",
+                "DTypes",
+                "Format",
+                "ModelSpec",
+                "Custom keys",
+                "Warnings",
+            ],
+            BatchKind::Params { .. } => {
+                vec!["File", "Parameters", "Exact", "Tensor data", "File size"]
+            }
+            BatchKind::ModelSpec => vec!["File", "Title", "Architecture", "Author", "License"],
+        }
+    }
+
+    fn cells(&self, info: &HeaderInfo) -> Vec<String> {
+        match self.kind {
+            BatchKind::Info => {
+                let summary = Summary::new(info);
+                let (params, unit) = pretty_param_size(summary.params, false);
+
+                vec![
+                    pretty_byte_size(summary.file_size),
+                    summary.tensors.to_string(),
+                    format!("{}{}", params, unit),
+                    summary.dtype_distribution(),
+                    match &summary.format {
+                        Some(format) => format.to_string(),
+                        None => "-".to_string(),
+                    },
+                    match &summary.model_spec {
+                        Some(spec) => spec.title.clone().unwrap_or("yes".to_string()),
+                        None => "-".to_string(),
+                    },
+                    summary.custom_keys.to_string(),
+                    summary.warnings.len().to_string(),
+                ]
+            }
+            BatchKind::Params { binary } => {
+                let params = info
+                    .header
+                    .weights
+                    .values()
+                    .map(|weight| weight.num_elements())
+                    .sum::<i64>();
+                let (pretty_params, unit) = pretty_param_size(params, binary);
+
+                vec![
+                    format!("{}{}", pretty_params, unit),
+                    format_with_separators(params),
+                    pretty_byte_size(info.tensor_bytes()),
+                    pretty_byte_size(info.file_size),
+                ]
+            }
+            BatchKind::ModelSpec => {
+                let spec = info
+                    .header
+                    .metadata
+                    .as_ref()
+                    .and_then(|metadata| metadata.model_spec.as_ref());
+                let field = |get: fn(&ModelSpec) -> &Option<String>| {
+                    spec.and_then(|spec| get(spec).clone())
+                        .unwrap_or("-".to_string())
+                };
+
+                vec![
+                    field(|spec| &spec.title),
+                    field(|spec| &spec.architecture),
+                    field(|spec| &spec.author),
+                    field(|spec| &spec.license),
+                ]
+            }
+        }
+    }
+}
+
+impl InfoTable for BatchView<'_> {
+    fn format_table(&self) -> Table {
+        let mut builder = self.create_builder();
 
-        table
+        let headers = self.headers();
+        let columns = headers.len();
+        builder.push_record(
+            headers
+                .into_iter()
+                .chain(["Error"])
+                .map(|header| header.to_string()),
+        );
+
+        for file in self.files {
+            let mut record = vec![file.name.clone()];
+            match &file.result {
+                Ok(info) => {
+                    record.extend(self.cells(info));
+                    record.push(String::new());
+                }
+                Err(err) => {
+                    record.extend(vec![String::new(); columns - 1]);
+                    record.push(format!("{:#}", err));
+                }
+            }
+            builder.push_record(record);
+        }
+
+        self.build_table(builder)
     }
 }
 
@@ -94,7 +431,7 @@ impl InfoTable for ModelSpec {
 
         builder.push_record(vec!["Key".to_string(), "Value".to_string()]);
 
-        let value = serde_json::to_value(&self).unwrap();
+        let value = serde_json::to_value(self).unwrap();
 
         if let Value::Object(map) = value {
             for (key, value) in map {
@@ -106,8 +443,45 @@ impl InfoTable for ModelSpec {
             }
         }
 
-        let table = self.build_table(builder);
+        self.build_table(builder)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use safemetadata::metadata::Dtype;
+
+    #[test]
+    fn test_layers_view_columns() {
+        let weights = Weights::from([(
+            "lm_head.weight".to_string(),
+            Weight {
+                dtype: Dtype::Fp16,
+                shape: vec![2, 4],
+                data_offsets: [16, 32],
+            },
+        )]);
+        let view = LayersView {
+            layers: weights.iter().collect(),
+            columns: vec![
+                LayerColumn::Offsets,
+                LayerColumn::Name,
+                LayerColumn::Percent,
+            ],
+            total_bytes: 64,
+        };
+
+        let table = view.format_table().to_string();
+        let lines = table.lines().collect::<Vec<_>>();
+        let header = lines.iter().find(|line| line.contains("Offsets")).unwrap();
+        assert!(header.find("Offsets") < header.find("Parameter Name"));
+        assert!(header.find("Parameter Name") < header.find('%'));
+        assert!(!table.contains("DType"));
+        assert!(!table.contains("Shape"));
 
-        table
+        let row = lines.iter().find(|line| line.contains("lm_head")).unwrap();
+        assert!(row.contains("16..32"));
+        assert!(row.contains("25.00%"));
     }
 }
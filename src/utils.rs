@@ -1,5 +1,9 @@
+use std::cmp::Ordering;
+
 //  Million, Billion, Trillion
-const PARAM_UNITS: &'static [&'static str] = &["", "K", "M", "B", "T"];
+const PARAM_UNITS: &[&str] = &["", "K", "M", "B", "T"];
+
+const BYTE_UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
 
 pub fn pretty_floating_point(value: f64) -> String {
     if value == value.floor() {
@@ -10,52 +14,192 @@ pub fn pretty_floating_point(value: f64) -> String {
     }
 }
 
-/// Prettify the parameter size
-pub fn pretty_param_size(param_size: i64) -> (String, &'static str) {
+/// Prettify the parameter size.
+///
+/// Uses SI units (1K = 1000) by default, or binary units (1K = 1024) if `binary` is set.
+pub fn pretty_param_size(param_size: i64, binary: bool) -> (String, &'static str) {
+    let base = if binary { 1024. } else { 1000. };
+
     let mut param_size = param_size as f64;
-    for (_i, unit) in PARAM_UNITS.iter().enumerate() {
-        if param_size < 1024. / 10. {
+    for unit in PARAM_UNITS.iter() {
+        if param_size < base / 10. {
             // 少数第一位まで表示。小数点以下が0の場合は表示しない
             return (pretty_floating_point(param_size), unit);
         } else {
-            param_size /= 1024.;
+            param_size /= base;
         }
     }
 
+    // larger than the last unit: the loop divided once more than the unit accounts for
     (
-        pretty_floating_point(param_size),
+        pretty_floating_point(param_size * base),
         PARAM_UNITS.last().unwrap(),
     )
 }
 
+/// Format an integer with thousands separators, e.g. `7,000,000,000`
+pub fn format_with_separators(value: i64) -> String {
+    let digits = value.unsigned_abs().to_string();
+    let mut formatted = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+
+    if value < 0 {
+        format!("-{}", formatted)
+    } else {
+        formatted
+    }
+}
+
+/// Prettify the byte size with binary units
+pub fn pretty_byte_size(byte_size: u64) -> String {
+    let mut size = byte_size as f64;
+    for unit in BYTE_UNITS.iter() {
+        if size < 1024. {
+            return format!("{} {}", pretty_floating_point(size), unit);
+        }
+        size /= 1024.;
+    }
+
+    format!(
+        "{} {}",
+        pretty_floating_point(size * 1024.),
+        BYTE_UNITS.last().unwrap()
+    )
+}
+
+/// Compare two strings treating runs of digits as numbers,
+/// so that `layers.2` comes before `layers.10`.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+
+    loop {
+        match (a_chars.peek(), b_chars.peek()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let mut x_digits = String::new();
+                while let Some(c) = a_chars.next_if(|c| c.is_ascii_digit()) {
+                    x_digits.push(c);
+                }
+                let mut y_digits = String::new();
+                while let Some(c) = b_chars.next_if(|c| c.is_ascii_digit()) {
+                    y_digits.push(c);
+                }
+
+                // compare by value first, ignoring leading zeros
+                let x_trimmed = x_digits.trim_start_matches('0');
+                let y_trimmed = y_digits.trim_start_matches('0');
+                let ordering = x_trimmed
+                    .len()
+                    .cmp(&y_trimmed.len())
+                    .then_with(|| x_trimmed.cmp(y_trimmed))
+                    .then_with(|| x_digits.len().cmp(&y_digits.len()));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.cmp(y);
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a_chars.next();
+                b_chars.next();
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
     #[test]
     fn test_pretty_param_size() {
-        assert_eq!(pretty_param_size(0), ("0".to_string(), ""));
-        assert_eq!(pretty_param_size(1), ("1".to_string(), ""));
-        assert_eq!(pretty_param_size(500), ("0.5".to_string(), "K"));
-        assert_eq!(pretty_param_size(1023), ("1.0".to_string(), "K"));
-        assert_eq!(pretty_param_size(1024), ("1".to_string(), "K"));
-        assert_eq!(pretty_param_size(1024 * 1024), ("1".to_string(), "M"));
-        assert_eq!(
-            pretty_param_size((3.5 * 1024. * 1024.) as i64),
+        assert_eq!(pretty_param_size(0, false), ("0".to_string(), ""));
+        assert_eq!(pretty_param_size(1, false), ("1".to_string(), ""));
+        assert_eq!(pretty_param_size(500, false), ("0.5".to_string(), "K"));
+        assert_eq!(pretty_param_size(1000, false), ("1".to_string(), "K"));
+        assert_eq!(
+            pretty_param_size(494_032_768, false),
+            ("0.5".to_string(), "B")
+        );
+        assert_eq!(
+            pretty_param_size(7_000_000_000, false),
+            ("7".to_string(), "B")
+        );
+        assert_eq!(
+            pretty_param_size(3_500_000_000_000, false),
+            ("3.5".to_string(), "T")
+        );
+        assert_eq!(
+            pretty_param_size(5_000_000_000_000_000, false),
+            ("5000".to_string(), "T")
+        );
+    }
+
+    #[test]
+    fn test_pretty_param_size_binary() {
+        assert_eq!(pretty_param_size(0, true), ("0".to_string(), ""));
+        assert_eq!(pretty_param_size(1, true), ("1".to_string(), ""));
+        assert_eq!(pretty_param_size(500, true), ("0.5".to_string(), "K"));
+        assert_eq!(pretty_param_size(1023, true), ("1.0".to_string(), "K"));
+        assert_eq!(pretty_param_size(1024, true), ("1".to_string(), "K"));
+        assert_eq!(pretty_param_size(1024 * 1024, true), ("1".to_string(), "M"));
+        assert_eq!(
+            pretty_param_size((3.5 * 1024. * 1024.) as i64, true),
             ("3.5".to_string(), "M")
         );
-        assert_eq!(pretty_param_size(2 * 1024 * 1024), ("2".to_string(), "M"));
         assert_eq!(
-            pretty_param_size(3 * 1024 * 1024 * 1024),
+            pretty_param_size(2 * 1024 * 1024, true),
+            ("2".to_string(), "M")
+        );
+        assert_eq!(
+            pretty_param_size(3 * 1024 * 1024 * 1024, true),
             ("3".to_string(), "B")
         );
         assert_eq!(
-            pretty_param_size(4 * 1024 * 1024 * 1024 * 1024),
+            pretty_param_size(4 * 1024 * 1024 * 1024 * 1024, true),
             ("4".to_string(), "T")
         );
         assert_eq!(
-            pretty_param_size(5 * 1024 * 1024 * 1024 * 1024 * 1024),
-            ("5".to_string(), "T")
+            pretty_param_size(5 * 1024 * 1024 * 1024 * 1024 * 1024, true),
+            ("5120".to_string(), "T")
         );
     }
+
+    #[test]
+    fn test_format_with_separators() {
+        assert_eq!(format_with_separators(0), "0");
+        assert_eq!(format_with_separators(999), "999");
+        assert_eq!(format_with_separators(1000), "1,000");
+        assert_eq!(format_with_separators(7_000_000_000), "7,000,000,000");
+        assert_eq!(format_with_separators(-1234567), "-1,234,567");
+    }
+
+    #[test]
+    fn test_pretty_byte_size() {
+        assert_eq!(pretty_byte_size(0), "0 B");
+        assert_eq!(pretty_byte_size(1023), "1023 B");
+        assert_eq!(pretty_byte_size(1024), "1 KiB");
+        assert_eq!(pretty_byte_size(1536), "1.5 KiB");
+        assert_eq!(pretty_byte_size(3 * 1024 * 1024 * 1024), "3 GiB");
+    }
+
+    #[test]
+    fn test_natural_cmp() {
+        assert_eq!(natural_cmp("layers.2", "layers.10"), Ordering::Less);
+        assert_eq!(natural_cmp("layers.10", "layers.2"), Ordering::Greater);
+        assert_eq!(natural_cmp("layers.2.a", "layers.2.b"), Ordering::Less);
+        assert_eq!(natural_cmp("layers.02", "layers.2"), Ordering::Greater);
+        assert_eq!(natural_cmp("layers", "layers.0"), Ordering::Less);
+        assert_eq!(natural_cmp("abc", "abc"), Ordering::Equal);
+    }
 }
@@ -0,0 +1,206 @@
+use std::fmt::Display;
+
+use crate::parser::HeaderInfo;
+
+/// A problem found in the header that may make the file unloadable
+/// or indicate corruption.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationWarning {
+    /// The header size is not a multiple of 8, so the data section is not aligned.
+    UnalignedHeader { header_size: u64 },
+    /// `data_offsets` are negative or the end is before the begin.
+    InvalidOffsets { name: String },
+    /// The byte size does not match the dtype and the shape.
+    SizeMismatch {
+        name: String,
+        expected: i64,
+        actual: i64,
+    },
+    /// The tensor overlaps with the previous tensor in the data section.
+    Overlap { name: String, previous: String },
+    /// There are unreferenced bytes before the tensor.
+    Hole { name: String, bytes: i64 },
+    /// The tensor data extends beyond the end of the file.
+    OutOfBounds { name: String },
+    /// There are unreferenced bytes after the last tensor.
+    TrailingBytes { bytes: u64 },
+}
+
+impl Display for ValidationWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationWarning::UnalignedHeader { header_size } => {
+                write!(f, "header size {} is not a multiple of 8", header_size)
+            }
+            ValidationWarning::InvalidOffsets { name } => {
+                write!(f, "{}: invalid data offsets", name)
+            }
+            ValidationWarning::SizeMismatch {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{}: expected {} bytes from dtype and shape, but has {} bytes",
+                name, expected, actual
+            ),
+            ValidationWarning::Overlap { name, previous } => {
+                write!(f, "{}: overlaps with {}", name, previous)
+            }
+            ValidationWarning::Hole { name, bytes } => {
+                write!(
+                    f,
+                    "{}: {} unreferenced bytes before the tensor",
+                    name, bytes
+                )
+            }
+            ValidationWarning::OutOfBounds { name } => {
+                write!(f, "{}: data extends beyond the end of the file", name)
+            }
+            ValidationWarning::TrailingBytes { bytes } => {
+                write!(f, "{} unreferenced bytes after the last tensor", bytes)
+            }
+        }
+    }
+}
+
+/// Check the header against the safetensors format rules.
+pub fn validate(info: &HeaderInfo) -> Vec<ValidationWarning> {
+    let mut warnings = Vec::new();
+
+    if !info.header_size.is_multiple_of(8) {
+        warnings.push(ValidationWarning::UnalignedHeader {
+            header_size: info.header_size,
+        });
+    }
+
+    let mut weights = info.header.weights.iter().collect::<Vec<_>>();
+    weights.sort_by_key(|(_, weight)| weight.data_offsets);
+
+    let data_size = info.data_size() as i64;
+    let mut previous: Option<(&String, i64)> = None; // (name, end)
+
+    for (name, weight) in weights {
+        let [begin, end] = weight.data_offsets;
+        if begin < 0 || end < begin || weight.shape.iter().any(|dim| *dim < 0) {
+            warnings.push(ValidationWarning::InvalidOffsets { name: name.clone() });
+            continue;
+        }
+
+        let expected = (weight.num_elements() * weight.dtype.bits() as i64 + 7) / 8;
+        if expected != weight.byte_size() {
+            warnings.push(ValidationWarning::SizeMismatch {
+                name: name.clone(),
+                expected,
+                actual: weight.byte_size(),
+            });
+        }
+
+        let previous_end = previous.map(|(_, end)| end).unwrap_or(0);
+        if begin < previous_end {
+            warnings.push(ValidationWarning::Overlap {
+                name: name.clone(),
+                previous: previous.unwrap().0.clone(),
+            });
+        } else if begin > previous_end {
+            warnings.push(ValidationWarning::Hole {
+                name: name.clone(),
+                bytes: begin - previous_end,
+            });
+        }
+
+        if end > data_size {
+            warnings.push(ValidationWarning::OutOfBounds { name: name.clone() });
+        }
+
+        if end >= previous_end {
+            previous = Some((name, end));
+        }
+    }
+
+    let last_end = previous.map(|(_, end)| end).unwrap_or(0);
+    if data_size > last_end {
+        warnings.push(ValidationWarning::TrailingBytes {
+            bytes: (data_size - last_end) as u64,
+        });
+    }
+
+    warnings
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::metadata::{Dtype, Header, Weight, Weights};
+
+    fn header_info(weights: Vec<(&str, Dtype, Vec<i64>, [i64; 2])>, data_size: u64) -> HeaderInfo {
+        let weights = weights
+            .into_iter()
+            .map(|(name, dtype, shape, data_offsets)| {
+                (
+                    name.to_string(),
+                    Weight {
+                        dtype,
+                        shape,
+                        data_offsets,
+                    },
+                )
+            })
+            .collect::<Weights>();
+
+        HeaderInfo {
+            header: Header {
+                metadata: None,
+                weights,
+            },
+            header_size: 64,
+            file_size: 8 + 64 + data_size,
+        }
+    }
+
+    #[test]
+    fn test_validate_valid() {
+        let info = header_info(
+            vec![
+                ("a", Dtype::Fp32, vec![2, 2], [0, 16]),
+                ("b", Dtype::Fp16, vec![4], [16, 24]),
+            ],
+            24,
+        );
+
+        assert_eq!(validate(&info), vec![]);
+    }
+
+    #[test]
+    fn test_validate_invalid() {
+        let info = header_info(
+            vec![
+                ("a", Dtype::Fp32, vec![2, 2], [0, 16]),
+                ("b", Dtype::Fp16, vec![4], [8, 16]),
+                ("c", Dtype::Fp16, vec![3], [20, 26]),
+                ("d", Dtype::Int8, vec![4], [26, 40]),
+            ],
+            48,
+        );
+
+        assert_eq!(
+            validate(&info),
+            vec![
+                ValidationWarning::Overlap {
+                    name: "b".to_string(),
+                    previous: "a".to_string()
+                },
+                ValidationWarning::Hole {
+                    name: "c".to_string(),
+                    bytes: 4
+                },
+                ValidationWarning::SizeMismatch {
+                    name: "d".to_string(),
+                    expected: 4,
+                    actual: 14
+                },
+                ValidationWarning::TrailingBytes { bytes: 8 },
+            ]
+        );
+    }
+}
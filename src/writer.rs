@@ -0,0 +1,684 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::File,
+    io::{self, BufWriter, Read, Seek, SeekFrom, Take, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use crate::atomic::AtomicFile;
+use crate::document::from_value;
+use crate::fetch::RemoteFile;
+use crate::file::{serialize_header, WriteReport};
+use crate::hash::HashKind;
+use crate::metadata::{Dtype, Header, Metadata, ModelSpec, Weight, Weights};
+use crate::parser::HeaderInfo;
+use crate::patch::diff;
+use crate::progress::{copy_with_progress, Progress};
+
+/// The key of the metadata in the header, which cannot be used as a tensor name.
+const METADATA_KEY: &str = "__metadata__";
+
+/// Where the bytes of a tensor come from.
+pub enum TensorSource {
+    /// Bytes in memory.
+    Bytes(Vec<u8>),
+    /// A byte range of a file, e.g. a tensor of another safetensors file.
+    File {
+        path: PathBuf,
+        offset: u64,
+        length: u64,
+    },
+    /// A byte range of a remote file, fetched with a range request when it is written.
+    Remote {
+        file: RemoteFile,
+        offset: u64,
+        length: u64,
+    },
+    /// A reader that yields exactly `length` bytes.
+    Reader {
+        reader: Box<dyn Read + Send>,
+        length: u64,
+    },
+}
+
+impl TensorSource {
+    pub fn len(&self) -> u64 {
+        match self {
+            TensorSource::Bytes(bytes) => bytes.len() as u64,
+            TensorSource::File { length, .. } => *length,
+            TensorSource::Remote { length, .. } => *length,
+            TensorSource::Reader { length, .. } => *length,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn copy_to<W: Write>(self, writer: &mut W, progress: Option<&Progress>) -> Result<u64> {
+        let length = self.len();
+        if length == 0 {
+            return Ok(0);
+        }
+        let copied = match self {
+            TensorSource::Bytes(bytes) => {
+                copy_with_progress(&mut &bytes[..], writer, length, progress)?
+            }
+            TensorSource::File { path, offset, .. } => {
+                let mut file = File::open(path)?;
+                file.seek(SeekFrom::Start(offset))?;
+                copy_with_progress(&mut file, writer, length, progress)?
+            }
+            TensorSource::Remote { file, offset, .. } => {
+                let mut response = file.open_range(offset, length)?;
+                copy_with_progress(&mut response, writer, length, progress)?
+            }
+            TensorSource::Reader { mut reader, .. } => {
+                copy_with_progress(&mut reader, writer, length, progress)?
+            }
+        };
+
+        Ok(copied)
+    }
+}
+
+impl From<Vec<u8>> for TensorSource {
+    fn from(bytes: Vec<u8>) -> Self {
+        TensorSource::Bytes(bytes)
+    }
+}
+
+/// A byte range of a file that is opened on the first read, so that many
+/// pending [`TensorSource::Reader`]s don't keep files open.
+pub struct FileRange {
+    path: PathBuf,
+    offset: u64,
+    length: u64,
+    file: Option<Take<File>>,
+}
+
+impl FileRange {
+    pub fn new<P: AsRef<Path>>(path: P, offset: u64, length: u64) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            offset,
+            length,
+            file: None,
+        }
+    }
+}
+
+impl Read for FileRange {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                let mut file = File::open(&self.path)?;
+                file.seek(SeekFrom::Start(self.offset))?;
+                self.file.insert(file.take(self.length))
+            }
+        };
+        file.read(buf)
+    }
+}
+
+/// The tensors that were skipped or renamed by [`SafetensorsWriter::add_tensors_from`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Selection {
+    pub skipped: Vec<String>,
+    /// `(old, new)` names.
+    pub renamed: Vec<(String, String)>,
+}
+
+struct PendingTensor {
+    name: String,
+    dtype: Dtype,
+    shape: Vec<i64>,
+    source: TensorSource,
+}
+
+/// Creates a safetensors file from named tensors.
+///
+/// The tensors are ordered by decreasing element size, then by name, like the
+/// reference implementation does, so that every tensor is aligned to its
+/// element size. The header is padded so that the data section is aligned.
+/// The data is streamed from the sources to a temporary file, which replaces
+/// the output path once it is complete.
+///
+/// The hashes of the tensor data stored in the metadata, such as
+/// `modelspec.hash_sha256`, are dropped, since the data is laid out anew.
+///
+/// ```no_run
+/// use safemetadata::metadata::Dtype;
+/// use safemetadata::writer::SafetensorsWriter;
+///
+/// let mut writer = SafetensorsWriter::new();
+/// writer.add_tensor("weight", Dtype::Fp32, vec![2], vec![0u8; 8].into())?;
+/// writer.write("model.safetensors")?;
+/// # anyhow::Ok(())
+/// ```
+#[derive(Default)]
+pub struct SafetensorsWriter {
+    tensors: Vec<PendingTensor>,
+    names: HashSet<String>,
+    metadata: BTreeMap<String, String>,
+    headroom: usize,
+    progress: Option<Progress>,
+}
+
+impl SafetensorsWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the metadata entries, overwriting existing keys.
+    pub fn with_metadata(mut self, metadata: BTreeMap<String, String>) -> Self {
+        self.metadata.extend(metadata);
+        self
+    }
+
+    /// Add the modelspec keys to the metadata.
+    pub fn with_model_spec(mut self, model_spec: &ModelSpec) -> Result<Self> {
+        self.metadata
+            .extend(from_value(serde_json::to_value(model_spec)?)?);
+        Ok(self)
+    }
+
+    /// Reserve extra bytes in the header, so that later edits can be done in place.
+    pub fn with_headroom(mut self, headroom: usize) -> Self {
+        self.headroom = headroom;
+        self
+    }
+
+    /// Report the bytes of tensor data written.
+    pub fn with_progress(mut self, progress: Progress) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Add a tensor. Fails if the name is already used or the size of the
+    /// source does not match the dtype and the shape.
+    pub fn add_tensor(
+        &mut self,
+        name: &str,
+        dtype: Dtype,
+        shape: Vec<i64>,
+        source: TensorSource,
+    ) -> Result<()> {
+        if name == METADATA_KEY {
+            return Err(anyhow::anyhow!("{} is not a valid tensor name", name));
+        }
+        if self.names.contains(name) {
+            return Err(anyhow::anyhow!("Duplicate tensor name: {}", name));
+        }
+        let expected = shape
+            .iter()
+            .try_fold(dtype.bits() as u64, |bits, dim| {
+                bits.checked_mul(u64::try_from(*dim).ok()?)
+            })
+            .map(|bits| bits.div_ceil(8))
+            .ok_or_else(|| anyhow::anyhow!("{}: invalid shape {:?}", name, shape))?;
+        if source.len() != expected {
+            return Err(anyhow::anyhow!(
+                "{}: expected {} bytes from dtype and shape, but the source has {} bytes",
+                name,
+                expected,
+                source.len()
+            ));
+        }
+
+        self.names.insert(name.to_string());
+        self.tensors.push(PendingTensor {
+            name: name.to_string(),
+            dtype,
+            shape,
+            source,
+        });
+
+        Ok(())
+    }
+
+    /// Add the tensors of another file.
+    ///
+    /// `select` returns the new name of the tensor, or `None` to skip it.
+    /// `source` returns the source of a byte range, given as the absolute
+    /// offset in the file and the length.
+    pub fn add_tensors_from<F, S>(
+        &mut self,
+        info: &HeaderInfo,
+        select: F,
+        source: S,
+    ) -> Result<Selection>
+    where
+        F: Fn(&str) -> Option<String>,
+        S: Fn(u64, u64) -> TensorSource,
+    {
+        let data_start = info.data_start();
+        let mut selection = Selection::default();
+
+        for (name, weight) in &info.header.weights {
+            let Some(new_name) = select(name) else {
+                selection.skipped.push(name.clone());
+                continue;
+            };
+            if &new_name != name {
+                selection.renamed.push((name.clone(), new_name.clone()));
+            }
+
+            let source = source(
+                data_start + weight.data_offsets[0] as u64,
+                weight.byte_size() as u64,
+            );
+            self.add_tensor(&new_name, weight.dtype, weight.shape.clone(), source)?;
+        }
+
+        Ok(selection)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tensors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tensors.is_empty()
+    }
+
+    /// The header of the file, with the tensors in the order they are written.
+    fn layout(&mut self) -> Result<Header> {
+        self.tensors.sort_by(|left, right| {
+            right
+                .dtype
+                .bits()
+                .cmp(&left.dtype.bits())
+                .then_with(|| left.name.cmp(&right.name))
+        });
+
+        let mut weights = Weights::new();
+        let mut offset = 0;
+        for tensor in &self.tensors {
+            let end = offset + tensor.source.len() as i64;
+            weights.insert(
+                tensor.name.clone(),
+                Weight {
+                    dtype: tensor.dtype,
+                    shape: tensor.shape.clone(),
+                    data_offsets: [offset, end],
+                },
+            );
+            offset = end;
+        }
+
+        let metadata = match self.metadata.is_empty() {
+            true => None,
+            false => Some(Metadata::from_map(self.metadata.clone())?),
+        };
+
+        Ok(Header { metadata, weights })
+    }
+
+    /// Write the file. The dropped hashes are listed as removed keys in the report.
+    pub fn write<P: AsRef<Path>>(mut self, output_path: P) -> Result<WriteReport> {
+        let old_metadata = self.metadata.clone();
+        for key in HashKind::metadata_keys() {
+            self.metadata.remove(key);
+        }
+        let changes = diff(&old_metadata, &self.metadata);
+
+        let header = self.layout()?;
+        let header_buffer = serialize_header(&header, self.headroom)?;
+        let header_size = header_buffer.len() as u64;
+        let total = self.tensors.iter().map(|tensor| tensor.source.len()).sum();
+
+        let mut output_file = AtomicFile::create(output_path.as_ref())?;
+        let mut writer = BufWriter::new(output_file.file());
+        writer.write_all(&header_size.to_le_bytes())?;
+        writer.write_all(&header_buffer)?;
+
+        let tensors_written = self.tensors.len();
+        let mut written = 0;
+        for tensor in self.tensors {
+            let progress = self.progress.clone().map(|progress| {
+                let base = written;
+                Progress::new(move |done, _| progress.update(base + done, total))
+            });
+            written += tensor.source.copy_to(&mut writer, progress.as_ref())?;
+        }
+        writer.flush()?;
+        drop(writer);
+        output_file.commit()?;
+
+        Ok(WriteReport {
+            output: output_path.as_ref().to_path_buf(),
+            changes,
+            new_header_size: header_size,
+            bytes_copied: written,
+            rewritten: true,
+            tensors_written,
+            ..Default::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::file::HEADER_ALIGNMENT;
+    use crate::hash::MODELSPEC_HASH_KEY;
+    use crate::parser::{LocalParser, MetadataParser};
+    use crate::patch::MetadataChange;
+    use crate::validate::validate;
+
+    /// The header and the data section of a written file.
+    fn read_file(path: &Path) -> (HeaderInfo, Vec<u8>) {
+        let info = LocalParser::new(path).parse_header_info().unwrap();
+        assert_eq!(validate(&info), vec![]);
+        let data = std::fs::read(path).unwrap()[info.data_start() as usize..].to_vec();
+        (info, data)
+    }
+
+    #[test]
+    fn test_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.safetensors");
+        let source_path = dir.path().join("source.bin");
+        std::fs::write(&source_path, (0..32u8).collect::<Vec<_>>()).unwrap();
+
+        let mut writer = SafetensorsWriter::new()
+            .with_metadata(BTreeMap::from([("format".to_string(), "pt".to_string())]))
+            .with_model_spec(&ModelSpec {
+                title: Some("test".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        writer
+            .add_tensor("b", Dtype::Fp16, vec![3], vec![1; 6].into())
+            .unwrap();
+        writer
+            .add_tensor(
+                "a",
+                Dtype::Fp32,
+                vec![2, 2],
+                TensorSource::File {
+                    path: source_path,
+                    offset: 8,
+                    length: 16,
+                },
+            )
+            .unwrap();
+        writer
+            .add_tensor("c", Dtype::Fp64, vec![1], vec![2; 8].into())
+            .unwrap();
+        assert_eq!(writer.len(), 3);
+
+        let report = writer.write(&path).unwrap();
+        assert_eq!(report.bytes_copied, 30);
+        assert_eq!(report.tensors_written, 3);
+        assert!(report.changes.is_empty());
+
+        let (info, data) = read_file(&path);
+        assert_eq!(info.header_size, report.new_header_size);
+
+        // ordered by decreasing element size, then by name
+        let weights = &info.header.weights;
+        assert_eq!(weights["c"].data_offsets, [0, 8]);
+        assert_eq!(weights["a"].data_offsets, [8, 24]);
+        assert_eq!(weights["b"].data_offsets, [24, 30]);
+
+        let metadata = info.header.metadata.as_ref().unwrap().to_map().unwrap();
+        assert_eq!(metadata["format"], "pt");
+        assert_eq!(metadata["modelspec.title"], "test");
+
+        assert_eq!(&data[..8], &[2; 8]);
+        assert_eq!(&data[8..24], &(8..24u8).collect::<Vec<_>>()[..]);
+        assert_eq!(&data[24..], &[1; 6]);
+    }
+
+    #[test]
+    fn test_add_tensor_errors() {
+        let mut writer = SafetensorsWriter::new();
+        writer
+            .add_tensor("a", Dtype::Fp32, vec![1], vec![0; 4].into())
+            .unwrap();
+
+        // duplicate and reserved names
+        assert!(writer
+            .add_tensor("a", Dtype::Fp32, vec![1], vec![0; 4].into())
+            .is_err());
+        assert!(writer
+            .add_tensor(METADATA_KEY, Dtype::Uint8, vec![1], vec![0].into())
+            .is_err());
+        // the size of the source does not match the dtype and the shape
+        assert!(writer
+            .add_tensor("d", Dtype::Fp32, vec![2], vec![0; 4].into())
+            .is_err());
+        // negative dimensions and sizes that overflow are rejected
+        assert!(writer
+            .add_tensor("e", Dtype::Uint8, vec![-1, -4], vec![0; 4].into())
+            .is_err());
+        assert!(writer
+            .add_tensor("f", Dtype::Fp64, vec![1 << 31, 1 << 31], vec![0; 8].into())
+            .is_err());
+
+        assert_eq!(writer.len(), 1);
+    }
+
+    #[test]
+    fn test_add_tensors_from() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("input.safetensors");
+        let path = dir.path().join("model.safetensors");
+        fixtures::write_model(
+            &input,
+            &[
+                ("keep", Dtype::Uint8, b"abc".to_vec()),
+                ("rename", Dtype::Uint8, b"de".to_vec()),
+                ("skip", Dtype::Uint8, b"f".to_vec()),
+            ],
+        );
+        let info = LocalParser::new(&input).parse_header_info().unwrap();
+
+        let mut writer = SafetensorsWriter::new();
+        let selection = writer
+            .add_tensors_from(
+                &info,
+                |name| match name {
+                    "skip" => None,
+                    "rename" => Some("renamed".to_string()),
+                    _ => Some(name.to_string()),
+                },
+                |offset, length| TensorSource::File {
+                    path: input.clone(),
+                    offset,
+                    length,
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            selection,
+            Selection {
+                skipped: vec!["skip".to_string()],
+                renamed: vec![("rename".to_string(), "renamed".to_string())],
+            }
+        );
+
+        // a renamed tensor cannot take the name of another one
+        let mut conflict = SafetensorsWriter::new();
+        let result = conflict.add_tensors_from(
+            &info,
+            |_| Some("same".to_string()),
+            |_, length| vec![0; length as usize].into(),
+        );
+        assert!(result.is_err());
+
+        writer.write(&path).unwrap();
+        let (info, data) = read_file(&path);
+        assert_eq!(
+            info.header.weights.keys().collect::<Vec<_>>(),
+            ["keep", "renamed"]
+        );
+        assert_eq!(data, b"abcde");
+    }
+
+    #[test]
+    fn test_reader_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.safetensors");
+        let source_path = dir.path().join("source.bin");
+        std::fs::write(&source_path, (0..32u8).collect::<Vec<_>>()).unwrap();
+
+        let mut writer = SafetensorsWriter::new();
+        writer
+            .add_tensor(
+                "range",
+                Dtype::Uint8,
+                vec![4],
+                TensorSource::Reader {
+                    reader: Box::new(FileRange::new(&source_path, 28, 4)),
+                    length: 4,
+                },
+            )
+            .unwrap();
+        writer
+            .add_tensor(
+                "reader",
+                Dtype::Uint8,
+                vec![3],
+                TensorSource::Reader {
+                    reader: Box::new(io::Cursor::new(b"xyz".to_vec())),
+                    length: 3,
+                },
+            )
+            .unwrap();
+        // the file of a range is only opened when it is read
+        std::fs::write(&source_path, (100..132u8).collect::<Vec<_>>()).unwrap();
+
+        writer.write(&path).unwrap();
+        let (_, data) = read_file(&path);
+        assert_eq!(data, [128, 129, 130, 131, b'x', b'y', b'z']);
+
+        // a reader that ends early fails the write
+        let mut writer = SafetensorsWriter::new();
+        writer
+            .add_tensor(
+                "short",
+                Dtype::Uint8,
+                vec![8],
+                TensorSource::Reader {
+                    reader: Box::new(FileRange::new(&source_path, 28, 8)),
+                    length: 8,
+                },
+            )
+            .unwrap();
+        assert!(writer.write(dir.path().join("short.safetensors")).is_err());
+        assert!(!dir.path().join("short.safetensors").exists());
+    }
+
+    #[test]
+    fn test_write_drops_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("model.safetensors");
+
+        let mut writer = SafetensorsWriter::new().with_metadata(BTreeMap::from([
+            ("format".to_string(), "pt".to_string()),
+            (MODELSPEC_HASH_KEY.to_string(), "0xabcd".to_string()),
+            ("sshs_model_hash".to_string(), "abcd".to_string()),
+        ]));
+        writer
+            .add_tensor("a", Dtype::Uint8, vec![1], vec![0].into())
+            .unwrap();
+        let report = writer.write(&path).unwrap();
+
+        assert_eq!(
+            report.changes,
+            vec![
+                MetadataChange::Removed {
+                    key: MODELSPEC_HASH_KEY.to_string(),
+                    value: "0xabcd".to_string(),
+                },
+                MetadataChange::Removed {
+                    key: "sshs_model_hash".to_string(),
+                    value: "abcd".to_string(),
+                },
+            ]
+        );
+        let (info, _) = read_file(&path);
+        let metadata = info.header.metadata.as_ref().unwrap().to_map().unwrap();
+        assert_eq!(metadata.keys().collect::<Vec<_>>(), ["format"]);
+    }
+
+    #[test]
+    fn test_headroom() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, headroom| {
+            let mut writer = SafetensorsWriter::new().with_headroom(headroom);
+            writer
+                .add_tensor("a", Dtype::Fp32, vec![1], vec![1; 4].into())
+                .unwrap();
+            let path = dir.path().join(name);
+            let report = writer.write(&path).unwrap();
+            let (info, data) = read_file(&path);
+            assert_eq!(info.header_size, report.new_header_size);
+            assert_eq!(data, [1; 4]);
+            report.new_header_size
+        };
+
+        let plain = write("plain.safetensors", 0);
+        let padded = write("padded.safetensors", 100);
+        assert_eq!(padded % HEADER_ALIGNMENT as u64, 0);
+        assert!(padded >= plain + 100 - HEADER_ALIGNMENT as u64);
+        assert!(padded <= plain + 100 + HEADER_ALIGNMENT as u64);
+    }
+
+    #[test]
+    fn test_empty_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("empty.safetensors");
+
+        let writer = SafetensorsWriter::new();
+        assert!(writer.is_empty());
+        let report = writer.write(&path).unwrap();
+        assert_eq!((report.tensors_written, report.bytes_copied), (0, 0));
+
+        let (info, data) = read_file(&path);
+        assert!(info.header.weights.is_empty());
+        assert!(info.header.metadata.is_none());
+        assert!(data.is_empty());
+    }
+}
+
+/// Model files for the tests of the other modules.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use super::*;
+
+    /// Write a file with one-dimensional tensors of the given dtypes and bytes.
+    pub(crate) fn write_model<N: AsRef<str>, B: AsRef<[u8]>>(
+        path: &Path,
+        tensors: &[(N, Dtype, B)],
+    ) {
+        let mut writer = SafetensorsWriter::new();
+        for (name, dtype, bytes) in tensors {
+            let bytes = bytes.as_ref();
+            let elements = bytes.len() * 8 / dtype.bits();
+            writer
+                .add_tensor(
+                    name.as_ref(),
+                    *dtype,
+                    vec![elements as i64],
+                    bytes.to_vec().into(),
+                )
+                .unwrap();
+        }
+        writer.write(path).unwrap();
+    }
+
+    /// The little-endian bytes of fp32 values.
+    pub(crate) fn f32_bytes(values: &[f32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect()
+    }
+}